@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{bail, Result};
+use parking_lot::{Condvar, Mutex};
+
+/// Pauses the flushes, compactions and value log GCs started by the background threads, and
+/// cancels the running compactions.
+#[derive(Default)]
+pub(crate) struct BackgroundWork {
+    paused: AtomicBool,
+    /// Set while the running compactions are being cancelled.
+    cancelling: AtomicBool,
+    compactions: Mutex<CompactionCounts>,
+    /// Notified when a compaction stops.
+    compaction_stopped: Condvar,
+}
+
+#[derive(Default)]
+struct CompactionCounts {
+    /// The number of compactions running, both in the background and started by the user.
+    running: usize,
+    /// The number of compactions stopped so far.
+    stopped: u64,
+}
+
+impl BackgroundWork {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether the background threads should start new work.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst) || self.cancelling.load(Ordering::SeqCst)
+    }
+
+    /// Register a running compaction until the returned guard is dropped.
+    pub fn start_compaction(&self) -> RunningCompaction<'_> {
+        self.compactions.lock().running += 1;
+        RunningCompaction { work: self }
+    }
+
+    /// The number of compactions stopped so far, to be passed to
+    /// [`BackgroundWork::wait_for_compaction_stopped`].
+    pub fn stopped_compactions(&self) -> u64 {
+        self.compactions.lock().stopped
+    }
+
+    /// Wait until a compaction stops after `stopped_compactions` of them stopped.
+    pub fn wait_for_compaction_stopped(&self, stopped_compactions: u64) {
+        let mut compactions = self.compactions.lock();
+        while compactions.stopped == stopped_compactions {
+            self.compaction_stopped.wait(&mut compactions);
+        }
+    }
+
+    /// Fails if the running compactions are being cancelled.
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.cancelling.load(Ordering::SeqCst) {
+            bail!("compaction cancelled");
+        }
+        Ok(())
+    }
+
+    /// Cancel the running compactions, and wait until all of them stopped.
+    pub fn cancel_running_compactions(&self) {
+        let mut compactions = self.compactions.lock();
+        self.cancelling.store(true, Ordering::SeqCst);
+        while compactions.running > 0 {
+            self.compaction_stopped.wait(&mut compactions);
+        }
+        self.cancelling.store(false, Ordering::SeqCst);
+    }
+}
+
+/// A compaction registered by [`BackgroundWork::start_compaction`].
+pub(crate) struct RunningCompaction<'a> {
+    work: &'a BackgroundWork,
+}
+
+impl Drop for RunningCompaction<'_> {
+    fn drop(&mut self) {
+        let mut compactions = self.work.compactions.lock();
+        compactions.running -= 1;
+        compactions.stopped += 1;
+        self.work.compaction_stopped.notify_all();
+    }
+}
@@ -1 +0,0 @@
-../../../mini-lsm-starter/src/bin/compaction-simulator.rs
\ No newline at end of file
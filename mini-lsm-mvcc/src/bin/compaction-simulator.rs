@@ -0,0 +1,999 @@
+mod wrapper;
+use wrapper::mini_lsm_wrapper;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, BufMut, BytesMut};
+use clap::Parser;
+use mini_lsm_wrapper::compact::{
+    FifoCompactionController, FifoCompactionOptions, LeveledCompactionController,
+    LeveledCompactionOptions, SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+    TieredCompactionController, TieredCompactionLimits, TieredCompactionOptions, TimeSource,
+    TimeWindowCompactionController, TimeWindowCompactionOptions,
+};
+use mini_lsm_wrapper::key::KeyBytes;
+use mini_lsm_wrapper::lsm_storage::LsmStorageState;
+use mini_lsm_wrapper::mem_table::MemTable;
+use mini_lsm_wrapper::table::SsTable;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+enum Args {
+    Simple {
+        #[clap(long)]
+        dump_real_id: bool,
+        #[clap(long, default_value = "2")]
+        level0_file_num_compaction_trigger: usize,
+        #[clap(long, default_value = "3")]
+        max_levels: usize,
+        #[clap(long, default_value = "200")]
+        size_ratio_percent: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+    },
+    Tiered {
+        #[clap(long)]
+        dump_real_id: bool,
+        #[clap(long, default_value = "3")]
+        num_tiers: usize,
+        #[clap(long, default_value = "200")]
+        max_size_amplification_percent: usize,
+        #[clap(long, default_value = "1")]
+        size_ratio: usize,
+        #[clap(long, default_value = "2")]
+        min_merge_width: usize,
+        #[clap(long)]
+        max_merge_width: Option<usize>,
+        #[clap(long)]
+        max_compaction_bytes_mb: Option<u64>,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "16")]
+        sst_size_mb: usize,
+    },
+    Leveled {
+        #[clap(long)]
+        dump_real_id: bool,
+        #[clap(long, default_value = "2")]
+        level0_file_num_compaction_trigger: usize,
+        #[clap(long, default_value = "2")]
+        level_size_multiplier: usize,
+        #[clap(long, default_value = "4")]
+        max_levels: usize,
+        #[clap(long, default_value = "128")]
+        base_level_size_mb: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+    },
+    Fifo {
+        #[clap(long)]
+        dump_real_id: bool,
+        #[clap(long, default_value = "256")]
+        max_table_files_size_mb: usize,
+        /// The TTL in iterations, as each iteration flushes one SST
+        #[clap(long)]
+        ttl: Option<u64>,
+        #[clap(long)]
+        intra_l0_compaction_trigger: Option<usize>,
+        #[clap(long, default_value = "64")]
+        intra_l0_max_file_size_mb: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "16")]
+        sst_size_mb: usize,
+    },
+    TimeWindow {
+        #[clap(long)]
+        dump_real_id: bool,
+        /// The window size in iterations, as each iteration flushes the data of one time unit
+        #[clap(long, default_value = "8")]
+        window_size: u64,
+        #[clap(long, default_value = "1")]
+        size_ratio: usize,
+        #[clap(long, default_value = "4")]
+        min_merge_width: usize,
+        #[clap(long)]
+        max_windows: Option<usize>,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "16")]
+        sst_size_mb: usize,
+    },
+}
+
+pub struct MockStorage {
+    snapshot: LsmStorageState,
+    next_sst_id: usize,
+    /// Maps SST ID to the original flushed SST ID
+    file_list: HashMap<usize, usize>,
+    total_flushes: usize,
+    total_writes: usize,
+}
+
+impl Default for MockStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockStorage {
+    pub fn new() -> Self {
+        let snapshot = LsmStorageState {
+            memtable: Arc::new(MemTable::create(0)),
+            imm_memtables: Vec::new(),
+            l0_sstables: Vec::new(),
+            levels: Vec::new(),
+            sstables: Default::default(),
+        };
+        Self {
+            snapshot,
+            next_sst_id: 1,
+            file_list: Default::default(),
+            total_flushes: 0,
+            total_writes: 0,
+        }
+    }
+
+    fn generate_sst_id(&mut self) -> usize {
+        let id = self.next_sst_id;
+        self.next_sst_id += 1;
+        id
+    }
+
+    pub fn flush_sst_to_l0(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.l0_sstables.push(id);
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    /// Flush an SST to the front of L0, which keeps L0 from the newest to the oldest SST as the
+    /// storage engine does.
+    pub fn flush_sst_to_l0_front(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.l0_sstables.insert(0, id);
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    pub fn flush_sst_to_new_tier(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.levels.insert(0, (id, vec![id]));
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    pub fn remove(&mut self, files_to_remove: &[usize]) {
+        for file_id in files_to_remove {
+            let ret = self.file_list.remove(file_id);
+            assert!(ret.is_some(), "failed to remove file {}", file_id);
+        }
+    }
+
+    fn check_keys(&self) {
+        for (level, files) in &self.snapshot.levels {
+            if files.len() >= 2 {
+                for id in 0..(files.len() - 1) {
+                    let this_file = self.snapshot.sstables[&files[id]].clone();
+                    let next_file = self.snapshot.sstables[&files[id + 1]].clone();
+                    if this_file.last_key() >= next_file.first_key() {
+                        panic!(
+                            "invalid file arrangement in L{}: id={}, range={:x}..={:x}; id={}, range={:x}..={:x}",
+                            level,
+                            this_file.sst_id(),
+                            this_file.first_key().for_testing_key_ref().get_u64(),
+                            this_file.last_key().for_testing_key_ref().get_u64(),
+                            next_file.sst_id(),
+                            next_file.first_key().for_testing_key_ref().get_u64(),
+                            next_file.last_key().for_testing_key_ref().get_u64()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn dump_original_id(&self, always_show_l0: bool, with_key: bool) {
+        if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
+            println!(
+                "L0 ({}): {:?}",
+                self.snapshot.l0_sstables.len(),
+                self.snapshot.l0_sstables,
+            );
+        }
+        for (level, files) in &self.snapshot.levels {
+            println!(
+                "L{level} ({}): {:?}",
+                files.len(),
+                files.iter().map(|x| self.file_list[x]).collect::<Vec<_>>()
+            );
+        }
+        if with_key {
+            self.check_keys();
+        }
+    }
+
+    pub fn dump_real_id(&self, always_show_l0: bool, with_key: bool) {
+        if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
+            println!(
+                "L0 ({}): {:?}",
+                self.snapshot.l0_sstables.len(),
+                self.snapshot.l0_sstables,
+            );
+        }
+        for (level, files) in &self.snapshot.levels {
+            println!("L{level} ({}): {:?}", files.len(), files);
+        }
+        if with_key {
+            self.check_keys();
+        }
+    }
+}
+
+fn generate_random_key_range() -> (KeyBytes, KeyBytes) {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    let begin: usize = rng.gen_range(0..(1 << 31));
+    let end: usize = begin + rng.gen_range((1 << 10)..(1 << 31));
+    let mut begin_bytes = BytesMut::new();
+    let mut end_bytes = BytesMut::new();
+    begin_bytes.put_u64(begin as u64);
+    end_bytes.put_u64(end as u64);
+    (
+        KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+        KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+    )
+}
+
+/// Generate the key range of the data written at `time`, with the time in the upper 32 bits of
+/// the keys.
+fn generate_time_key_range(time: u64) -> (KeyBytes, KeyBytes) {
+    let mut begin_bytes = BytesMut::new();
+    let mut end_bytes = BytesMut::new();
+    begin_bytes.put_u64(time << 32);
+    end_bytes.put_u64((time << 32) | 0xffff_ffff);
+    (
+        KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+        KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+    )
+}
+
+fn generate_random_split(
+    begin_bytes: KeyBytes,
+    end_bytes: KeyBytes,
+    split: usize,
+) -> Vec<(KeyBytes, KeyBytes)> {
+    let begin = begin_bytes.for_testing_key_ref().get_u64();
+    let end = end_bytes.for_testing_key_ref().get_u64();
+    let len = end - begin + 1;
+    let mut result = Vec::new();
+    let split = split as u64;
+    assert!(len >= split, "well, this is unfortunate... run again!");
+    for i in 0..split {
+        let nb = begin + len * i / split;
+        let ne = begin + len * (i + 1) / split - 1;
+        let mut begin_bytes = BytesMut::new();
+        let mut end_bytes = BytesMut::new();
+        begin_bytes.put_u64(nb);
+        end_bytes.put_u64(ne);
+        result.push((
+            KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+            KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+        ));
+    }
+    result
+}
+
+fn main() {
+    let args = Args::parse();
+    match args {
+        Args::Simple {
+            dump_real_id,
+            size_ratio_percent,
+            iterations,
+            level0_file_num_compaction_trigger,
+            max_levels,
+        } => {
+            // TODO(chi): use unified logic for all 3 compactions...
+            let controller =
+                SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+                    size_ratio_percent,
+                    level0_file_num_compaction_trigger,
+                    max_levels,
+                });
+            let mut storage = MockStorage::new();
+            for i in 0..max_levels {
+                storage.snapshot.levels.push((i + 1, Vec::new()));
+            }
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                storage.flush_sst_to_l0();
+                println!("--- After Flush ---");
+                if dump_real_id {
+                    storage.dump_real_id(true, false);
+                } else {
+                    storage.dump_original_id(true, false);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    println!("--- Compaction Task ---");
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let mut sst_ids = Vec::new();
+                    for file in task
+                        .upper_level_sst_ids
+                        .iter()
+                        .chain(task.lower_level_sst_ids.iter())
+                    {
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage.file_list.insert(new_sst_id, *file);
+                        storage.total_writes += 1;
+                    }
+                    print!(
+                        "Upper L{} {:?} ",
+                        task.upper_level.unwrap_or_default(),
+                        task.upper_level_sst_ids
+                    );
+                    print!(
+                        "Lower L{} {:?} ",
+                        task.lower_level, task.lower_level_sst_ids
+                    );
+                    println!("-> {:?}", sst_ids);
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if dump_real_id {
+                        storage.dump_real_id(true, false);
+                    } else {
+                        storage.dump_original_id(true, false);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= max_levels * 2 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                        + storage
+                            .snapshot
+                            .levels
+                            .iter()
+                            .filter(|(_, f)| !f.is_empty())
+                            .count()
+                );
+                println!();
+            }
+        }
+        Args::Tiered {
+            dump_real_id,
+            num_tiers: level0_file_num_compaction_trigger,
+            max_size_amplification_percent,
+            size_ratio,
+            min_merge_width,
+            max_merge_width,
+            max_compaction_bytes_mb,
+            iterations,
+            sst_size_mb,
+        } => {
+            let controller = TieredCompactionController::new(TieredCompactionOptions {
+                num_tiers: level0_file_num_compaction_trigger,
+                max_size_amplification_percent,
+                size_ratio,
+                min_merge_width,
+            })
+            .with_limits(TieredCompactionLimits {
+                max_merge_width,
+                max_compaction_bytes: max_compaction_bytes_mb.map(|x| x * 1024 * 1024),
+            });
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_new_tier();
+                let (first_key, last_key) = generate_random_key_range();
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(
+                        id,
+                        sst_size_mb as u64 * 1024 * 1024,
+                        first_key,
+                        last_key,
+                    )),
+                );
+                println!("--- After Flush ---");
+                if dump_real_id {
+                    storage.dump_real_id(false, true);
+                } else {
+                    storage.dump_original_id(false, true);
+                }
+                println!("--- Compaction Task ---");
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    println!("--- Compaction Task ---");
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let mut sst_ids = Vec::new();
+                    let files = task
+                        .tiers
+                        .iter()
+                        .flat_map(|(_, files)| files.iter().copied())
+                        .collect::<Vec<_>>();
+                    let begin = files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].first_key())
+                        .min()
+                        .unwrap()
+                        .clone();
+                    let end = files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].last_key())
+                        .max()
+                        .unwrap()
+                        .clone();
+                    // each output SST takes the size of an input SST
+                    let splits = generate_random_split(begin, end, files.len());
+                    for (file, (first_key, last_key)) in files.iter().zip(splits) {
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage.file_list.insert(new_sst_id, *file);
+                        storage.total_writes += 1;
+                        let size = storage.snapshot.sstables[file].table_size();
+                        storage.snapshot.sstables.insert(
+                            new_sst_id,
+                            Arc::new(SsTable::create_meta_only(
+                                new_sst_id, size, first_key, last_key,
+                            )),
+                        );
+                    }
+                    for (tier_id, files) in &task.tiers {
+                        print!("L{} {:?} ", tier_id, files);
+                    }
+                    println!("-> {:?}", sst_ids);
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    for file in &del {
+                        storage.snapshot.sstables.remove(file);
+                    }
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if dump_real_id {
+                        storage.dump_real_id(false, true);
+                    } else {
+                        storage.dump_original_id(false, true);
+                    }
+                    num_compactions += 1;
+                    // incremental compactions take a few key ranges at a time
+                    let max_compactions = match max_compaction_bytes_mb {
+                        Some(_) => level0_file_num_compaction_trigger * iterations,
+                        None => level0_file_num_compaction_trigger * 3,
+                    };
+                    if num_compactions >= max_compactions {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                        + storage
+                            .snapshot
+                            .levels
+                            .iter()
+                            .filter(|(_, f)| !f.is_empty())
+                            .count()
+                );
+                println!();
+            }
+        }
+        Args::Leveled {
+            dump_real_id,
+            level0_file_num_compaction_trigger,
+            level_size_multiplier,
+            max_levels,
+            base_level_size_mb,
+            iterations,
+            sst_size_mb,
+        } => {
+            let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger,
+                level_size_multiplier,
+                max_levels,
+                base_level_size_mb,
+            });
+
+            let mut storage = MockStorage::new();
+            for i in 0..max_levels {
+                storage.snapshot.levels.push((i + 1, Vec::new()));
+            }
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_l0();
+                let (first_key, last_key) = generate_random_key_range();
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(
+                        id,
+                        sst_size_mb as u64 * 1024 * 1024,
+                        first_key,
+                        last_key,
+                    )),
+                );
+                println!("--- After Flush ---");
+                if dump_real_id {
+                    storage.dump_real_id(false, true);
+                } else {
+                    storage.dump_original_id(false, true);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    println!("--- Compaction Task ---");
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let mut sst_ids = Vec::new();
+                    if task.is_trivial_move {
+                        // the SSTs are moved without being rewritten
+                        sst_ids = task.upper_level_sst_ids.clone();
+                    } else {
+                        let split_num =
+                            task.upper_level_sst_ids.len() + task.lower_level_sst_ids.len();
+                        let mut first_keys = Vec::new();
+                        let mut last_keys = Vec::new();
+                        for file in task
+                            .upper_level_sst_ids
+                            .iter()
+                            .chain(task.lower_level_sst_ids.iter())
+                        {
+                            first_keys.push(storage.snapshot.sstables[file].first_key().clone());
+                            last_keys.push(storage.snapshot.sstables[file].last_key().clone());
+                        }
+                        let begin = first_keys.into_iter().min().unwrap();
+                        let end = last_keys.into_iter().max().unwrap();
+                        let splits = generate_random_split(begin, end, split_num);
+                        for (id, file) in task
+                            .upper_level_sst_ids
+                            .iter()
+                            .chain(task.lower_level_sst_ids.iter())
+                            .enumerate()
+                        {
+                            let new_sst_id = storage.generate_sst_id();
+                            sst_ids.push(new_sst_id);
+                            storage.file_list.insert(new_sst_id, *file);
+                            storage.total_writes += 1;
+                            storage.snapshot.sstables.insert(
+                                new_sst_id,
+                                Arc::new(SsTable::create_meta_only(
+                                    new_sst_id,
+                                    sst_size_mb as u64 * 1024 * 1024,
+                                    splits[id].0.clone(),
+                                    splits[id].1.clone(),
+                                )),
+                            );
+                        }
+                    }
+                    print!(
+                        "Upper L{} [{}] ",
+                        task.upper_level.unwrap_or_default(),
+                        task.upper_level_sst_ids
+                            .iter()
+                            .map(|id| format!(
+                                "{}.sst {:x}..={:x}",
+                                id,
+                                storage.snapshot.sstables[id]
+                                    .first_key()
+                                    .for_testing_key_ref()
+                                    .get_u64(),
+                                storage.snapshot.sstables[id]
+                                    .last_key()
+                                    .for_testing_key_ref()
+                                    .get_u64()
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    print!(
+                        "Lower L{} [{}] ",
+                        task.lower_level,
+                        task.lower_level_sst_ids
+                            .iter()
+                            .map(|id| format!(
+                                "{}.sst {:x}..={:x}",
+                                id,
+                                storage.snapshot.sstables[id]
+                                    .first_key()
+                                    .for_testing_key_ref()
+                                    .get_u64(),
+                                storage.snapshot.sstables[id]
+                                    .last_key()
+                                    .for_testing_key_ref()
+                                    .get_u64()
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    println!(
+                        "-> [{}]",
+                        sst_ids
+                            .iter()
+                            .map(|id| format!(
+                                "{}.sst {:x}..={:x}",
+                                id,
+                                storage.snapshot.sstables[id]
+                                    .first_key()
+                                    .for_testing_key_ref()
+                                    .get_u64(),
+                                storage.snapshot.sstables[id]
+                                    .last_key()
+                                    .for_testing_key_ref()
+                                    .get_u64()
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if dump_real_id {
+                        storage.dump_real_id(true, true);
+                    } else {
+                        storage.dump_original_id(true, true);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= level0_file_num_compaction_trigger * max_levels * 2 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                        + storage
+                            .snapshot
+                            .levels
+                            .iter()
+                            .filter(|(_, f)| !f.is_empty())
+                            .count()
+                );
+                println!();
+            }
+        }
+        Args::Fifo {
+            dump_real_id,
+            max_table_files_size_mb,
+            ttl,
+            intra_l0_compaction_trigger,
+            intra_l0_max_file_size_mb,
+            iterations,
+            sst_size_mb,
+        } => {
+            let controller = FifoCompactionController::new(FifoCompactionOptions {
+                max_table_files_size: max_table_files_size_mb as u64 * 1024 * 1024,
+                ttl: ttl.map(Duration::from_secs),
+                intra_l0_compaction_trigger,
+                intra_l0_max_file_size: intra_l0_max_file_size_mb as u64 * 1024 * 1024,
+            });
+            // each iteration takes one second of simulated time
+            let clock = |i: usize| SystemTime::UNIX_EPOCH + Duration::from_secs(i as u64);
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_l0_front();
+                let (first_key, last_key) = generate_random_key_range();
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(
+                        SsTable::create_meta_only(
+                            id,
+                            sst_size_mb as u64 * 1024 * 1024,
+                            first_key,
+                            last_key,
+                        )
+                        .with_created_at(clock(i)),
+                    ),
+                );
+                println!("--- After Flush ---");
+                if dump_real_id {
+                    storage.dump_real_id(true, false);
+                } else {
+                    storage.dump_original_id(true, false);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    println!("--- Compaction Task ---");
+                    controller.generate_compaction_task_at(
+                        &storage.snapshot,
+                        &HashSet::new(),
+                        clock(i),
+                    )
+                } {
+                    let mut sst_ids = Vec::new();
+                    if !task.merged_sst_ids.is_empty() {
+                        // the merged SSTs are written into one SST with the newest creation time
+                        let tables = task
+                            .merged_sst_ids
+                            .iter()
+                            .map(|id| storage.snapshot.sstables[id].clone())
+                            .collect::<Vec<_>>();
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage
+                            .file_list
+                            .insert(new_sst_id, storage.file_list[&task.merged_sst_ids[0]]);
+                        storage.total_writes += task.merged_sst_ids.len();
+                        storage.snapshot.sstables.insert(
+                            new_sst_id,
+                            Arc::new(
+                                SsTable::create_meta_only(
+                                    new_sst_id,
+                                    tables.iter().map(|table| table.table_size()).sum(),
+                                    tables.iter().map(|t| t.first_key()).min().unwrap().clone(),
+                                    tables.iter().map(|t| t.last_key()).max().unwrap().clone(),
+                                )
+                                .with_created_at(
+                                    tables.iter().map(|t| t.created_at()).max().unwrap(),
+                                ),
+                            ),
+                        );
+                    }
+                    print!("Dropped L0 {:?} ", task.dropped_sst_ids);
+                    print!("Merged L0 {:?} ", task.merged_sst_ids);
+                    println!("-> {:?}", sst_ids);
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    storage.remove(&del);
+                    for id in &del {
+                        storage.snapshot.sstables.remove(id);
+                    }
+                    println!("--- After Compaction ---");
+                    if dump_real_id {
+                        storage.dump_real_id(true, false);
+                    } else {
+                        storage.dump_original_id(true, false);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= 4 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Total Size: {} MB",
+                    storage
+                        .snapshot
+                        .l0_sstables
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].table_size())
+                        .sum::<u64>()
+                        / 1024
+                        / 1024
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                );
+                println!();
+            }
+        }
+        Args::TimeWindow {
+            dump_real_id,
+            window_size,
+            size_ratio,
+            min_merge_width,
+            max_windows,
+            iterations,
+            sst_size_mb,
+        } => {
+            let controller = TimeWindowCompactionController::new(TimeWindowCompactionOptions {
+                window_size,
+                time_source: TimeSource::Key(Arc::new(|mut key: &[u8]| key.get_u64() >> 32)),
+                size_ratio,
+                min_merge_width,
+                max_windows,
+            });
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_new_tier();
+                let (first_key, last_key) = generate_time_key_range(i as u64);
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(
+                        id,
+                        sst_size_mb as u64 * 1024 * 1024,
+                        first_key,
+                        last_key,
+                    )),
+                );
+                println!("--- After Flush ---");
+                if dump_real_id {
+                    storage.dump_real_id(false, false);
+                } else {
+                    storage.dump_original_id(false, false);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    println!("--- Compaction Task ---");
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let mut sst_ids = Vec::new();
+                    let files = task
+                        .tiers
+                        .iter()
+                        .flat_map(|(_, files)| files.iter().copied())
+                        .collect::<Vec<_>>();
+                    if !files.is_empty() {
+                        let begin = files
+                            .iter()
+                            .map(|id| storage.snapshot.sstables[id].first_key())
+                            .min()
+                            .unwrap()
+                            .clone();
+                        let end = files
+                            .iter()
+                            .map(|id| storage.snapshot.sstables[id].last_key())
+                            .max()
+                            .unwrap()
+                            .clone();
+                        let splits = generate_random_split(begin, end, files.len());
+                        for (file, (first_key, last_key)) in files.iter().zip(splits) {
+                            let new_sst_id = storage.generate_sst_id();
+                            sst_ids.push(new_sst_id);
+                            storage.file_list.insert(new_sst_id, *file);
+                            storage.total_writes += 1;
+                            storage.snapshot.sstables.insert(
+                                new_sst_id,
+                                Arc::new(SsTable::create_meta_only(
+                                    new_sst_id,
+                                    sst_size_mb as u64 * 1024 * 1024,
+                                    first_key,
+                                    last_key,
+                                )),
+                            );
+                        }
+                    }
+                    for (tier_id, files) in &task.dropped_tiers {
+                        print!("Dropped L{} {:?} ", tier_id, files);
+                    }
+                    for (tier_id, files) in &task.tiers {
+                        print!("L{} {:?} ", tier_id, files);
+                    }
+                    println!("-> {:?}", sst_ids);
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if dump_real_id {
+                        storage.dump_real_id(false, true);
+                    } else {
+                        storage.dump_original_id(false, true);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= window_size as usize * 3 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!("Read Amplification: {}x", storage.snapshot.levels.len());
+                println!();
+            }
+        }
+    }
+}
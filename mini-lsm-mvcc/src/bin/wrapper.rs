@@ -1,5 +1,36 @@
 pub mod mini_lsm_wrapper {
     pub use mini_lsm_mvcc::*;
+
+    pub mod lsm_storage {
+        pub use mini_lsm_mvcc::lsm_storage::*;
+
+        use mini_lsm_mvcc::compact::CompactionOptions;
+
+        /// The storage options the shared binaries set. The other options of this crate keep
+        /// their defaults.
+        pub struct LsmStorageOptions {
+            pub block_size: usize,
+            pub target_sst_size: usize,
+            pub num_memtable_limit: usize,
+            pub compaction_options: CompactionOptions,
+            pub enable_wal: bool,
+            pub serializable: bool,
+        }
+
+        impl From<LsmStorageOptions> for mini_lsm_mvcc::lsm_storage::LsmStorageOptions {
+            fn from(options: LsmStorageOptions) -> Self {
+                Self {
+                    block_size: options.block_size,
+                    target_sst_size: options.target_sst_size,
+                    num_memtable_limit: options.num_memtable_limit,
+                    compaction_options: options.compaction_options,
+                    enable_wal: options.enable_wal,
+                    serializable: options.serializable,
+                    ..Self::default_for_week1_test()
+                }
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
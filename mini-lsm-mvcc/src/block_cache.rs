@@ -0,0 +1,299 @@
+mod secondary;
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use moka::notification::RemovalCause;
+use moka::sync::ConcurrentCacheExt;
+use parking_lot::Mutex;
+
+pub use secondary::{CacheTierStats, SecondaryCacheOptions, SecondaryCacheStats};
+
+use self::secondary::SecondaryCache;
+use crate::block::Block;
+use crate::table::SsTableIndex;
+
+/// The cache key is (namespace, SST id, block index). Every storage instance sharing a cache gets
+/// its own namespace, as SST ids are only unique within an instance.
+type BlockCacheKey = (usize, usize, usize);
+
+/// The block index of the index entry of an SST in the cache key.
+const INDEX_BLOCK_IDX: usize = usize::MAX;
+
+/// The SST id of the dummy entries reserving cache capacity in the cache key.
+const RESERVATION_SST_ID: usize = usize::MAX;
+
+/// The size of each dummy entry reserving cache capacity.
+pub(crate) const RESERVATION_CHUNK_SIZE: u64 = 256 << 10;
+
+#[derive(Clone)]
+enum CacheEntry {
+    Block(Arc<Block>),
+    Index(Arc<SsTableIndex>),
+    /// Takes `RESERVATION_CHUNK_SIZE` of the capacity for memory used outside of the cache.
+    Reservation,
+}
+
+#[derive(Default)]
+struct BlockCacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A snapshot of the block cache statistics. The counters are shared by all storage instances
+/// using the same cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+    /// The number of cached blocks and SST indexes.
+    pub entries: u64,
+    /// The total size of cached blocks and SST indexes in bytes.
+    pub size: u64,
+    /// The capacity of the cache in bytes.
+    pub capacity: u64,
+    /// Statistics of the secondary cache, `None` if there is no secondary cache.
+    pub secondary: Option<SecondaryCacheStats>,
+}
+
+struct BlockCacheInner {
+    cache: moka::sync::Cache<BlockCacheKey, CacheEntry>,
+    counters: Arc<BlockCacheCounters>,
+    capacity: u64,
+    next_namespace: AtomicUsize,
+    secondary: Option<Arc<SecondaryCache>>,
+}
+
+/// A block cache bounded by the total size of the blocks in bytes.
+///
+/// Use [`BlockCache::share`] to get a handle of the same cache for another storage instance, the
+/// storage engine does that itself for a cache passed in `LsmStorageOptions::block_cache`.
+pub struct BlockCache {
+    inner: Arc<BlockCacheInner>,
+    namespace: usize,
+    /// The number of dummy entries reserving capacity in this namespace.
+    reserved_chunks: Mutex<usize>,
+}
+
+impl BlockCache {
+    /// Create a block cache holding up to `capacity` bytes of blocks.
+    pub fn new(capacity: u64) -> Self {
+        Self::new_inner(capacity, None)
+    }
+
+    /// Create a block cache holding up to `capacity` bytes of blocks, backed by a secondary cache
+    /// that keeps the evicted blocks in compressed form.
+    pub fn new_with_secondary_cache(
+        capacity: u64,
+        secondary_options: &SecondaryCacheOptions,
+    ) -> Result<Self> {
+        let secondary = Arc::new(SecondaryCache::new(secondary_options)?);
+        Ok(Self::new_inner(capacity, Some(secondary)))
+    }
+
+    fn new_inner(capacity: u64, secondary: Option<Arc<SecondaryCache>>) -> Self {
+        let counters = Arc::new(BlockCacheCounters::default());
+        let cache = moka::sync::Cache::builder()
+            .max_capacity(capacity)
+            .weigher(|_, entry: &CacheEntry| Self::weigh(entry))
+            .eviction_listener({
+                let counters = counters.clone();
+                let secondary = secondary.clone();
+                move |key: Arc<BlockCacheKey>, entry, cause| {
+                    if cause != RemovalCause::Size {
+                        return;
+                    }
+                    counters.evictions.fetch_add(1, Ordering::Relaxed);
+                    if let (Some(secondary), CacheEntry::Block(block)) = (&secondary, entry) {
+                        if let Err(e) = secondary.insert(*key, &block) {
+                            eprintln!("failed to keep the evicted block: {}", e);
+                        }
+                    }
+                }
+            })
+            .build();
+        Self {
+            inner: Arc::new(BlockCacheInner {
+                cache,
+                counters,
+                capacity,
+                next_namespace: AtomicUsize::new(1),
+                secondary,
+            }),
+            namespace: 0,
+            reserved_chunks: Mutex::new(0),
+        }
+    }
+
+    /// Get a handle of the same cache with a separate key space.
+    pub fn share(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            namespace: self.inner.next_namespace.fetch_add(1, Ordering::Relaxed),
+            reserved_chunks: Mutex::new(0),
+        }
+    }
+
+    fn weigh(entry: &CacheEntry) -> u32 {
+        let size = match entry {
+            CacheEntry::Block(block) => {
+                block.data.len() + block.offsets.len() * std::mem::size_of::<u16>()
+            }
+            CacheEntry::Index(index) => index.size(),
+            CacheEntry::Reservation => RESERVATION_CHUNK_SIZE as usize,
+        };
+        size.try_into().unwrap_or(u32::MAX)
+    }
+
+    fn try_get_entry_with(
+        &self,
+        sst_id: usize,
+        block_idx: usize,
+        init: impl FnOnce() -> Result<CacheEntry>,
+    ) -> Result<CacheEntry> {
+        let counters = &self.inner.counters;
+        let mut missed = false;
+        let entry = self
+            .inner
+            .cache
+            .try_get_with((self.namespace, sst_id, block_idx), || {
+                missed = true;
+                counters.misses.fetch_add(1, Ordering::Relaxed);
+                let entry = init()?;
+                counters.inserts.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, anyhow::Error>(entry)
+            })
+            .map_err(|e| anyhow!("{}", e))?;
+        if !missed {
+            counters.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(entry)
+    }
+
+    /// Get a block from the cache, or from the secondary cache on a miss. If both miss, the block
+    /// is read with `init`.
+    pub fn try_get_with(
+        &self,
+        sst_id: usize,
+        block_idx: usize,
+        init: impl FnOnce() -> Result<Arc<Block>>,
+    ) -> Result<Arc<Block>> {
+        let init = || {
+            if let Some(secondary) = &self.inner.secondary {
+                if let Some(block) = secondary.get(&(self.namespace, sst_id, block_idx))? {
+                    return Ok(CacheEntry::Block(Arc::new(block)));
+                }
+            }
+            Ok(CacheEntry::Block(init()?))
+        };
+        match self.try_get_entry_with(sst_id, block_idx, init)? {
+            CacheEntry::Block(block) => Ok(block),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get a block from the cache without reading it on a miss.
+    pub fn get(&self, sst_id: usize, block_idx: usize) -> Option<Arc<Block>> {
+        let counters = &self.inner.counters;
+        match self.inner.cache.get(&(self.namespace, sst_id, block_idx)) {
+            Some(CacheEntry::Block(block)) => {
+                counters.hits.fetch_add(1, Ordering::Relaxed);
+                Some(block)
+            }
+            Some(_) => unreachable!(),
+            None => {
+                counters.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Get a block from the secondary cache without putting it into the cache. Returns `None` if
+    /// there is no secondary cache.
+    pub fn get_secondary(&self, sst_id: usize, block_idx: usize) -> Result<Option<Arc<Block>>> {
+        match &self.inner.secondary {
+            Some(secondary) => Ok(secondary
+                .get(&(self.namespace, sst_id, block_idx))?
+                .map(Arc::new)),
+            None => Ok(None),
+        }
+    }
+
+    /// Put a block read from the disk into the cache.
+    pub fn insert(&self, sst_id: usize, block_idx: usize, block: Arc<Block>) {
+        self.inner.counters.inserts.fetch_add(1, Ordering::Relaxed);
+        self.inner.cache.insert(
+            (self.namespace, sst_id, block_idx),
+            CacheEntry::Block(block),
+        );
+    }
+
+    /// Get the index of an SST from the cache, or read it with `init` and insert it on a miss.
+    pub fn try_get_index_with(
+        &self,
+        sst_id: usize,
+        init: impl FnOnce() -> Result<Arc<SsTableIndex>>,
+    ) -> Result<Arc<SsTableIndex>> {
+        match self.try_get_entry_with(sst_id, INDEX_BLOCK_IDX, || Ok(CacheEntry::Index(init()?)))? {
+            CacheEntry::Index(index) => Ok(index),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Reserve `size` bytes of the capacity for memory used outside of the cache, which replaces
+    /// the previous reservation of this handle. The capacity is taken by dummy entries of 256KB, so
+    /// the reservation is rounded up. The dummy entries can be evicted like blocks under pressure,
+    /// and the missing ones are inserted again on the next call.
+    pub fn reserve(&self, size: u64) {
+        let chunks = size.div_ceil(RESERVATION_CHUNK_SIZE) as usize;
+        let mut reserved_chunks = self.reserved_chunks.lock();
+        for idx in chunks..*reserved_chunks {
+            self.inner
+                .cache
+                .invalidate(&(self.namespace, RESERVATION_SST_ID, idx));
+        }
+        for idx in 0..chunks {
+            let key = (self.namespace, RESERVATION_SST_ID, idx);
+            if !self.inner.cache.contains_key(&key) {
+                self.inner.cache.insert(key, CacheEntry::Reservation);
+            }
+        }
+        *reserved_chunks = chunks;
+    }
+
+    /// The bytes reserved by [`BlockCache::reserve`] on this handle.
+    pub fn reserved_size(&self) -> u64 {
+        *self.reserved_chunks.lock() as u64 * RESERVATION_CHUNK_SIZE
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        // apply the pending inserts and evictions so that the sizes are up to date
+        self.inner.cache.sync();
+        let counters = &self.inner.counters;
+        BlockCacheStats {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            inserts: counters.inserts.load(Ordering::Relaxed),
+            evictions: counters.evictions.load(Ordering::Relaxed),
+            entries: self.inner.cache.entry_count(),
+            size: self.inner.cache.weighted_size(),
+            capacity: self.inner.capacity,
+            secondary: self.inner.secondary.as_ref().map(|x| x.stats()),
+        }
+    }
+}
+
+impl Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("namespace", &self.namespace)
+            .field("capacity", &self.inner.capacity)
+            .finish()
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::BlockCacheKey;
+use crate::block::Block;
+
+#[derive(Debug, Clone)]
+pub struct SecondaryCacheOptions {
+    /// Capacity of the in-memory arena holding compressed blocks, in bytes.
+    pub arena_capacity: u64,
+    /// The local file holding compressed blocks evicted from the arena, disabled if `None`. The
+    /// file is truncated when the cache is created.
+    pub cache_file: Option<PathBuf>,
+    /// Capacity of the cache file in bytes.
+    pub cache_file_capacity: u64,
+}
+
+/// Statistics of one tier of the secondary cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheTierStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+    /// The number of cached blocks.
+    pub entries: u64,
+    /// The total size of the compressed blocks in bytes.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecondaryCacheStats {
+    pub arena: CacheTierStats,
+    /// Statistics of the cache file, `None` if there is no cache file.
+    pub file: Option<CacheTierStats>,
+}
+
+/// The space a tier writes its entries to.
+trait TierStorage {
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>>;
+}
+
+impl TierStorage for Vec<u8> {
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let (begin, end) = (offset as usize, offset as usize + data.len());
+        if self.len() < end {
+            self.resize(end, 0);
+        }
+        self[begin..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        Ok(self[offset as usize..offset as usize + len].to_vec())
+    }
+}
+
+impl TierStorage for File {
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+        FileExt::write_all_at(self, data, offset)?;
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        use std::os::unix::fs::FileExt;
+        let mut data = vec![0; len];
+        FileExt::read_exact_at(self, &mut data, offset)?;
+        Ok(data)
+    }
+}
+
+struct TierEntry {
+    key: BlockCacheKey,
+    /// The logical offset, which grows monotonically and wraps around the capacity.
+    offset: u64,
+    len: u32,
+    checksum: u32,
+}
+
+/// A cache tier storing entries in a ring buffer. When the buffer is full, the oldest entries are
+/// overwritten first.
+struct RingTier<S: TierStorage> {
+    storage: S,
+    capacity: u64,
+    head: u64,
+    entries: VecDeque<TierEntry>,
+    index: HashMap<BlockCacheKey, (u64, u32, u32)>,
+    stats: CacheTierStats,
+}
+
+impl<S: TierStorage> RingTier<S> {
+    fn new(storage: S, capacity: u64) -> Self {
+        Self {
+            storage,
+            capacity,
+            head: 0,
+            entries: VecDeque::new(),
+            index: HashMap::new(),
+            stats: CacheTierStats::default(),
+        }
+    }
+
+    fn contains(&self, key: &BlockCacheKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    fn get(&mut self, key: &BlockCacheKey) -> Result<Option<Vec<u8>>> {
+        let Some(&(offset, len, checksum)) = self.index.get(key) else {
+            self.stats.misses += 1;
+            return Ok(None);
+        };
+        let data = self.storage.read_at(offset % self.capacity, len as usize)?;
+        if crc32fast::hash(&data) != checksum {
+            bail!("secondary cache checksum mismatched");
+        }
+        self.stats.hits += 1;
+        Ok(Some(data))
+    }
+
+    /// Insert an entry, and returns the entries overwritten by it.
+    fn insert(&mut self, key: BlockCacheKey, data: &[u8]) -> Result<Vec<(BlockCacheKey, Vec<u8>)>> {
+        let len = data.len() as u64;
+        if len > self.capacity || self.contains(&key) {
+            return Ok(Vec::new());
+        }
+        // an entry never wraps around the end of the buffer
+        let mut offset = self.head;
+        if offset % self.capacity + len > self.capacity {
+            offset = (offset / self.capacity + 1) * self.capacity;
+        }
+        let end = offset + len;
+        let mut evicted = Vec::new();
+        while let Some(entry) = self.entries.front() {
+            if entry.offset + self.capacity >= end {
+                break;
+            }
+            let entry = self.entries.pop_front().unwrap();
+            self.index.remove(&entry.key);
+            self.stats.evictions += 1;
+            self.stats.size -= entry.len as u64;
+            let data = self
+                .storage
+                .read_at(entry.offset % self.capacity, entry.len as usize)?;
+            if crc32fast::hash(&data) == entry.checksum {
+                evicted.push((entry.key, data));
+            }
+        }
+        self.storage.write_at(offset % self.capacity, data)?;
+        let checksum = crc32fast::hash(data);
+        self.entries.push_back(TierEntry {
+            key,
+            offset,
+            len: len as u32,
+            checksum,
+        });
+        self.index.insert(key, (offset, len as u32, checksum));
+        self.head = end;
+        self.stats.inserts += 1;
+        self.stats.size += len;
+        Ok(evicted)
+    }
+
+    fn stats(&self) -> CacheTierStats {
+        CacheTierStats {
+            entries: self.index.len() as u64,
+            ..self.stats
+        }
+    }
+}
+
+/// A second block cache tier behind the in-memory block cache. Blocks evicted from the block cache
+/// are compressed into an in-memory arena, and the blocks evicted from the arena are moved to the
+/// cache file if there is one.
+pub(crate) struct SecondaryCache {
+    arena: Mutex<RingTier<Vec<u8>>>,
+    file: Option<Mutex<RingTier<File>>>,
+}
+
+impl SecondaryCache {
+    pub(crate) fn new(options: &SecondaryCacheOptions) -> Result<Self> {
+        let file = match &options.cache_file {
+            Some(path) => {
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)?;
+                Some(Mutex::new(RingTier::new(file, options.cache_file_capacity)))
+            }
+            None => None,
+        };
+        Ok(Self {
+            arena: Mutex::new(RingTier::new(Vec::new(), options.arena_capacity)),
+            file,
+        })
+    }
+
+    /// Keep a block evicted from the block cache.
+    pub(crate) fn insert(&self, key: BlockCacheKey, block: &Block) -> Result<()> {
+        if self
+            .file
+            .as_ref()
+            .is_some_and(|file| file.lock().contains(&key))
+        {
+            return Ok(());
+        }
+        let compressed = snap::raw::Encoder::new().compress_vec(&block.encode())?;
+        let evicted = self.arena.lock().insert(key, &compressed)?;
+        if let Some(file) = &self.file {
+            let mut file = file.lock();
+            for (key, data) in evicted {
+                file.insert(key, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get a block from the arena, or from the cache file.
+    pub(crate) fn get(&self, key: &BlockCacheKey) -> Result<Option<Block>> {
+        let mut compressed = self.arena.lock().get(key)?;
+        if compressed.is_none() {
+            if let Some(file) = &self.file {
+                compressed = file.lock().get(key)?;
+            }
+        }
+        let Some(compressed) = compressed else {
+            return Ok(None);
+        };
+        let data = snap::raw::Decoder::new().decompress_vec(&compressed)?;
+        Ok(Some(Block::decode_bytes(Bytes::from(data))))
+    }
+
+    pub(crate) fn stats(&self) -> SecondaryCacheStats {
+        SecondaryCacheStats {
+            arena: self.arena.lock().stats(),
+            file: self.file.as_ref().map(|file| file.lock().stats()),
+        }
+    }
+}
@@ -1,27 +1,56 @@
+mod fifo;
+mod filter;
 mod leveled;
+mod partitioner;
+mod range;
 mod simple_leveled;
 mod tiered;
+mod time_window;
+mod trigger;
 
 use std::collections::HashSet;
+use std::fs::File;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+pub use fifo::{FifoCompactionController, FifoCompactionOptions, FifoCompactionTask};
+pub use filter::{
+    CompactionFilter, CompactionFilterContext, CompactionFilterDecision, CompactionFilterFactory,
+    CompactionKeyFilter,
+};
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+use parking_lot::Mutex;
+pub use partitioner::{FixedPrefixSstPartitioner, SstPartitioner};
+pub use range::RangeCompactionTask;
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
-pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
+pub use tiered::{
+    TieredCompactionController, TieredCompactionLimits, TieredCompactionOptions,
+    TieredCompactionTask,
+};
+pub use time_window::{
+    KeyTimeExtractor, TimeSource, TimeWindowCompactionController, TimeWindowCompactionOptions,
+    TimeWindowCompactionTask,
+};
+pub use trigger::CompactionTriggerOptions;
 
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{CurrentOptions, LsmStorageInner, LsmStorageOptions, LsmStorageState};
 use crate::manifest::ManifestRecord;
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::rate_limiter::IoPriority;
+use crate::table::{SsTable, SsTableIterator};
+use crate::value_log::ValuePointer;
+use crate::write_stall::WriteStallCause;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum CompactionTask {
@@ -32,6 +61,9 @@ pub enum CompactionTask {
         l0_sstables: Vec<usize>,
         l1_sstables: Vec<usize>,
     },
+    Range(RangeCompactionTask),
+    Fifo(FifoCompactionTask),
+    TimeWindow(TimeWindowCompactionTask),
 }
 
 impl CompactionTask {
@@ -41,33 +73,240 @@ impl CompactionTask {
             CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
             CompactionTask::Simple(task) => task.is_lower_level_bottom_level,
             CompactionTask::Tiered(task) => task.bottom_tier_included,
+            CompactionTask::Range(task) => task.is_target_level_bottom_level,
+            // older SSTs may hold the keys deleted in the merged SSTs
+            CompactionTask::Fifo(_) => false,
+            CompactionTask::TimeWindow(task) => task.bottom_tier_included,
+        }
+    }
+
+    /// The level receiving the output, or 0 for the strategies without levels.
+    fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Range(task) => task.target_level.unwrap_or_default(),
+            CompactionTask::Tiered(_) | CompactionTask::Fifo(_) | CompactionTask::TimeWindow(_) => {
+                0
+            }
         }
     }
+
+    /// Whether the task moves the upper level SSTs to the lower level without rewriting them.
+    fn is_trivial_move(&self) -> bool {
+        match self {
+            CompactionTask::Leveled(task) => task.is_trivial_move,
+            CompactionTask::Simple(task) => task.is_trivial_move,
+            _ => false,
+        }
+    }
+
+    /// Get the ids of all SSTs no other task may compact while the task runs.
+    fn compacting_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => upper_level_sst_ids
+                .iter()
+                .chain(lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect(),
+            CompactionTask::Range(task) => task.compacting_sst_ids(),
+            CompactionTask::Fifo(task) => task
+                .dropped_sst_ids
+                .iter()
+                .chain(&task.merged_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::TimeWindow(task) => task
+                .tiers
+                .iter()
+                .chain(&task.dropped_tiers)
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect(),
+        }
+    }
+}
+
+/// Whether the upper level SSTs can be moved to the lower level without being rewritten, which is
+/// when their key ranges overlap neither with each other nor with the lower level SSTs. SSTs
+/// without metadata, as in the compaction simulator, are never moved.
+pub(crate) fn can_move_trivially(
+    snapshot: &LsmStorageState,
+    upper_level_sst_ids: &[usize],
+    lower_level_sst_ids: &[usize],
+) -> bool {
+    let Some(mut ranges) = upper_level_sst_ids
+        .iter()
+        .chain(lower_level_sst_ids)
+        .map(|id| {
+            let table = snapshot.sstables.get(id)?;
+            Some((table.first_key().key_ref(), table.last_key().key_ref()))
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    ranges.sort();
+    ranges.windows(2).all(|pair| pair[0].1 < pair[1].0)
+}
+
+/// The SSTs written by a compaction so far, which are removed if the compaction does not finish.
+struct CompactionOutput<'a> {
+    storage: &'a LsmStorageInner,
+    ssts: Vec<Arc<SsTable>>,
+}
+
+impl Drop for CompactionOutput<'_> {
+    fn drop(&mut self) {
+        self.storage.remove_compaction_output(&self.ssts);
+    }
+}
+
+/// Decides where a compaction cuts its output SSTs besides the target SST size, which is where an
+/// SST starts to overlap too many bytes of the grandparent level, or where the SST partitioner
+/// asks for a cut.
+struct OutputSplitter {
+    /// The SSTs of the level below the output level in key order, empty if the overlap is not
+    /// limited.
+    grandparents: Vec<Arc<SsTable>>,
+    max_grandparent_overlap_bytes: u64,
+    /// The first grandparent SST not ending before the last key added.
+    grandparent_idx: usize,
+    /// The bytes of the grandparent SSTs overlapped by the current SST.
+    overlapped_bytes: u64,
+    partitioner: Option<Arc<dyn SstPartitioner>>,
+    /// The last user key added, only kept for the partitioner.
+    last_key: Vec<u8>,
+}
+
+impl OutputSplitter {
+    /// Whether to start a new SST before adding `key`, a user key greater than the keys added so
+    /// far. `is_output_empty` tells if no key was added yet.
+    fn should_cut_before(&mut self, key: &[u8], is_output_empty: bool) -> bool {
+        // the current SST overlaps the grandparent SSTs passed since it started
+        while let Some(table) = self.grandparents.get(self.grandparent_idx) {
+            if key <= table.last_key().key_ref() {
+                break;
+            }
+            if !is_output_empty {
+                self.overlapped_bytes += table.table_size();
+            }
+            self.grandparent_idx += 1;
+        }
+        let cut = !is_output_empty
+            && (self.overlapped_bytes > self.max_grandparent_overlap_bytes
+                || self
+                    .partitioner
+                    .as_ref()
+                    .is_some_and(|partitioner| partitioner.should_partition(&self.last_key, key)));
+        if self.partitioner.is_some() {
+            self.last_key.clear();
+            self.last_key.extend(key);
+        }
+        cut
+    }
+
+    /// Start counting the overlap of a new SST.
+    fn start_output(&mut self) {
+        self.overlapped_bytes = 0;
+    }
 }
 
 pub(crate) enum CompactionController {
     Leveled(LeveledCompactionController),
     Tiered(TieredCompactionController),
     Simple(SimpleLeveledCompactionController),
+    Fifo(FifoCompactionController),
+    TimeWindow(TimeWindowCompactionController),
     NoCompaction,
 }
 
 impl CompactionController {
-    pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
+    pub fn new(options: &LsmStorageOptions) -> Self {
+        let triggers = options.compaction_triggers.clone();
+        match &options.compaction_options {
+            CompactionOptions::Leveled(options) => CompactionController::Leveled(
+                LeveledCompactionController::new(options.clone())
+                    .with_compaction_triggers(triggers),
+            ),
+            CompactionOptions::Tiered(tiered_options) => CompactionController::Tiered(
+                TieredCompactionController::new(tiered_options.clone())
+                    .with_limits(options.tiered_compaction_limits.clone())
+                    .with_compaction_triggers(triggers),
+            ),
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone())
+                    .with_compaction_triggers(triggers),
+            ),
+            CompactionOptions::Fifo(options) => {
+                CompactionController::Fifo(FifoCompactionController::new(options.clone()))
+            }
+            CompactionOptions::TimeWindow(options) => CompactionController::TimeWindow(
+                TimeWindowCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
+    /// Generate a task which can run next to the running tasks, which hold the SSTs in
+    /// `compacting_sstables`.
+    pub fn generate_compaction_task_skipping(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+    ) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_skipping(snapshot, compacting_sstables)
                 .map(CompactionTask::Leveled),
             CompactionController::Simple(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_skipping(snapshot, compacting_sstables)
                 .map(CompactionTask::Simple),
             CompactionController::Tiered(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_skipping(snapshot, compacting_sstables)
                 .map(CompactionTask::Tiered),
+            CompactionController::Fifo(ctrl) => ctrl
+                .generate_compaction_task_skipping(snapshot, compacting_sstables)
+                .map(CompactionTask::Fifo),
+            CompactionController::TimeWindow(ctrl) => ctrl
+                .generate_compaction_task_skipping(snapshot, compacting_sstables)
+                .map(CompactionTask::TimeWindow),
             CompactionController::NoCompaction => unreachable!(),
         }
     }
 
+    /// Estimate the bytes compaction needs to rewrite to catch up with the writes.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl.estimate_pending_compaction_bytes(snapshot),
+            CompactionController::Simple(ctrl) => ctrl.estimate_pending_compaction_bytes(snapshot),
+            CompactionController::Tiered(ctrl) => ctrl.estimate_pending_compaction_bytes(snapshot),
+            CompactionController::TimeWindow(ctrl) => {
+                ctrl.estimate_pending_compaction_bytes(snapshot)
+            }
+            // FIFO compaction drops SSTs without rewriting them
+            CompactionController::Fifo(_) | CompactionController::NoCompaction => 0,
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -84,6 +323,13 @@ impl CompactionController {
             (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
+            (CompactionController::Fifo(ctrl), CompactionTask::Fifo(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            (CompactionController::TimeWindow(ctrl), CompactionTask::TimeWindow(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            (_, CompactionTask::Range(task)) => task.apply_compaction_result(snapshot, output),
             _ => unreachable!(),
         }
     }
@@ -93,12 +339,12 @@ impl CompactionController {
     pub fn flush_to_l0(&self) -> bool {
         matches!(
             self,
-            Self::Leveled(_) | Self::Simple(_) | Self::NoCompaction
+            Self::Leveled(_) | Self::Simple(_) | Self::Fifo(_) | Self::NoCompaction
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionOptions {
     /// Leveled compaction with partial compaction + dynamic level support (= RocksDB's Leveled
     /// Compaction)
@@ -107,25 +353,100 @@ pub enum CompactionOptions {
     Tiered(TieredCompactionOptions),
     /// Simple leveled compaction
     Simple(SimpleLeveledCompactionOptions),
+    /// FIFO compaction, which keeps all SSTs in L0 and drops the oldest ones (= RocksDB's FIFO
+    /// compaction)
+    Fifo(FifoCompactionOptions),
+    /// Time-window compaction, which keeps the tiers of each time window apart (= Cassandra's
+    /// time-window compaction)
+    TimeWindow(TimeWindowCompactionOptions),
     /// In no compaction mode (week 1), always flush to L0
     NoCompaction,
 }
 
+/// The threads running the background compaction tasks, which they receive over a channel.
+struct CompactionWorkerPool {
+    workers: Vec<std::thread::JoinHandle<()>>,
+    task_tx: crossbeam_channel::Sender<(CompactionTask, Arc<CurrentOptions>)>,
+    task_rx: crossbeam_channel::Receiver<(CompactionTask, Arc<CurrentOptions>)>,
+    /// The number of tasks sent to the workers and not finished yet.
+    pending_tasks: Arc<AtomicUsize>,
+}
+
+impl Default for CompactionWorkerPool {
+    fn default() -> Self {
+        let (task_tx, task_rx) = crossbeam_channel::unbounded();
+        Self {
+            workers: Vec::new(),
+            task_tx,
+            task_rx,
+            pending_tasks: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl CompactionWorkerPool {
+    /// Start workers until there are `num_workers` of them. The pool only grows, when
+    /// `max_background_compactions` is raised the extra workers are started.
+    fn grow(&mut self, storage: &Arc<LsmStorageInner>, num_workers: usize) {
+        while self.workers.len() < num_workers {
+            let storage = storage.clone();
+            let task_rx = self.task_rx.clone();
+            let pending_tasks = self.pending_tasks.clone();
+            self.workers.push(std::thread::spawn(move || {
+                for (task, current) in task_rx {
+                    if let Err(e) = storage.run_compaction_task(task, &current) {
+                        eprintln!("compaction failed: {}", e);
+                    }
+                    pending_tasks.fetch_sub(1, Ordering::SeqCst);
+                }
+            }));
+        }
+    }
+
+    /// Wait until the workers finish the tasks sent to them.
+    fn join(self) {
+        drop(self.task_tx);
+        for worker in self.workers {
+            worker.join().ok();
+        }
+    }
+}
+
 impl LsmStorageInner {
     fn compact_generate_sst_from_iter(
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
         compact_to_bottom_level: bool,
+        level: usize,
+        upper: Option<&[u8]>,
+        target_sst_size: usize,
+        discarded: &Mutex<Vec<ValuePointer>>,
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
-        let mut new_sst = Vec::new();
+        let mut new_sst = CompactionOutput {
+            storage: self,
+            ssts: Vec::new(),
+        };
         let watermark = self.mvcc().watermark();
+        let mut splitter = self.output_splitter(level);
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
-        let compaction_filters = self.compaction_filters.lock().clone();
-        'outer: while iter.is_valid() {
+        let mut discarded_values = Vec::new();
+        let mut compaction_filters = self.create_compaction_filters(&CompactionFilterContext {
+            level,
+            is_bottom_level: compact_to_bottom_level,
+        });
+        while iter.is_valid() {
+            // stop at the end of the key range of a subcompaction
+            if let Some(upper) = upper {
+                if iter.key().key_ref() >= upper {
+                    break;
+                }
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                self.background_work.check_cancelled()?;
+                let sst_id = self.next_sst_id();
+                builder = Some((sst_id, self.new_sst_builder(sst_id, IoPriority::Low)));
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -145,44 +466,75 @@ impl LsmStorageInner {
                 continue;
             }
 
+            // the value replacing the current value, which is empty if the version is removed
+            let mut new_value = None;
             if iter.key().ts() <= watermark {
                 if same_as_last_key && !first_key_below_watermark {
+                    Self::discard_value(&mut discarded_values, &iter)?;
                     iter.next()?;
                     continue;
                 }
 
                 first_key_below_watermark = false;
 
-                if !compaction_filters.is_empty() {
-                    for filter in &compaction_filters {
-                        match filter {
-                            CompactionFilter::Prefix(x) => {
-                                if iter.key().key_ref().starts_with(x) {
-                                    iter.next()?;
-                                    continue 'outer;
-                                }
-                            }
+                if !compaction_filters.is_empty() && !iter.value().is_empty() {
+                    new_value = self.filter_version(
+                        &mut compaction_filters,
+                        iter.key(),
+                        iter.value(),
+                        iter.is_value_pointer(),
+                        level,
+                    )?;
+                    if compact_to_bottom_level
+                        && matches!(&new_value, Some(value) if value.is_empty())
+                    {
+                        // no older version is left to be hidden by a delete
+                        Self::discard_value(&mut discarded_values, &iter)?;
+                        if !same_as_last_key {
+                            last_key.clear();
+                            last_key.extend(iter.key().key_ref());
                         }
+                        iter.next()?;
+                        continue;
                     }
                 }
             }
 
-            let builder_inner = builder.as_mut().unwrap();
+            let (_, builder_inner) = builder.as_mut().unwrap();
 
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
+            let cut_by_splitter = !same_as_last_key
+                && splitter.should_cut_before(iter.key().key_ref(), builder_inner.is_empty());
+            if (builder_inner.estimated_size() >= target_sst_size && !same_as_last_key)
+                || cut_by_splitter
+            {
+                let (sst_id, old_builder) = builder.take().unwrap();
+                let sst = self.build_sst(old_builder, sst_id, false)?;
+                new_sst.ssts.push(sst);
+                self.background_work.check_cancelled()?;
                 let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
-                let sst = Arc::new(old_builder.build(
-                    sst_id,
-                    Some(self.block_cache.clone()),
-                    self.path_of_sst(sst_id),
-                )?);
-                new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some((sst_id, self.new_sst_builder(sst_id, IoPriority::Low)));
+                splitter.start_output();
             }
 
-            let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            let (_, builder_inner) = builder.as_mut().unwrap();
+            let num_blocks = builder_inner.num_blocks();
+            if let Some(value) = new_value {
+                Self::discard_value(&mut discarded_values, &iter)?;
+                builder_inner.add(iter.key(), &value);
+            } else if !iter.is_value_pointer() {
+                builder_inner.add(iter.key(), iter.value());
+            } else if builder_inner.separates_values() {
+                builder_inner.add_value_pointer(iter.key(), iter.value());
+            } else {
+                // key-value separation is disabled, move the value back into the SST
+                let pointer = ValuePointer::decode(iter.value())?;
+                builder_inner.add(iter.key(), &self.value_log.read(pointer)?);
+                discarded_values.push(pointer);
+            }
+            // stop a cancelled compaction once a block is finished
+            if builder_inner.num_blocks() > num_blocks {
+                self.background_work.check_cancelled()?;
+            }
 
             if !same_as_last_key {
                 last_key.clear();
@@ -191,23 +543,382 @@ impl LsmStorageInner {
 
             iter.next()?;
         }
-        if let Some(builder) = builder {
-            let sst_id = self.next_sst_id(); // lock dropped here
-            let sst = Arc::new(builder.build(
-                sst_id,
-                Some(self.block_cache.clone()),
-                self.path_of_sst(sst_id),
-            )?);
-            new_sst.push(sst);
+        if let Some((sst_id, builder)) = builder {
+            let sst = self.build_sst(builder, sst_id, false)?;
+            new_sst.ssts.push(sst);
+        }
+        discarded.lock().extend(discarded_values);
+        Ok(std::mem::take(&mut new_sst.ssts))
+    }
+
+    /// Create the splitter of a compaction writing to `level`. The overlap is only limited for the
+    /// strategies with levels, where `level` is the level number, and the level below it is read
+    /// from the current state.
+    fn output_splitter(&self, level: usize) -> OutputSplitter {
+        let options = &self.options;
+        let grandparents = match options.max_grandparent_overlap_bytes {
+            Some(_) if level > 0 => {
+                let snapshot = self.state.read();
+                snapshot
+                    .levels
+                    .get(level)
+                    .map(|(_, ids)| ids.iter().map(|id| snapshot.sstables[id].clone()).collect())
+                    .unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+        OutputSplitter {
+            grandparents,
+            max_grandparent_overlap_bytes: options
+                .max_grandparent_overlap_bytes
+                .unwrap_or(u64::MAX),
+            grandparent_idx: 0,
+            overlapped_bytes: 0,
+            partitioner: options.sst_partitioner.clone(),
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Remove the SSTs written by a compaction which failed or was cancelled, with the value log
+    /// files sharing their ids.
+    fn remove_compaction_output(&self, ssts: &[Arc<SsTable>]) {
+        for sst in ssts {
+            if let Err(e) = std::fs::remove_file(self.path_of_sst(sst.sst_id())) {
+                eprintln!("failed to remove {}.sst: {}", sst.sst_id(), e);
+            }
+            // no value log file is written if no value is separated
+            match std::fs::remove_file(self.path_of_vlog(sst.sst_id())) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    eprintln!("failed to remove {}.vlog: {}", sst.sst_id(), e);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Create the compaction filters of one compaction from the factories added so far.
+    fn create_compaction_filters(
+        &self,
+        context: &CompactionFilterContext,
+    ) -> Vec<Box<dyn CompactionKeyFilter>> {
+        self.compaction_filters
+            .lock()
+            .iter()
+            .map(|factory| factory.create_compaction_filter(context))
+            .collect()
+    }
+
+    /// Run the compaction filters on the current version, which is not a delete. Returns the new
+    /// value if the filters change the value, or an empty value if they remove the version.
+    fn filter_version(
+        &self,
+        compaction_filters: &mut [Box<dyn CompactionKeyFilter>],
+        key: KeySlice,
+        value: &[u8],
+        is_value_pointer: bool,
+        level: usize,
+    ) -> Result<Option<Bytes>> {
+        let mut value = if is_value_pointer {
+            self.value_log.read(ValuePointer::decode(value)?)?
+        } else {
+            Bytes::copy_from_slice(value)
+        };
+        let mut changed = false;
+        for filter in compaction_filters {
+            // a filter sees the value changed by the filters before it
+            match filter.filter(key.key_ref(), key.ts(), &value, level) {
+                CompactionFilterDecision::Keep => {}
+                CompactionFilterDecision::Remove => return Ok(Some(Bytes::new())),
+                CompactionFilterDecision::ChangeValue(new_value) => {
+                    value = new_value;
+                    changed = true;
+                }
+            }
+        }
+        Ok(changed.then_some(value))
+    }
+
+    /// Record a value dropped by a compaction, which is reported to the value log once the
+    /// compaction result is applied.
+    fn discard_value(
+        discarded_values: &mut Vec<ValuePointer>,
+        iter: &impl StorageIterator,
+    ) -> Result<()> {
+        if iter.is_value_pointer() {
+            discarded_values.push(ValuePointer::decode(iter.value())?);
+        }
+        Ok(())
+    }
+
+    /// Get the input SSTs of a task, as the SSTs which may overlap with each other, and the sorted
+    /// runs of non-overlapping SSTs.
+    fn compaction_inputs(
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+    ) -> (Vec<Arc<SsTable>>, Vec<Vec<Arc<SsTable>>>) {
+        let tables = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>()
+        };
+        match task {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => (tables(l0_sstables), vec![tables(l1_sstables)]),
+            CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => match upper_level {
+                Some(_) => (
+                    Vec::new(),
+                    vec![tables(upper_level_sst_ids), tables(lower_level_sst_ids)],
+                ),
+                None => (
+                    tables(upper_level_sst_ids),
+                    vec![tables(lower_level_sst_ids)],
+                ),
+            },
+            CompactionTask::Tiered(TieredCompactionTask { tiers, .. })
+            | CompactionTask::TimeWindow(TimeWindowCompactionTask { tiers, .. }) => (
+                Vec::new(),
+                tiers.iter().map(|(_, ids)| tables(ids)).collect(),
+            ),
+            CompactionTask::Range(RangeCompactionTask {
+                l0_sst_ids, levels, ..
+            }) => (
+                tables(l0_sst_ids),
+                levels.iter().map(|(_, ids)| tables(ids)).collect(),
+            ),
+            CompactionTask::Fifo(FifoCompactionTask { merged_sst_ids, .. }) => {
+                (tables(merged_sst_ids), Vec::new())
+            }
         }
-        Ok(new_sst)
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+    /// Split the key range of the inputs into up to `max_subcompactions` ranges at the first keys
+    /// of the input SSTs. Returns the first keys of the ranges except the first range.
+    fn subcompaction_boundaries(
+        &self,
+        overlapping: &[Arc<SsTable>],
+        sorted_runs: &[Vec<Arc<SsTable>>],
+        max_subcompactions: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut first_keys = overlapping
+            .iter()
+            .chain(sorted_runs.iter().flatten())
+            .map(|table| table.first_key().key_ref().to_vec())
+            .collect::<Vec<_>>();
+        first_keys.sort();
+        first_keys.dedup();
+        // a range starting at the smallest key would be empty
+        if first_keys.len() <= 1 {
+            return Vec::new();
+        }
+        let candidates = &first_keys[1..];
+        let num_ranges = max_subcompactions.min(candidates.len() + 1);
+        let mut boundaries = (1..num_ranges)
+            .map(|idx| candidates[idx * candidates.len() / num_ranges].clone())
+            .collect::<Vec<_>>();
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Compact the keys in `[lower, upper)` of the inputs of `task`, where `None` is unbounded.
+    fn compact_key_range(
+        &self,
+        task: &CompactionTask,
+        overlapping: &[Arc<SsTable>],
+        sorted_runs: &[Vec<Arc<SsTable>>],
+        (lower, upper): (Option<&[u8]>, Option<&[u8]>),
+        target_sst_size: usize,
+        discarded: &Mutex<Vec<ValuePointer>>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let read_options = self.compaction_read_options();
+        let in_range = |table: &Arc<SsTable>| {
+            lower.is_none_or(|lower| table.last_key().key_ref() >= lower)
+                && upper.is_none_or(|upper| table.first_key().key_ref() < upper)
+        };
+        let seek_key = lower.map(|lower| KeySlice::from_slice(lower, TS_RANGE_BEGIN));
+        let mut overlapping_iters = Vec::with_capacity(overlapping.len());
+        for table in overlapping.iter().filter(|table| in_range(table)) {
+            let iter = match seek_key {
+                Some(key) => SsTableIterator::create_and_seek_to_key_with_options(
+                    table.clone(),
+                    key,
+                    read_options.clone(),
+                )?,
+                None => SsTableIterator::create_and_seek_to_first_with_options(
+                    table.clone(),
+                    read_options.clone(),
+                )?,
+            };
+            overlapping_iters.push(Box::new(iter));
+        }
+        let mut sorted_run_iters = Vec::with_capacity(sorted_runs.len());
+        for sorted_run in sorted_runs {
+            let tables = sorted_run
+                .iter()
+                .filter(|table| in_range(table))
+                .cloned()
+                .collect::<Vec<_>>();
+            let iter = match seek_key {
+                Some(key) => SstConcatIterator::create_and_seek_to_key_with_options(
+                    tables,
+                    key,
+                    read_options.clone(),
+                )?,
+                None => SstConcatIterator::create_and_seek_to_first_with_options(
+                    tables,
+                    read_options.clone(),
+                )?,
+            };
+            sorted_run_iters.push(Box::new(iter));
+        }
+        let iter = TwoMergeIterator::create(
+            MergeIterator::create(overlapping_iters),
+            MergeIterator::create(sorted_run_iters),
+        )?;
+        self.compact_generate_sst_from_iter(
+            iter,
+            task.compact_to_bottom_level(),
+            task.output_level(),
+            upper,
+            target_sst_size,
+            discarded,
+        )
+    }
+
+    /// Run a task as key-range subcompactions in parallel, and concatenate the outputs in key
+    /// order. Returns `None` if the inputs cannot be split.
+    fn compact_in_parallel(
+        &self,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+        options: &LsmStorageOptions,
+        discarded: &Mutex<Vec<ValuePointer>>,
+    ) -> Option<Result<Vec<Arc<SsTable>>>> {
+        if options.max_subcompactions <= 1 {
+            return None;
+        }
+        let (overlapping, sorted_runs) = Self::compaction_inputs(task, snapshot);
+        let boundaries =
+            self.subcompaction_boundaries(&overlapping, &sorted_runs, options.max_subcompactions);
+        if boundaries.is_empty() {
+            return None;
+        }
+        let outputs = std::thread::scope(|scope| {
+            let handles = (0..=boundaries.len())
+                .map(|idx| {
+                    let lower = idx.checked_sub(1).map(|idx| boundaries[idx].as_slice());
+                    let upper = boundaries.get(idx).map(|key| key.as_slice());
+                    let (overlapping, sorted_runs) = (&overlapping, &sorted_runs);
+                    scope.spawn(move || {
+                        self.compact_key_range(
+                            task,
+                            overlapping,
+                            sorted_runs,
+                            (lower, upper),
+                            options.target_sst_size,
+                            discarded,
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .map_err(|e| anyhow!("subcompaction panicked: {:?}", e))?
+                })
+                .collect::<Vec<_>>()
+        });
+        if outputs.iter().any(|output| output.is_err()) {
+            // the subcompactions which finished leave their SSTs behind
+            for output in outputs.iter().flatten() {
+                self.remove_compaction_output(output);
+            }
+        }
+        Some(
+            outputs
+                .into_iter()
+                .collect::<Result<Vec<_>>>()
+                .map(|outputs| outputs.into_iter().flatten().collect()),
+        )
+    }
+
+    /// Merge the SSTs of a FIFO task. The output keeps the creation time of the newest input, so
+    /// that merging does not keep the data longer than the TTL.
+    fn compact_fifo(
+        &self,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+        target_sst_size: usize,
+        discarded: &Mutex<Vec<ValuePointer>>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let (tables, _) = Self::compaction_inputs(task, snapshot);
+        let Some(created_at) = tables.iter().map(|table| table.created_at()).max() else {
+            // dropping SSTs does not write anything
+            return Ok(Vec::new());
+        };
+        let output =
+            self.compact_key_range(task, &tables, &[], (None, None), target_sst_size, discarded)?;
+        output
+            .into_iter()
+            .map(|sst| {
+                // the creation time survives a restart as the modification time of the file
+                File::options()
+                    .write(true)
+                    .open(self.path_of_sst(sst.sst_id()))?
+                    .set_modified(created_at)?;
+                let sst = Arc::into_inner(sst).expect("the new SST is not shared");
+                Ok(Arc::new(sst.with_created_at(created_at)))
+            })
+            .collect()
+    }
+
+    fn compact(
+        &self,
+        task: &CompactionTask,
+        options: &LsmStorageOptions,
+        discarded: &Mutex<Vec<ValuePointer>>,
+    ) -> Result<Vec<Arc<SsTable>>> {
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
+        if let CompactionTask::Fifo(_) = task {
+            return self.compact_fifo(task, &snapshot, options.target_sst_size, discarded);
+        }
+        if let CompactionTask::Leveled(LeveledCompactionTask {
+            upper_level_sst_ids,
+            is_trivial_move: true,
+            ..
+        })
+        | CompactionTask::Simple(SimpleLeveledCompactionTask {
+            upper_level_sst_ids,
+            is_trivial_move: true,
+            ..
+        }) = task
+        {
+            return Ok(upper_level_sst_ids
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect());
+        }
+        if let Some(output) = self.compact_in_parallel(task, &snapshot, options, discarded) {
+            return output;
+        }
+        let read_options = self.compaction_read_options();
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
@@ -215,9 +926,12 @@ impl LsmStorageInner {
             } => {
                 let mut l0_iters = Vec::with_capacity(l0_sstables.len());
                 for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
+                    l0_iters.push(Box::new(
+                        SsTableIterator::create_and_seek_to_first_with_options(
+                            snapshot.sstables.get(id).unwrap().clone(),
+                            read_options.clone(),
+                        )?,
+                    ));
                 }
                 let mut l1_iters = Vec::with_capacity(l1_sstables.len());
                 for id in l1_sstables.iter() {
@@ -225,9 +939,19 @@ impl LsmStorageInner {
                 }
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    SstConcatIterator::create_and_seek_to_first_with_options(
+                        l1_iters,
+                        read_options.clone(),
+                    )?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    task.compact_to_bottom_level(),
+                    task.output_level(),
+                    None,
+                    options.target_sst_size,
+                    discarded,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -248,33 +972,53 @@ impl LsmStorageInner {
                     for id in upper_level_sst_ids.iter() {
                         upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
+                    let upper_iter = SstConcatIterator::create_and_seek_to_first_with_options(
+                        upper_ssts,
+                        read_options.clone(),
+                    )?;
                     let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
                     for id in lower_level_sst_ids.iter() {
                         lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = SstConcatIterator::create_and_seek_to_first_with_options(
+                        lower_ssts,
+                        read_options.clone(),
+                    )?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
                         task.compact_to_bottom_level(),
+                        task.output_level(),
+                        None,
+                        options.target_sst_size,
+                        discarded,
                     )
                 }
                 None => {
                     let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
                     for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
+                        upper_iters.push(Box::new(
+                            SsTableIterator::create_and_seek_to_first_with_options(
+                                snapshot.sstables.get(id).unwrap().clone(),
+                                read_options.clone(),
+                            )?,
+                        ));
                     }
                     let upper_iter = MergeIterator::create(upper_iters);
                     let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
                     for id in lower_level_sst_ids.iter() {
                         lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = SstConcatIterator::create_and_seek_to_first_with_options(
+                        lower_ssts,
+                        read_options.clone(),
+                    )?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
                         task.compact_to_bottom_level(),
+                        task.output_level(),
+                        None,
+                        options.target_sst_size,
+                        discarded,
                     )
                 }
             },
@@ -285,18 +1029,42 @@ impl LsmStorageInner {
                     for id in tier_sst_ids.iter() {
                         ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+                    iters.push(Box::new(
+                        SstConcatIterator::create_and_seek_to_first_with_options(
+                            ssts,
+                            read_options.clone(),
+                        )?,
+                    ));
                 }
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
                     task.compact_to_bottom_level(),
+                    task.output_level(),
+                    None,
+                    options.target_sst_size,
+                    discarded,
+                )
+            }
+            CompactionTask::Fifo(_) => unreachable!(),
+            // a task only dropping expired windows has no tiers to merge
+            CompactionTask::Range(_) | CompactionTask::TimeWindow(_) => {
+                let (overlapping, sorted_runs) = Self::compaction_inputs(task, &snapshot);
+                self.compact_key_range(
+                    task,
+                    &overlapping,
+                    &sorted_runs,
+                    (None, None),
+                    options.target_sst_size,
+                    discarded,
                 )
             }
         }
     }
 
     pub fn force_full_compaction(&self) -> Result<()> {
-        let CompactionOptions::NoCompaction = self.options.compaction_options else {
+        let current = self.current_options();
+        let options = &current.options;
+        let CompactionOptions::NoCompaction = options.compaction_options else {
             panic!("full compaction can only be called with compaction is not enabled")
         };
 
@@ -314,7 +1082,9 @@ impl LsmStorageInner {
 
         println!("force full compaction: {:?}", compaction_task);
 
-        let sstables = self.compact(&compaction_task)?;
+        let _running_compaction = self.background_work.start_compaction();
+        let discarded = Mutex::new(Vec::new());
+        let sstables = self.compact(&compaction_task, options, &discarded)?;
         let mut ids = Vec::with_capacity(sstables.len());
 
         {
@@ -345,30 +1115,113 @@ impl LsmStorageInner {
                 &state_lock,
                 ManifestRecord::Compaction(compaction_task, ids.clone()),
             )?;
+            self.record_discarded_values(&state_lock, discarded.into_inner())?;
         }
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
             std::fs::remove_file(self.path_of_sst(*sst))?;
         }
 
         println!("force full compaction done, new SSTs: {:?}", ids);
+        self.update_write_stall();
 
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
-        let snapshot = {
-            let state = self.state.read();
-            state.clone()
+    /// Compact every SST overlapping `[lower, upper]` on each level down to `target_level`, or the
+    /// bottom level if `None`, into the target level. With tiered compaction, the SSTs overlapping
+    /// the range in every tier are compacted into a new tier, and `target_level` must be `None`.
+    /// Waits for the background compactions holding any of the SSTs to finish first.
+    pub fn compact_range(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        target_level: Option<usize>,
+    ) -> Result<()> {
+        let (task, current) = loop {
+            // a task blocked by the SSTs of a running compaction is generated again once it stops
+            let stopped_compactions = self.background_work.stopped_compactions();
+            {
+                let _state_lock = self.state_lock.lock();
+                let snapshot = self.state.read().clone();
+                let current = self.current_options();
+                let task = match current.options.compaction_options {
+                    CompactionOptions::Fifo(_) => {
+                        bail!("range compaction is not supported with FIFO compaction");
+                    }
+                    CompactionOptions::TimeWindow(_) => {
+                        bail!("range compaction is not supported with time-window compaction");
+                    }
+                    CompactionOptions::Tiered(_) => {
+                        if target_level.is_some() {
+                            bail!("target level is not supported with tiered compaction");
+                        }
+                        RangeCompactionTask::generate_tiered(&snapshot, lower, upper)
+                    }
+                    _ => RangeCompactionTask::generate_leveled(
+                        &snapshot,
+                        lower,
+                        upper,
+                        target_level,
+                    )?,
+                };
+                let Some(task) = task else {
+                    return Ok(());
+                };
+                let sst_ids = task.compacting_sst_ids();
+                let mut compacting_sstables = self.compacting_sstables.lock();
+                if sst_ids.iter().all(|id| !compacting_sstables.contains(id)) {
+                    compacting_sstables.extend(sst_ids);
+                    break (task, current);
+                }
+            }
+            self.background_work
+                .wait_for_compaction_stopped(stopped_compactions);
         };
-        let task = self
-            .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
+        self.run_compaction_task(CompactionTask::Range(task), &current)
+    }
+
+    /// Run one compaction task in the calling thread.
+    #[cfg(test)]
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let current = self.current_options();
+        let Some(task) = self.schedule_compaction_task(&current) else {
             return Ok(());
         };
+        self.run_compaction_task(task, &current)
+    }
+
+    /// Generate a compaction task and mark its input SSTs as being compacted, so that tasks
+    /// scheduled later take other SSTs.
+    fn schedule_compaction_task(&self, current: &CurrentOptions) -> Option<CompactionTask> {
+        let _state_lock = self.state_lock.lock();
+        let snapshot = self.state.read().clone();
+        let mut compacting_sstables = self.compacting_sstables.lock();
+        let task = current
+            .compaction_controller
+            .generate_compaction_task_skipping(&snapshot, &compacting_sstables)?;
+        compacting_sstables.extend(task.compacting_sst_ids());
+        Some(task)
+    }
+
+    /// Run a task scheduled by `schedule_compaction_task` with the same options, and apply the
+    /// result to the state which other tasks may have changed in the meantime.
+    fn run_compaction_task(&self, task: CompactionTask, current: &CurrentOptions) -> Result<()> {
+        // the compaction stops once its SSTs are released
+        let _running_compaction = self.background_work.start_compaction();
         self.dump_structure();
         println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+        let discarded = Mutex::new(Vec::new());
+        let sstables = match self.compact(&task, &current.options, &discarded) {
+            Ok(sstables) => sstables,
+            Err(e) => {
+                let _state_lock = self.state_lock.lock();
+                let mut compacting_sstables = self.compacting_sstables.lock();
+                for id in task.compacting_sst_ids() {
+                    compacting_sstables.remove(&id);
+                }
+                return Err(e);
+            }
+        };
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
         let ssts_to_remove = {
             let state_lock = self.state_lock.lock();
@@ -376,10 +1229,14 @@ impl LsmStorageInner {
             let mut new_sst_ids = Vec::new();
             for file_to_add in sstables {
                 new_sst_ids.push(file_to_add.sst_id());
+                // the SSTs moved without being rewritten are already in the state
+                if task.is_trivial_move() {
+                    continue;
+                }
                 let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
                 assert!(result.is_none());
             }
-            let (mut snapshot, files_to_remove) = self
+            let (mut snapshot, files_to_remove) = current
                 .compaction_controller
                 .apply_compaction_result(&snapshot, &task, &output);
             let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
@@ -391,9 +1248,16 @@ impl LsmStorageInner {
             let mut state = self.state.write();
             *state = Arc::new(snapshot);
             drop(state);
+            let mut compacting_sstables = self.compacting_sstables.lock();
+            for id in task.compacting_sst_ids() {
+                compacting_sstables.remove(&id);
+            }
+            drop(compacting_sstables);
             self.sync_dir()?;
             self.manifest()
                 .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+            // the dropped values are still referenced by the input SSTs until the result is applied
+            self.record_discarded_values(&state_lock, discarded.into_inner())?;
             ssts_to_remove
         };
         println!(
@@ -406,6 +1270,7 @@ impl LsmStorageInner {
             std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
         }
         self.sync_dir()?;
+        self.update_write_stall();
 
         Ok(())
     }
@@ -414,33 +1279,72 @@ impl LsmStorageInner {
         self: &Arc<Self>,
         rx: crossbeam_channel::Receiver<()>,
     ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        if let CompactionOptions::Leveled(_)
-        | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
-        {
+        if self.current_options().options.is_compaction_enabled() {
             let this = self.clone();
             let handle = std::thread::spawn(move || {
                 let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                let mut pool = CompactionWorkerPool::default();
                 loop {
                     crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+                        recv(ticker) -> _ => this.schedule_compaction_tasks(&mut pool),
+                        recv(rx) -> _ => break
                     }
                 }
+                pool.join();
             });
             return Ok(Some(handle));
         }
         Ok(None)
     }
 
+    /// Send each task which can run next to the running tasks to the worker pool, so that at
+    /// most `max_background_compactions` tasks run at a time.
+    fn schedule_compaction_tasks(self: &Arc<Self>, pool: &mut CompactionWorkerPool) {
+        if self.background_work.is_paused() {
+            return;
+        }
+        let current = self.current_options();
+        let max_background_compactions = current.options.max_background_compactions.max(1);
+        pool.grow(self, max_background_compactions);
+        while pool.pending_tasks.load(Ordering::SeqCst) < max_background_compactions {
+            let Some(task) = self.schedule_compaction_task(&current) else {
+                break;
+            };
+            pool.pending_tasks.fetch_add(1, Ordering::SeqCst);
+            pool.task_tx.send((task, current.clone())).unwrap();
+        }
+    }
+
     fn trigger_flush(&self) -> Result<()> {
-        let res = {
-            let state = self.state.read();
-            state.imm_memtables.len() >= self.options.num_memtable_limit
-        };
-        if res {
+        // the write buffer manager asks the instance using the most memory to flush everything
+        if self
+            .write_buffer
+            .as_ref()
+            .is_some_and(|x| x.take_flush_request())
+        {
+            if !self.state.read().memtable.is_empty() {
+                self.force_freeze_memtable(&self.state_lock.lock())?;
+            }
+            while !self.state.read().imm_memtables.is_empty() {
+                self.force_flush_next_imm_memtable()?;
+            }
+        }
+
+        // keep flushing while writes are stalled by the memtables, so that writes resume before
+        // the next tick
+        let num_memtable_limit = self.current_options().options.num_memtable_limit;
+        loop {
+            let res = {
+                let state = self.state.read();
+                state.imm_memtables.len() >= num_memtable_limit
+                    || (!state.imm_memtables.is_empty()
+                        && self
+                            .write_controller
+                            .stalled_by(WriteStallCause::ImmMemtables))
+            };
+            if !res {
+                break;
+            }
             self.force_flush_next_imm_memtable()?;
         }
 
@@ -456,8 +1360,10 @@ impl LsmStorageInner {
             let ticker = crossbeam_channel::tick(Duration::from_millis(50));
             loop {
                 crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("flush failed: {}", e);
+                    recv(ticker) -> _ => if !this.background_work.is_paused() {
+                        if let Err(e) = this.trigger_flush() {
+                            eprintln!("flush failed: {}", e);
+                        }
                     },
                     recv(rx) -> _ => return
                 }
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsm_storage::LsmStorageState;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FifoCompactionOptions {
+    /// Drop the oldest SSTs once all SSTs take more than this many bytes.
+    pub max_table_files_size: u64,
+    /// Drop the SSTs written longer ago than this, disabled if `None`.
+    pub ttl: Option<Duration>,
+    /// Merge the newest L0 SSTs smaller than `intra_l0_max_file_size` into one once there are
+    /// this many of them, disabled if `None`.
+    pub intra_l0_compaction_trigger: Option<usize>,
+    pub intra_l0_max_file_size: u64,
+}
+
+/// A FIFO compaction task, which either drops the oldest SSTs or merges the newest SSTs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FifoCompactionTask {
+    /// The oldest L0 SSTs, removed without being rewritten.
+    pub dropped_sst_ids: Vec<usize>,
+    /// The newest L0 SSTs, merged into new SSTs.
+    pub merged_sst_ids: Vec<usize>,
+}
+
+/// Keeps all SSTs in L0, and drops the oldest SSTs wholesale once they take too much space or are
+/// too old. It suits data only kept for a while, such as logs and metrics, as overwritten and
+/// deleted keys are never cleaned up.
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<FifoCompactionTask> {
+        self.generate_compaction_task_at(snapshot, &HashSet::new(), SystemTime::now())
+    }
+
+    pub fn generate_compaction_task_skipping(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+    ) -> Option<FifoCompactionTask> {
+        self.generate_compaction_task_at(snapshot, compacting_sstables, SystemTime::now())
+    }
+
+    /// Generate a compaction task with `now` as the current time, which decides the SSTs
+    /// exceeding the TTL.
+    ///
+    /// Returns `None` if no compaction needs to be scheduled. The SSTs in `compacting_sstables`,
+    /// which other tasks are compacting, are never picked.
+    pub fn generate_compaction_task_at(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+        now: SystemTime,
+    ) -> Option<FifoCompactionTask> {
+        let is_free = |id: &&usize| !compacting_sstables.contains(*id);
+
+        // drop from the oldest SST until the remaining SSTs fit in the size limit and the TTL
+        let mut total_size = snapshot
+            .l0_sstables
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        let mut dropped_sst_ids = Vec::new();
+        for id in snapshot.l0_sstables.iter().rev().take_while(is_free) {
+            let table = &snapshot.sstables[id];
+            let expired = self.options.ttl.is_some_and(|ttl| {
+                now.duration_since(table.created_at())
+                    .is_ok_and(|age| age > ttl)
+            });
+            if total_size <= self.options.max_table_files_size && !expired {
+                break;
+            }
+            total_size -= table.table_size();
+            dropped_sst_ids.push(*id);
+        }
+        if !dropped_sst_ids.is_empty() {
+            println!(
+                "fifo compaction triggered by size limit or ttl: {:?}",
+                dropped_sst_ids
+            );
+            return Some(FifoCompactionTask {
+                dropped_sst_ids,
+                merged_sst_ids: Vec::new(),
+            });
+        }
+
+        let trigger = self.options.intra_l0_compaction_trigger?;
+        let merged_sst_ids = snapshot
+            .l0_sstables
+            .iter()
+            .take_while(is_free)
+            .take_while(|id| {
+                snapshot.sstables[*id].table_size() < self.options.intra_l0_max_file_size
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        if merged_sst_ids.len() >= trigger.max(2) {
+            println!("fifo intra-L0 compaction triggered: {:?}", merged_sst_ids);
+            return Some(FifoCompactionTask {
+                dropped_sst_ids: Vec::new(),
+                merged_sst_ids,
+            });
+        }
+        None
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &FifoCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let mut files_to_remove = task.dropped_sst_ids.clone();
+        files_to_remove.extend(&task.merged_sst_ids);
+        let removed = files_to_remove.iter().copied().collect::<HashSet<_>>();
+        // the output takes the place of the merged SSTs, after the SSTs flushed in the meantime
+        let position = snapshot
+            .l0_sstables
+            .iter()
+            .position(|id| task.merged_sst_ids.contains(id));
+        let mut l0_sstables = Vec::with_capacity(snapshot.l0_sstables.len());
+        for (idx, id) in snapshot.l0_sstables.iter().enumerate() {
+            if Some(idx) == position {
+                l0_sstables.extend(output);
+            }
+            if !removed.contains(id) {
+                l0_sstables.push(*id);
+            }
+        }
+        assert_eq!(
+            l0_sstables.len() + removed.len(),
+            snapshot.l0_sstables.len() + output.len(),
+            "some SSTs of the task are not in L0"
+        );
+        snapshot.l0_sstables = l0_sstables;
+        (snapshot, files_to_remove)
+    }
+}
@@ -0,0 +1,78 @@
+use bytes::Bytes;
+
+/// What a compaction filter does with a version of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionFilterDecision {
+    Keep,
+    /// Remove the version. Outside the bottom level, it is replaced by a delete, so that the older
+    /// versions in the lower levels are not visible again.
+    Remove,
+    /// Replace the value of the version.
+    ChangeValue(Bytes),
+}
+
+/// Decides what to do with the versions of the keys written by a compaction. Only the newest
+/// version at or below the watermark of each key is passed to the filter, as the versions above
+/// the watermark may still be read by a transaction, and the older versions are removed anyway.
+/// Deletes are never passed to the filter.
+pub trait CompactionKeyFilter: Send {
+    /// Filter a version of `key` with timestamp `ts` written to `level`. The level is 0 for the
+    /// compaction strategies without levels, which are tiered, time-window and FIFO compaction.
+    fn filter(
+        &mut self,
+        key: &[u8],
+        ts: u64,
+        value: &[u8],
+        level: usize,
+    ) -> CompactionFilterDecision;
+}
+
+/// The compaction being filtered.
+#[derive(Debug, Clone)]
+pub struct CompactionFilterContext {
+    /// The level the output is written to, as passed to `CompactionKeyFilter::filter`.
+    pub level: usize,
+    pub is_bottom_level: bool,
+}
+
+/// Creates a compaction filter for every compaction, so that a filter can keep state across the
+/// keys of one compaction. Parallel subcompactions each get their own filter.
+pub trait CompactionFilterFactory: Send + Sync {
+    fn create_compaction_filter(
+        &self,
+        context: &CompactionFilterContext,
+    ) -> Box<dyn CompactionKeyFilter>;
+}
+
+/// The built-in compaction filters.
+#[derive(Debug, Clone)]
+pub enum CompactionFilter {
+    /// Removes every key starting with the prefix.
+    Prefix(Bytes),
+}
+
+impl CompactionKeyFilter for CompactionFilter {
+    fn filter(
+        &mut self,
+        key: &[u8],
+        _ts: u64,
+        _value: &[u8],
+        _level: usize,
+    ) -> CompactionFilterDecision {
+        match self {
+            CompactionFilter::Prefix(prefix) if key.starts_with(prefix) => {
+                CompactionFilterDecision::Remove
+            }
+            CompactionFilter::Prefix(_) => CompactionFilterDecision::Keep,
+        }
+    }
+}
+
+impl CompactionFilterFactory for CompactionFilter {
+    fn create_compaction_filter(
+        &self,
+        _context: &CompactionFilterContext,
+    ) -> Box<dyn CompactionKeyFilter> {
+        Box::new(self.clone())
+    }
+}
@@ -1,7 +1,9 @@
 use std::collections::HashSet;
+use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 
+use super::{can_move_trivially, CompactionTriggerOptions};
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,9 +14,12 @@ pub struct LeveledCompactionTask {
     pub lower_level: usize,
     pub lower_level_sst_ids: Vec<usize>,
     pub is_lower_level_bottom_level: bool,
+    // move the upper level SSTs to the lower level without rewriting them
+    #[serde(default)]
+    pub is_trivial_move: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionOptions {
     pub level_size_multiplier: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -24,11 +29,21 @@ pub struct LeveledCompactionOptions {
 
 pub struct LeveledCompactionController {
     options: LeveledCompactionOptions,
+    triggers: CompactionTriggerOptions,
 }
 
 impl LeveledCompactionController {
     pub fn new(options: LeveledCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            triggers: CompactionTriggerOptions::default(),
+        }
+    }
+
+    /// Also compact the SSTs with too many deletes or written too long ago.
+    pub fn with_compaction_triggers(mut self, triggers: CompactionTriggerOptions) -> Self {
+        self.triggers = triggers;
+        self
     }
 
     fn find_overlapping_ssts(
@@ -61,11 +76,8 @@ impl LeveledCompactionController {
         overlap_ssts
     }
 
-    pub fn generate_compaction_task(
-        &self,
-        snapshot: &LsmStorageState,
-    ) -> Option<LeveledCompactionTask> {
-        // step 1: compute target level size
+    /// Compute the target sizes and the real sizes of the levels, and the base level.
+    fn level_sizes(&self, snapshot: &LsmStorageState) -> (Vec<usize>, Vec<usize>, usize) {
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
         let mut real_level_size = Vec::with_capacity(self.options.max_levels);
         let mut base_level = self.options.max_levels;
@@ -93,21 +105,67 @@ impl LeveledCompactionController {
                 base_level = i + 1;
             }
         }
+        (target_level_size, real_level_size, base_level)
+    }
 
-        // Flush L0 SST is the top priority
+    /// Estimate the bytes compaction needs to rewrite to bring the levels back to their targets,
+    /// which are the L0 SSTs and the bytes of each level above the target.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let (target_level_size, real_level_size, _) = self.level_sizes(snapshot);
+        let mut pending_bytes = 0;
         if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
-            println!("flush L0 SST to base level {}", base_level);
-            return Some(LeveledCompactionTask {
-                upper_level: None,
-                upper_level_sst_ids: snapshot.l0_sstables.clone(),
-                lower_level: base_level,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &snapshot.l0_sstables,
-                    base_level,
-                ),
-                is_lower_level_bottom_level: base_level == self.options.max_levels,
-            });
+            pending_bytes += snapshot
+                .l0_sstables
+                .iter()
+                .map(|x| snapshot.sstables[x].table_size())
+                .sum::<u64>();
+        }
+        for (target, real) in target_level_size.iter().zip(real_level_size.iter()) {
+            pending_bytes += real.saturating_sub(*target) as u64;
+        }
+        pending_bytes
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        self.generate_compaction_task_skipping(snapshot, &HashSet::new())
+    }
+
+    /// Generates a compaction task which does not take any SST in `compacting_sstables`, the SSTs
+    /// taken by the running tasks. Only one L0 compaction runs at a time.
+    pub fn generate_compaction_task_skipping(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+    ) -> Option<LeveledCompactionTask> {
+        let compacting = |ids: &[usize]| ids.iter().any(|x| compacting_sstables.contains(x));
+
+        // step 1: compute target level size
+        let (target_level_size, real_level_size, base_level) = self.level_sizes(snapshot);
+
+        // Flush L0 SST is the top priority
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+            && !compacting(&snapshot.l0_sstables)
+        {
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, base_level);
+            if !compacting(&lower_level_sst_ids) {
+                println!("flush L0 SST to base level {}", base_level);
+                return Some(LeveledCompactionTask {
+                    upper_level: None,
+                    upper_level_sst_ids: snapshot.l0_sstables.clone(),
+                    lower_level: base_level,
+                    is_trivial_move: can_move_trivially(
+                        snapshot,
+                        &snapshot.l0_sstables,
+                        &lower_level_sst_ids,
+                    ),
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level: base_level == self.options.max_levels,
+                });
+            }
         }
 
         let mut priorities = Vec::with_capacity(self.options.max_levels);
@@ -119,8 +177,26 @@ impl LeveledCompactionController {
         }
         priorities.sort_by(|a, b| a.partial_cmp(b).unwrap().reverse());
 
-        let priority = priorities.first();
-        if let Some((_, level)) = priority {
+        for (_, level) in &priorities {
+            // select the oldest SST to compact which does not overlap with running tasks
+            let level = *level;
+            let mut candidates = snapshot.levels[level - 1]
+                .1
+                .iter()
+                .copied()
+                .filter(|x| !compacting_sstables.contains(x))
+                .collect::<Vec<_>>();
+            candidates.sort();
+            let Some((selected_sst, lower_level_sst_ids)) =
+                candidates.into_iter().find_map(|selected_sst| {
+                    let lower_level_sst_ids =
+                        self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
+                    (!compacting(&lower_level_sst_ids))
+                        .then_some((selected_sst, lower_level_sst_ids))
+                })
+            else {
+                continue;
+            };
             println!(
                 "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
                 target_level_size
@@ -134,8 +210,6 @@ impl LeveledCompactionController {
                 base_level,
             );
 
-            let level = *level;
-            let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
             println!(
                 "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
                 priorities
@@ -144,14 +218,99 @@ impl LeveledCompactionController {
                 upper_level: Some(level),
                 upper_level_sst_ids: vec![selected_sst],
                 lower_level: level + 1,
-                lower_level_sst_ids: self.find_overlapping_ssts(
+                is_trivial_move: can_move_trivially(
                     snapshot,
                     &[selected_sst],
-                    level + 1,
+                    &lower_level_sst_ids,
                 ),
+                lower_level_sst_ids,
                 is_lower_level_bottom_level: level + 1 == self.options.max_levels,
             });
         }
+        self.generate_triggered_task(snapshot, compacting_sstables, base_level, SystemTime::now())
+    }
+
+    /// Generate a task for the oldest SST selected by the compaction triggers, from the top level.
+    /// An L0 SST moves all L0 SSTs to the base level, an SST in the bottom level is rewritten in
+    /// place, and an SST in another level is merged into the next level.
+    fn generate_triggered_task(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+        base_level: usize,
+        now: SystemTime,
+    ) -> Option<LeveledCompactionTask> {
+        if !self.triggers.is_enabled() {
+            return None;
+        }
+        let compacting = |ids: &[usize]| ids.iter().any(|x| compacting_sstables.contains(x));
+        let is_triggered = |id: &usize, is_bottom_level| {
+            self.triggers
+                .is_triggered(&snapshot.sstables[id], is_bottom_level, now)
+        };
+
+        if !snapshot.l0_sstables.is_empty()
+            && !compacting(&snapshot.l0_sstables)
+            && snapshot
+                .l0_sstables
+                .iter()
+                .any(|id| is_triggered(id, false))
+        {
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, base_level);
+            if !compacting(&lower_level_sst_ids) {
+                println!(
+                    "L0 compaction to base level {} triggered by SST",
+                    base_level
+                );
+                return Some(LeveledCompactionTask {
+                    upper_level: None,
+                    upper_level_sst_ids: snapshot.l0_sstables.clone(),
+                    lower_level: base_level,
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level: base_level == self.options.max_levels,
+                    is_trivial_move: false,
+                });
+            }
+        }
+
+        for level in 1..=self.options.max_levels {
+            let is_bottom_level = level == self.options.max_levels;
+            let mut candidates = snapshot.levels[level - 1]
+                .1
+                .iter()
+                .copied()
+                .filter(|x| !compacting_sstables.contains(x))
+                .filter(|x| is_triggered(x, is_bottom_level))
+                .collect::<Vec<_>>();
+            candidates.sort();
+            for selected_sst in candidates {
+                if is_bottom_level {
+                    println!("compaction triggered by SST {selected_sst} in bottom level {level}");
+                    return Some(LeveledCompactionTask {
+                        upper_level: Some(level),
+                        upper_level_sst_ids: vec![selected_sst],
+                        lower_level: level,
+                        lower_level_sst_ids: Vec::new(),
+                        is_lower_level_bottom_level: true,
+                        is_trivial_move: false,
+                    });
+                }
+                let lower_level_sst_ids =
+                    self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
+                if !compacting(&lower_level_sst_ids) {
+                    println!("compaction triggered by SST {selected_sst} in level {level}");
+                    return Some(LeveledCompactionTask {
+                        upper_level: Some(level),
+                        upper_level_sst_ids: vec![selected_sst],
+                        lower_level: level + 1,
+                        lower_level_sst_ids,
+                        is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+                        is_trivial_move: false,
+                    });
+                }
+            }
+        }
         None
     }
 
@@ -201,7 +360,10 @@ impl LeveledCompactionController {
             snapshot.l0_sstables = new_l0_ssts;
         }
 
-        files_to_remove.extend(&task.upper_level_sst_ids);
+        // the moved SSTs are kept
+        if !task.is_trivial_move {
+            files_to_remove.extend(&task.upper_level_sst_ids);
+        }
         files_to_remove.extend(&task.lower_level_sst_ids);
 
         let mut new_lower_level_ssts = snapshot.levels[task.lower_level - 1]
@@ -216,14 +378,21 @@ impl LeveledCompactionController {
             .collect::<Vec<_>>();
         assert!(lower_level_sst_ids_set.is_empty());
         new_lower_level_ssts.extend(output);
-        new_lower_level_ssts.sort_by(|x, y| {
-            snapshot
-                .sstables
-                .get(x)
-                .unwrap()
-                .first_key()
-                .cmp(snapshot.sstables.get(y).unwrap().first_key())
-        });
+        // the SSTs are not opened yet when replaying the manifest, the levels are sorted once they
+        // are
+        if new_lower_level_ssts
+            .iter()
+            .all(|id| snapshot.sstables.contains_key(id))
+        {
+            new_lower_level_ssts.sort_by(|x, y| {
+                snapshot
+                    .sstables
+                    .get(x)
+                    .unwrap()
+                    .first_key()
+                    .cmp(snapshot.sstables.get(y).unwrap().first_key())
+            });
+        }
         snapshot.levels[task.lower_level - 1].1 = new_lower_level_ssts;
         (snapshot, files_to_remove)
     }
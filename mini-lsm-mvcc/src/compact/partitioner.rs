@@ -0,0 +1,28 @@
+use std::fmt::Debug;
+
+/// Chooses key boundaries which no output SST of a compaction crosses, such as the boundaries of
+/// tenant prefixes, so that the SSTs of different partitions never overlap.
+pub trait SstPartitioner: Debug + Send + Sync {
+    /// Whether an SST ending at `last_key` must not take `key`, the next user key written by the
+    /// compaction.
+    fn should_partition(&self, last_key: &[u8], key: &[u8]) -> bool;
+}
+
+/// Cuts the SSTs between keys with different prefixes of a fixed length.
+#[derive(Debug, Clone)]
+pub struct FixedPrefixSstPartitioner {
+    prefix_len: usize,
+}
+
+impl FixedPrefixSstPartitioner {
+    pub fn new(prefix_len: usize) -> Self {
+        Self { prefix_len }
+    }
+}
+
+impl SstPartitioner for FixedPrefixSstPartitioner {
+    fn should_partition(&self, last_key: &[u8], key: &[u8]) -> bool {
+        let prefix_len = self.prefix_len;
+        last_key[..last_key.len().min(prefix_len)] != key[..key.len().min(prefix_len)]
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::lsm_storage::LsmStorageState;
+
+/// A manual compaction of every SST overlapping a key range, created by
+/// `MiniLsm::compact_range`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeCompactionTask {
+    /// The L0 SSTs overlapping the range.
+    pub l0_sst_ids: Vec<usize>,
+    /// The SSTs overlapping the range in each level or tier, by level number or tier id, from the
+    /// newest to the oldest.
+    pub levels: Vec<(usize, Vec<usize>)>,
+    /// The level receiving the output. `None` with tiered compaction, where the output becomes a
+    /// new tier in place of the oldest tier compacted.
+    pub target_level: Option<usize>,
+    pub is_target_level_bottom_level: bool,
+    /// The SSTs of the target level, or of the level above if the target level is empty, which
+    /// are not compacted but held from other tasks, so that no other task changes the target level
+    /// while the output joins it.
+    #[serde(default)]
+    pub held_sst_ids: Vec<usize>,
+}
+
+/// The key range covered by the SSTs selected so far, which grows as more SSTs are selected.
+struct KeyRange {
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+}
+
+impl KeyRange {
+    fn overlaps(&self, first_key: &[u8], last_key: &[u8]) -> bool {
+        let above_lower = match &self.lower {
+            Bound::Included(lower) => last_key >= lower.as_slice(),
+            Bound::Excluded(lower) => last_key > lower.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let below_upper = match &self.upper {
+            Bound::Included(upper) => first_key <= upper.as_slice(),
+            Bound::Excluded(upper) => first_key < upper.as_slice(),
+            Bound::Unbounded => true,
+        };
+        above_lower && below_upper
+    }
+
+    fn extend(&mut self, first_key: &[u8], last_key: &[u8]) {
+        match &self.lower {
+            Bound::Included(lower) | Bound::Excluded(lower) if first_key > lower.as_slice() => {}
+            Bound::Unbounded => {}
+            _ => self.lower = Bound::Included(first_key.to_vec()),
+        }
+        match &self.upper {
+            Bound::Included(upper) | Bound::Excluded(upper) if last_key < upper.as_slice() => {}
+            Bound::Unbounded => {}
+            _ => self.upper = Bound::Included(last_key.to_vec()),
+        }
+    }
+
+    fn overlaps_any(&self, snapshot: &LsmStorageState, ids: &[usize]) -> bool {
+        ids.iter().any(|id| {
+            let table = &snapshot.sstables[id];
+            self.overlaps(table.first_key().key_ref(), table.last_key().key_ref())
+        })
+    }
+
+    /// Select the SSTs of `ids` overlapping the range and extend the range to cover them.
+    fn select(&mut self, snapshot: &LsmStorageState, ids: &[usize]) -> Vec<usize> {
+        let mut selected = Vec::new();
+        for id in ids {
+            let table = &snapshot.sstables[id];
+            let (first_key, last_key) = (table.first_key().key_ref(), table.last_key().key_ref());
+            if self.overlaps(first_key, last_key) {
+                self.extend(first_key, last_key);
+                selected.push(*id);
+            }
+        }
+        selected
+    }
+}
+
+impl RangeCompactionTask {
+    /// Create a task compacting the SSTs overlapping `[lower, upper]` on every level above and
+    /// including `target_level` into `target_level`, which defaults to the bottom level. SSTs
+    /// overlapping the range only partially widen the range, so that the output never overlaps
+    /// the SSTs left in the target level. Returns `None` if no SST overlaps the range.
+    pub fn generate_leveled(
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        target_level: Option<usize>,
+    ) -> Result<Option<Self>> {
+        let target_level = target_level.unwrap_or(snapshot.levels.len());
+        if target_level == 0 || target_level > snapshot.levels.len() {
+            bail!(
+                "target level {} is not in 1..={}",
+                target_level,
+                snapshot.levels.len()
+            );
+        }
+        let mut range = KeyRange {
+            lower: lower.map(|x| x.to_vec()),
+            upper: upper.map(|x| x.to_vec()),
+        };
+        // L0 SSTs overlap with each other, so select until no more L0 SST overlaps the range
+        let mut l0_sst_ids = Vec::new();
+        loop {
+            let remaining = snapshot
+                .l0_sstables
+                .iter()
+                .filter(|id| !l0_sst_ids.contains(*id))
+                .copied()
+                .collect::<Vec<_>>();
+            let selected = range.select(snapshot, &remaining);
+            if selected.is_empty() {
+                break;
+            }
+            l0_sst_ids.extend(selected);
+        }
+        // keep the L0 SSTs from the newest to the oldest
+        let l0_sst_ids = snapshot
+            .l0_sstables
+            .iter()
+            .filter(|id| l0_sst_ids.contains(*id))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut levels = Vec::new();
+        for (level, ids) in &snapshot.levels[..target_level] {
+            let selected = range.select(snapshot, ids);
+            if !selected.is_empty() {
+                levels.push((*level, selected));
+            }
+        }
+        if l0_sst_ids.is_empty() && levels.is_empty() {
+            return Ok(None);
+        }
+        let is_target_level_bottom_level = snapshot.levels[target_level..]
+            .iter()
+            .all(|(_, ids)| !range.overlaps_any(snapshot, ids));
+        // an empty level is only filled by the tasks taking the level above
+        let mut held_sst_ids = snapshot.levels[target_level - 1].1.clone();
+        if held_sst_ids.is_empty() {
+            held_sst_ids = if target_level == 1 {
+                snapshot.l0_sstables.clone()
+            } else {
+                snapshot.levels[target_level - 2].1.clone()
+            };
+        }
+        Ok(Some(Self {
+            l0_sst_ids,
+            levels,
+            target_level: Some(target_level),
+            is_target_level_bottom_level,
+            held_sst_ids,
+        }))
+    }
+
+    /// Create a task compacting the SSTs overlapping `[lower, upper]` in every tier into a new
+    /// tier. Returns `None` if no SST overlaps the range.
+    pub fn generate_tiered(
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<Self> {
+        let mut range = KeyRange {
+            lower: lower.map(|x| x.to_vec()),
+            upper: upper.map(|x| x.to_vec()),
+        };
+        // an SST selected in an older tier may widen the range over more SSTs of newer tiers
+        let mut selected = vec![Vec::new(); snapshot.levels.len()];
+        loop {
+            let mut changed = false;
+            for ((_, ids), selected) in snapshot.levels.iter().zip(selected.iter_mut()) {
+                let remaining = ids
+                    .iter()
+                    .filter(|id| !selected.contains(*id))
+                    .copied()
+                    .collect::<Vec<_>>();
+                let newly_selected = range.select(snapshot, &remaining);
+                if !newly_selected.is_empty() {
+                    selected.extend(newly_selected);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let levels = snapshot
+            .levels
+            .iter()
+            .zip(selected)
+            .filter(|(_, selected)| !selected.is_empty())
+            .map(|((tier_id, ids), selected)| {
+                let ids = ids
+                    .iter()
+                    .filter(|id| selected.contains(*id))
+                    .copied()
+                    .collect::<Vec<_>>();
+                (*tier_id, ids)
+            })
+            .collect::<Vec<_>>();
+        if levels.is_empty() {
+            return None;
+        }
+        // no SST left in the tiers overlaps the output, so it can drop the deletes
+        Some(Self {
+            l0_sst_ids: Vec::new(),
+            levels,
+            target_level: None,
+            is_target_level_bottom_level: true,
+            held_sst_ids: Vec::new(),
+        })
+    }
+
+    /// Get the ids of all input SSTs.
+    pub fn input_sst_ids(&self) -> Vec<usize> {
+        self.l0_sst_ids
+            .iter()
+            .chain(self.levels.iter().flat_map(|(_, ids)| ids))
+            .copied()
+            .collect()
+    }
+
+    /// Get the ids of all SSTs no other task may compact while the task runs, which are the input
+    /// SSTs and the held SSTs.
+    pub fn compacting_sst_ids(&self) -> Vec<usize> {
+        let mut ids = self.input_sst_ids();
+        for id in &self.held_sst_ids {
+            if !ids.contains(id) {
+                ids.push(*id);
+            }
+        }
+        ids
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let files_to_remove = self.input_sst_ids();
+        let inputs = files_to_remove.iter().copied().collect::<HashSet<_>>();
+        snapshot.l0_sstables.retain(|id| !inputs.contains(id));
+        match self.target_level {
+            Some(target_level) => {
+                for (_, ids) in &mut snapshot.levels {
+                    ids.retain(|id| !inputs.contains(id));
+                }
+                let ssts = &mut snapshot.levels[target_level - 1].1;
+                ssts.extend(output);
+                // the SSTs are not opened yet when replaying the manifest, the levels are sorted
+                // once they are
+                if ssts.iter().all(|id| snapshot.sstables.contains_key(id)) {
+                    ssts.sort_by(|x, y| {
+                        snapshot.sstables[x]
+                            .first_key()
+                            .cmp(snapshot.sstables[y].first_key())
+                    });
+                }
+            }
+            None => {
+                let oldest_tier_id = self.levels.last().unwrap().0;
+                let mut levels = Vec::with_capacity(snapshot.levels.len() + 1);
+                for (tier_id, mut ids) in std::mem::take(&mut snapshot.levels) {
+                    if tier_id == oldest_tier_id && !output.is_empty() {
+                        levels.push((output[0], output.to_vec()));
+                    }
+                    ids.retain(|id| !inputs.contains(id));
+                    if !ids.is_empty() {
+                        levels.push((tier_id, ids));
+                    }
+                }
+                snapshot.levels = levels;
+            }
+        }
+        (snapshot, files_to_remove)
+    }
+}
@@ -1,10 +1,12 @@
 use std::collections::HashSet;
+use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 
+use super::{can_move_trivially, CompactionTriggerOptions};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionOptions {
     pub size_ratio_percent: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -19,15 +21,53 @@ pub struct SimpleLeveledCompactionTask {
     pub lower_level: usize,
     pub lower_level_sst_ids: Vec<usize>,
     pub is_lower_level_bottom_level: bool,
+    // move the upper level SSTs to the lower level without rewriting them
+    #[serde(default)]
+    pub is_trivial_move: bool,
 }
 
 pub struct SimpleLeveledCompactionController {
     options: SimpleLeveledCompactionOptions,
+    triggers: CompactionTriggerOptions,
 }
 
 impl SimpleLeveledCompactionController {
     pub fn new(options: SimpleLeveledCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            triggers: CompactionTriggerOptions::default(),
+        }
+    }
+
+    /// Also compact the levels with an SST with too many deletes or written too long ago.
+    pub fn with_compaction_triggers(mut self, triggers: CompactionTriggerOptions) -> Self {
+        self.triggers = triggers;
+        self
+    }
+
+    /// Estimate the bytes compaction needs to rewrite, which are the SSTs of all pairs of adjacent
+    /// levels violating the size ratio.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let level_bytes = |ids: &[usize]| {
+            ids.iter()
+                .map(|x| snapshot.sstables[x].table_size())
+                .sum::<u64>()
+        };
+        let mut pending_bytes = 0;
+        let mut upper_ids = &snapshot.l0_sstables;
+        for (i, (_, lower_ids)) in snapshot.levels.iter().enumerate() {
+            let triggered = if i == 0 {
+                upper_ids.len() >= self.options.level0_file_num_compaction_trigger
+            } else {
+                !upper_ids.is_empty()
+            };
+            let size_ratio = lower_ids.len() as f64 / upper_ids.len() as f64;
+            if triggered && size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
+                pending_bytes += level_bytes(upper_ids) + level_bytes(lower_ids);
+            }
+            upper_ids = lower_ids;
+        }
+        pending_bytes
     }
 
     /// Generates a compaction task.
@@ -37,6 +77,17 @@ impl SimpleLeveledCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<SimpleLeveledCompactionTask> {
+        self.generate_compaction_task_skipping(snapshot, &HashSet::new())
+    }
+
+    /// Generates a compaction task next to the running tasks. Levels with SSTs in
+    /// `compacting_sstables` are skipped, and only one L0 compaction runs at a time.
+    pub fn generate_compaction_task_skipping(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        let compacting = |ids: &[usize]| ids.iter().any(|x| compacting_sstables.contains(x));
         let mut level_sizes = Vec::new();
         level_sizes.push(snapshot.l0_sstables.len());
         for (_, files) in &snapshot.levels {
@@ -53,23 +104,88 @@ impl SimpleLeveledCompactionController {
             let lower_level = i + 1;
             let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
             if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
+                let upper_level_sst_ids = if i == 0 {
+                    &snapshot.l0_sstables
+                } else {
+                    &snapshot.levels[i - 1].1
+                };
+                let lower_level_sst_ids = &snapshot.levels[lower_level - 1].1;
+                if compacting(upper_level_sst_ids) || compacting(lower_level_sst_ids) {
+                    continue;
+                }
                 println!(
                     "compaction triggered at level {} and {} with size ratio {}",
                     i, lower_level, size_ratio
                 );
                 return Some(SimpleLeveledCompactionTask {
                     upper_level: if i == 0 { None } else { Some(i) },
-                    upper_level_sst_ids: if i == 0 {
-                        snapshot.l0_sstables.clone()
-                    } else {
-                        snapshot.levels[i - 1].1.clone()
-                    },
+                    upper_level_sst_ids: upper_level_sst_ids.clone(),
                     lower_level,
-                    lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
+                    lower_level_sst_ids: lower_level_sst_ids.clone(),
                     is_lower_level_bottom_level: lower_level == self.options.max_levels,
+                    is_trivial_move: can_move_trivially(
+                        snapshot,
+                        upper_level_sst_ids,
+                        lower_level_sst_ids,
+                    ),
                 });
             }
         }
+        self.generate_triggered_task(snapshot, compacting_sstables, SystemTime::now())
+    }
+
+    /// Generate a task for the top level with an SST selected by the compaction triggers. The
+    /// level is merged into the next level, or rewritten in place if it is the bottom level.
+    fn generate_triggered_task(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+        now: SystemTime,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        if !self.triggers.is_enabled() {
+            return None;
+        }
+        let compacting = |ids: &[usize]| ids.iter().any(|x| compacting_sstables.contains(x));
+        for i in 0..=self.options.max_levels {
+            let is_bottom_level = i == self.options.max_levels;
+            let upper_level_sst_ids = if i == 0 {
+                &snapshot.l0_sstables
+            } else {
+                &snapshot.levels[i - 1].1
+            };
+            if compacting(upper_level_sst_ids)
+                || !upper_level_sst_ids.iter().any(|id| {
+                    self.triggers
+                        .is_triggered(&snapshot.sstables[id], is_bottom_level, now)
+                })
+            {
+                continue;
+            }
+            if is_bottom_level {
+                println!("compaction triggered by SST in bottom level {}", i);
+                return Some(SimpleLeveledCompactionTask {
+                    upper_level: Some(i),
+                    upper_level_sst_ids: upper_level_sst_ids.clone(),
+                    lower_level: i,
+                    lower_level_sst_ids: Vec::new(),
+                    is_lower_level_bottom_level: true,
+                    is_trivial_move: false,
+                });
+            }
+            let lower_level_sst_ids = &snapshot.levels[i].1;
+            if compacting(lower_level_sst_ids) {
+                continue;
+            }
+            println!("compaction triggered by SST at level {} and {}", i, i + 1);
+            return Some(SimpleLeveledCompactionTask {
+                upper_level: if i == 0 { None } else { Some(i) },
+                upper_level_sst_ids: upper_level_sst_ids.clone(),
+                lower_level: i + 1,
+                lower_level_sst_ids: lower_level_sst_ids.clone(),
+                is_lower_level_bottom_level: i + 1 == self.options.max_levels,
+                is_trivial_move: false,
+            });
+        }
         None
     }
 
@@ -89,15 +205,22 @@ impl SimpleLeveledCompactionController {
         let mut snapshot = snapshot.clone();
         let mut files_to_remove = Vec::new();
         if let Some(upper_level) = task.upper_level {
+            // tasks running at the same time never share a level, so the level is unchanged. The
+            // SSTs moved to a level are only sorted after the manifest is replayed, so the order
+            // is not compared.
             assert_eq!(
-                task.upper_level_sst_ids,
-                snapshot.levels[upper_level - 1].1,
+                sst_id_set(&task.upper_level_sst_ids),
+                sst_id_set(&snapshot.levels[upper_level - 1].1),
                 "sst mismatched"
             );
-            files_to_remove.extend(&snapshot.levels[upper_level - 1].1);
+            if !task.is_trivial_move {
+                files_to_remove.extend(&snapshot.levels[upper_level - 1].1);
+            }
             snapshot.levels[upper_level - 1].1.clear();
         } else {
-            files_to_remove.extend(&task.upper_level_sst_ids);
+            if !task.is_trivial_move {
+                files_to_remove.extend(&task.upper_level_sst_ids);
+            }
             let mut l0_ssts_compacted = task
                 .upper_level_sst_ids
                 .iter()
@@ -113,12 +236,34 @@ impl SimpleLeveledCompactionController {
             snapshot.l0_sstables = new_l0_sstables;
         }
         assert_eq!(
-            task.lower_level_sst_ids,
-            snapshot.levels[task.lower_level - 1].1,
+            sst_id_set(&task.lower_level_sst_ids),
+            sst_id_set(&snapshot.levels[task.lower_level - 1].1),
             "sst mismatched"
         );
-        files_to_remove.extend(&snapshot.levels[task.lower_level - 1].1);
-        snapshot.levels[task.lower_level - 1].1 = output.to_vec();
+        if !task.is_trivial_move {
+            files_to_remove.extend(&snapshot.levels[task.lower_level - 1].1);
+            snapshot.levels[task.lower_level - 1].1 = output.to_vec();
+            return (snapshot, files_to_remove);
+        }
+        // the moved SSTs join the lower level SSTs, which are kept
+        let lower_level_ssts = &mut snapshot.levels[task.lower_level - 1].1;
+        lower_level_ssts.extend(output);
+        // the SSTs are not opened yet when replaying the manifest, the levels are sorted once they
+        // are
+        if lower_level_ssts
+            .iter()
+            .all(|id| snapshot.sstables.contains_key(id))
+        {
+            lower_level_ssts.sort_by(|x, y| {
+                snapshot.sstables[x]
+                    .first_key()
+                    .cmp(snapshot.sstables[y].first_key())
+            });
+        }
         (snapshot, files_to_remove)
     }
 }
+
+fn sst_id_set(ids: &[usize]) -> HashSet<usize> {
+    ids.iter().copied().collect()
+}
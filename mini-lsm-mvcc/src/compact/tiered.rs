@@ -1,16 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 
+use super::CompactionTriggerOptions;
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
+    // merge the SSTs of a key range in every tier into the bottom tier, the tiers only list the
+    // SSTs of the range
+    #[serde(default)]
+    pub is_incremental: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
     pub max_size_amplification_percent: usize,
@@ -18,49 +25,155 @@ pub struct TieredCompactionOptions {
     pub min_merge_width: usize,
 }
 
+/// Bounds on the work of a single tiered compaction.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TieredCompactionLimits {
+    /// Merge at most this many tiers in one compaction, unlimited if `None`.
+    pub max_merge_width: Option<usize>,
+    /// Reduce the space amplification by merging the SSTs of a key range in every tier into the
+    /// bottom tier, reading at most about this many bytes, instead of merging all tiers at once,
+    /// disabled if `None`.
+    pub max_compaction_bytes: Option<u64>,
+}
+
 pub struct TieredCompactionController {
     options: TieredCompactionOptions,
+    limits: TieredCompactionLimits,
+    triggers: CompactionTriggerOptions,
 }
 
 impl TieredCompactionController {
     pub fn new(options: TieredCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            limits: TieredCompactionLimits::default(),
+            triggers: CompactionTriggerOptions::default(),
+        }
+    }
+
+    /// Bound the tiers and the bytes merged by one compaction.
+    pub fn with_limits(mut self, limits: TieredCompactionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Also compact the tiers with an SST with too many deletes or written too long ago.
+    pub fn with_compaction_triggers(mut self, triggers: CompactionTriggerOptions) -> Self {
+        self.triggers = triggers;
+        self
+    }
+
+    /// Estimate the bytes compaction needs to rewrite, which are the SSTs of all tiers above the
+    /// bottom tier once the number of tiers reaches `num_tiers`.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        if snapshot.levels.len() < self.options.num_tiers {
+            return 0;
+        }
+        snapshot.levels[..snapshot.levels.len() - 1]
+            .iter()
+            .flat_map(|(_, ids)| ids.iter())
+            .map(|x| snapshot.sstables[x].table_size())
+            .sum()
     }
 
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<TieredCompactionTask> {
+        self.generate_compaction_task_skipping(snapshot, &HashSet::new())
+    }
+
+    /// Generates a compaction task next to the running tasks, which hold the SSTs in
+    /// `compacting_sstables`.
+    pub fn generate_compaction_task_skipping(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
     ) -> Option<TieredCompactionTask> {
         assert!(
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
         );
         if snapshot.levels.len() < self.options.num_tiers {
-            return None;
+            return self.generate_triggered_task(snapshot, compacting_sstables, SystemTime::now());
         }
-        // compaction triggered by space amplification ratio
-        let mut size = 0;
-        for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
+        // a running task takes a run of tiers, only the newer tiers above it can be compacted
+        let free_tiers = snapshot
+            .levels
+            .iter()
+            .take_while(|(_, ids)| !ids.iter().any(|x| compacting_sstables.contains(x)))
+            .count();
+        if free_tiers < snapshot.levels.len() {
+            return self.generate_compaction_task_above_running_task(snapshot, free_tiers);
         }
-        let space_amp_ratio =
-            (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
+        let tier_sizes = tier_sizes(snapshot);
+        // compaction triggered by space amplification ratio
+        let size = tier_sizes[..tier_sizes.len() - 1].iter().sum::<u64>();
+        let space_amp_ratio = (size as f64) / (*tier_sizes.last().unwrap() as f64) * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
             println!(
                 "compaction triggered by space amplification ratio: {}",
                 space_amp_ratio
             );
+            let total_size = size + tier_sizes.last().unwrap();
+            if let Some(max_compaction_bytes) = self.limits.max_compaction_bytes {
+                if total_size > max_compaction_bytes {
+                    if let Some(task) = generate_incremental_task(snapshot, max_compaction_bytes) {
+                        return Some(task);
+                    }
+                }
+            }
+            // merge the oldest tiers into the bottom tier if not all of them can be merged
+            let num_tiers_to_take = self.max_merge_width().min(snapshot.levels.len());
             return Some(TieredCompactionTask {
-                tiers: snapshot.levels.clone(),
+                tiers: snapshot.levels[snapshot.levels.len() - num_tiers_to_take..].to_vec(),
                 bottom_tier_included: true,
+                is_incremental: false,
             });
         }
+        if let Some(task) = self.generate_size_ratio_task(snapshot, &tier_sizes, tier_sizes.len()) {
+            return Some(task);
+        }
+        // reducing the sorted runs only merges the newest tiers, which may not reach the SST
+        // selected by the compaction triggers
+        if let Some(task) =
+            self.generate_triggered_task(snapshot, compacting_sstables, SystemTime::now())
+        {
+            return Some(task);
+        }
+        // trying to reduce sorted runs without respecting size ratio
+        let num_tiers_to_take = (snapshot.levels.len() - self.options.num_tiers + 2)
+            .min(self.max_merge_width())
+            .max(2)
+            .min(snapshot.levels.len());
+        println!("compaction triggered by reducing sorted runs");
+        Some(TieredCompactionTask {
+            tiers: snapshot.levels[..num_tiers_to_take].to_vec(),
+            bottom_tier_included: num_tiers_to_take >= snapshot.levels.len(),
+            is_incremental: false,
+        })
+    }
+
+    fn max_merge_width(&self) -> usize {
+        self.limits.max_merge_width.unwrap_or(usize::MAX)
+    }
+
+    /// Generate a task merging the newest tiers up to the first tier whose size is within the size
+    /// ratio of the total size of the newer tiers, taking at most the first `num_tiers` tiers.
+    fn generate_size_ratio_task(
+        &self,
+        snapshot: &LsmStorageState,
+        tier_sizes: &[u64],
+        num_tiers: usize,
+    ) -> Option<TieredCompactionTask> {
         let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
-        // compaction triggered by size ratio
         let mut size = 0;
-        for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-            let next_level_size = snapshot.levels[id + 1].1.len();
+        for id in 0..num_tiers.saturating_sub(1) {
+            if id + 2 > self.max_merge_width() {
+                break;
+            }
+            size += tier_sizes[id];
+            let next_level_size = tier_sizes[id + 1];
             let current_size_ratio = size as f64 / next_level_size as f64;
             if current_size_ratio >= size_ratio_trigger && id + 2 >= self.options.min_merge_width {
                 println!(
@@ -68,28 +181,81 @@ impl TieredCompactionController {
                     current_size_ratio * 100.0
                 );
                 return Some(TieredCompactionTask {
-                    tiers: snapshot
-                        .levels
-                        .iter()
-                        .take(id + 2)
-                        .cloned()
-                        .collect::<Vec<_>>(),
+                    tiers: snapshot.levels[..id + 2].to_vec(),
                     bottom_tier_included: id + 2 >= snapshot.levels.len(),
+                    is_incremental: false,
                 });
             }
         }
-        // trying to reduce sorted runs without respecting size ratio
-        let num_tiers_to_take = snapshot.levels.len() - self.options.num_tiers + 2;
+        None
+    }
+
+    /// Generate a task for the newest tier with an SST selected by the compaction triggers, which
+    /// merges the tier and all older tiers, so that the deletes and the versions below the
+    /// watermark are removed.
+    fn generate_triggered_task(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+        now: SystemTime,
+    ) -> Option<TieredCompactionTask> {
+        if !self.triggers.is_enabled() {
+            return None;
+        }
+        let bottom_tier = snapshot.levels.len().checked_sub(1)?;
+        let idx = snapshot
+            .levels
+            .iter()
+            .enumerate()
+            .position(|(idx, (_, ids))| {
+                ids.iter().any(|id| {
+                    self.triggers
+                        .is_triggered(&snapshot.sstables[id], idx == bottom_tier, now)
+                })
+            })?;
+        let tiers = &snapshot.levels[idx..];
+        if tiers
+            .iter()
+            .flat_map(|(_, ids)| ids)
+            .any(|x| compacting_sstables.contains(x))
+        {
+            return None;
+        }
+        println!(
+            "compaction triggered by SST in tier {}: {} tiers",
+            snapshot.levels[idx].0,
+            tiers.len()
+        );
+        Some(TieredCompactionTask {
+            tiers: tiers.to_vec(),
+            bottom_tier_included: true,
+            is_incremental: false,
+        })
+    }
+
+    /// Generate a task from the first `free_tiers` tiers, which are newer than the tiers taken by
+    /// a running task. The bottom tier is never included.
+    fn generate_compaction_task_above_running_task(
+        &self,
+        snapshot: &LsmStorageState,
+        free_tiers: usize,
+    ) -> Option<TieredCompactionTask> {
+        let tier_sizes = tier_sizes(snapshot);
+        if let Some(task) = self.generate_size_ratio_task(snapshot, &tier_sizes, free_tiers) {
+            return Some(task);
+        }
+        let num_tiers_to_take = (snapshot.levels.len() - self.options.num_tiers + 2)
+            .min(free_tiers)
+            .min(self.max_merge_width());
+        if num_tiers_to_take < 2 {
+            return None;
+        }
         println!("compaction triggered by reducing sorted runs");
-        return Some(TieredCompactionTask {
-            tiers: snapshot
-                .levels
-                .iter()
-                .take(num_tiers_to_take)
-                .cloned()
-                .collect::<Vec<_>>(),
-            bottom_tier_included: snapshot.levels.len() >= num_tiers_to_take,
-        });
+        Some(TieredCompactionTask {
+            tiers: snapshot.levels[..num_tiers_to_take].to_vec(),
+            bottom_tier_included: false,
+            is_incremental: false,
+        })
     }
 
     pub fn apply_compaction_result(
@@ -102,6 +268,9 @@ impl TieredCompactionController {
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
         );
+        if task.is_incremental {
+            return apply_incremental_result(snapshot, task, output);
+        }
         let mut snapshot = snapshot.clone();
         let mut tier_to_remove = task
             .tiers
@@ -121,9 +290,11 @@ impl TieredCompactionController {
                 levels.push((*tier_id, files.clone()));
             }
             if tier_to_remove.is_empty() && !new_tier_added {
-                // add the compacted tier to the LSM tree
+                // add the compacted tier to the LSM tree, unless everything in it was deleted
                 new_tier_added = true;
-                levels.push((output[0], output.to_vec()));
+                if !output.is_empty() {
+                    levels.push((output[0], output.to_vec()));
+                }
             }
         }
         if !tier_to_remove.is_empty() {
@@ -133,3 +304,178 @@ impl TieredCompactionController {
         (snapshot, files_to_remove)
     }
 }
+
+/// The total size of the SSTs in each tier, or the number of SSTs if the snapshot has no SST
+/// objects, as in the compaction simulator.
+fn tier_sizes(snapshot: &LsmStorageState) -> Vec<u64> {
+    if snapshot.sstables.is_empty() {
+        return snapshot
+            .levels
+            .iter()
+            .map(|(_, ids)| ids.len() as u64)
+            .collect();
+    }
+    snapshot
+        .levels
+        .iter()
+        .map(|(_, ids)| ids.iter().map(|x| snapshot.sstables[x].table_size()).sum())
+        .collect()
+}
+
+/// Split the SSTs of all tiers into groups by key range, such that no SST overlaps the SSTs of
+/// another group and each group has an SST of the bottom tier. Returns the group of each SST and
+/// the bytes of the newer tiers and of the bottom tier in each group, in key order.
+fn incremental_groups(snapshot: &LsmStorageState) -> (HashMap<usize, usize>, Vec<(u64, u64)>) {
+    let bottom_tier = snapshot.levels.len() - 1;
+    let mut tables = snapshot
+        .levels
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, (_, ids))| ids.iter().map(move |id| (idx == bottom_tier, *id)))
+        .map(|(is_bottom, id)| (is_bottom, &snapshot.sstables[&id]))
+        .collect::<Vec<_>>();
+    tables.sort_by(|(_, a), (_, b)| a.first_key().key_ref().cmp(b.first_key().key_ref()));
+    let mut group_of = HashMap::with_capacity(tables.len());
+    let mut groups: Vec<(u64, u64)> = Vec::new();
+    let mut group_last_key: Option<&[u8]> = None;
+    let mut group_has_bottom = false;
+    for (is_bottom, table) in tables {
+        // a group without an SST of the bottom tier goes on with the next SSTs, as its output
+        // could not take a place in the bottom tier
+        let overlaps = group_last_key.is_some_and(|key| table.first_key().key_ref() <= key);
+        if groups.is_empty() || (!overlaps && group_has_bottom) {
+            groups.push((0, 0));
+            group_has_bottom = false;
+        }
+        let group = groups.last_mut().unwrap();
+        if is_bottom {
+            group.1 += table.table_size();
+            group_has_bottom = true;
+        } else {
+            group.0 += table.table_size();
+        }
+        let last_key = table.last_key().key_ref();
+        group_last_key = Some(group_last_key.map_or(last_key, |key| key.max(last_key)));
+        group_of.insert(table.sst_id(), groups.len() - 1);
+    }
+    // the SSTs after the last SST of the bottom tier join the last group with one
+    if !group_has_bottom && groups.len() > 1 {
+        let (upper_size, _) = groups.pop().unwrap();
+        groups.last_mut().unwrap().0 += upper_size;
+        let last_group = groups.len();
+        for group in group_of.values_mut() {
+            if *group == last_group {
+                *group = last_group - 1;
+            }
+        }
+    }
+    (group_of, groups)
+}
+
+/// Generate a task merging the SSTs of a key range in every tier into the bottom tier, which reads
+/// at most `max_compaction_bytes` unless a single group of overlapping SSTs exceeds it. For each
+/// group, the range extends over the following groups while it fits the limit, and the range
+/// removing the most bytes of the newer tiers per byte read is chosen. Returns `None` if no range
+/// has SSTs in the newer tiers.
+fn generate_incremental_task(
+    snapshot: &LsmStorageState,
+    max_compaction_bytes: u64,
+) -> Option<TieredCompactionTask> {
+    if snapshot.levels.is_empty() {
+        return None;
+    }
+    let (group_of, groups) = incremental_groups(snapshot);
+    // the best range of groups so far, by the ratio of the bytes of the newer tiers to all bytes
+    // read
+    let mut best_ratio = 0.0;
+    let mut best_groups = 0..0;
+    let mut consider = |upper_size: u64, bottom_size: u64, range: Range<usize>| {
+        let ratio = upper_size as f64 / (upper_size + bottom_size) as f64;
+        if ratio > best_ratio {
+            best_ratio = ratio;
+            best_groups = range;
+        }
+    };
+    // every range is considered as it grows and as it shrinks from the front, so that the end of
+    // the range only moves forward
+    let (mut upper_size, mut bottom_size) = (0, 0);
+    let mut end = 0;
+    for begin in 0..groups.len() {
+        if end > begin {
+            consider(upper_size, bottom_size, begin..end);
+        }
+        while end < groups.len()
+            && (end == begin
+                || upper_size + bottom_size + groups[end].0 + groups[end].1 <= max_compaction_bytes)
+        {
+            upper_size += groups[end].0;
+            bottom_size += groups[end].1;
+            end += 1;
+            consider(upper_size, bottom_size, begin..end);
+        }
+        upper_size -= groups[begin].0;
+        bottom_size -= groups[begin].1;
+    }
+    if best_groups.is_empty() {
+        return None;
+    }
+    let tiers = snapshot
+        .levels
+        .iter()
+        .map(|(tier_id, ids)| {
+            let ids = ids
+                .iter()
+                .filter(|id| best_groups.contains(&group_of[*id]))
+                .copied()
+                .collect::<Vec<_>>();
+            (*tier_id, ids)
+        })
+        .filter(|(_, ids)| !ids.is_empty())
+        .collect::<Vec<_>>();
+    println!(
+        "incremental compaction of {} SSTs in {} tiers",
+        tiers.iter().map(|(_, ids)| ids.len()).sum::<usize>(),
+        tiers.len()
+    );
+    Some(TieredCompactionTask {
+        tiers,
+        bottom_tier_included: true,
+        is_incremental: true,
+    })
+}
+
+/// Replace the SSTs of an incremental task in the bottom tier with the output, and remove them
+/// from the newer tiers. No SST left in any tier overlaps the output, which keeps the bottom tier
+/// sorted.
+fn apply_incremental_result(
+    snapshot: &LsmStorageState,
+    task: &TieredCompactionTask,
+    output: &[usize],
+) -> (LsmStorageState, Vec<usize>) {
+    let mut snapshot = snapshot.clone();
+    let (bottom_tier_id, _) = task.tiers.last().unwrap();
+    let files_to_remove = task
+        .tiers
+        .iter()
+        .flat_map(|(_, ids)| ids.iter().copied())
+        .collect::<Vec<_>>();
+    let inputs = files_to_remove.iter().copied().collect::<HashSet<_>>();
+    let mut levels = Vec::with_capacity(snapshot.levels.len());
+    for (tier_id, mut ids) in std::mem::take(&mut snapshot.levels) {
+        if tier_id == *bottom_tier_id {
+            let pos = ids
+                .iter()
+                .position(|id| inputs.contains(id))
+                .expect("bottom tier SSTs not found");
+            ids.retain(|id| !inputs.contains(id));
+            ids.splice(pos..pos, output.iter().copied());
+        } else {
+            ids.retain(|id| !inputs.contains(id));
+        }
+        if !ids.is_empty() {
+            levels.push((tier_id, ids));
+        }
+    }
+    snapshot.levels = levels;
+    (snapshot, files_to_remove)
+}
@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsm_storage::LsmStorageState;
+use crate::table::SsTable;
+
+/// Extracts the time of the data from a user key, for keys which carry their own timestamp.
+pub trait KeyTimeExtractor: Send + Sync {
+    fn extract_time(&self, key: &[u8]) -> u64;
+}
+
+impl<F: Fn(&[u8]) -> u64 + Send + Sync> KeyTimeExtractor for F {
+    fn extract_time(&self, key: &[u8]) -> u64 {
+        self(key)
+    }
+}
+
+/// Where the time of the data in an SST comes from.
+#[derive(Clone, Default)]
+pub enum TimeSource {
+    /// The largest commit timestamp in the SST.
+    #[default]
+    MaxTs,
+    /// The larger time extracted from the first and the last key of the SST, which is the largest
+    /// time in the SST as long as the keys are ordered by time.
+    Key(Arc<dyn KeyTimeExtractor>),
+}
+
+impl Debug for TimeSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MaxTs => write!(f, "MaxTs"),
+            Self::Key(_) => write!(f, "Key"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeWindowCompactionOptions {
+    /// The length of a time window, in the unit of the time given by `time_source`.
+    pub window_size: u64,
+    /// Not persisted with the other options, as a key time extractor is code.
+    #[serde(skip)]
+    pub time_source: TimeSource,
+    /// Tiers in the active window are of a similar size if the larger one is at most
+    /// `(100 + size_ratio)%` of the smaller one.
+    pub size_ratio: usize,
+    /// Merge the tiers of a similar size in the active window once there are this many of them.
+    pub min_merge_width: usize,
+    /// Drop the tiers of the windows older than the newest `max_windows` windows, disabled if
+    /// `None`.
+    pub max_windows: Option<usize>,
+}
+
+/// A time-window compaction task, which either drops the tiers of expired windows or merges the
+/// tiers of one window into a new tier.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimeWindowCompactionTask {
+    /// The tiers merged into a new tier, all in the same window.
+    pub tiers: Vec<(usize, Vec<usize>)>,
+    /// The tiers of expired windows, removed without being rewritten.
+    pub dropped_tiers: Vec<(usize, Vec<usize>)>,
+    pub bottom_tier_included: bool,
+}
+
+/// Buckets the tiers by the time window of their data, and only merges tiers within the same
+/// window: size-tiered merging in the active (newest) window, and one merge into a single tier
+/// once a window is closed. Closed windows are never merged with each other, so data written with
+/// increasing timestamps is rewritten a bounded number of times.
+pub struct TimeWindowCompactionController {
+    options: TimeWindowCompactionOptions,
+}
+
+impl TimeWindowCompactionController {
+    pub fn new(options: TimeWindowCompactionOptions) -> Self {
+        assert!(options.window_size > 0, "window size must be positive");
+        Self { options }
+    }
+
+    fn table_time(&self, table: &SsTable) -> u64 {
+        match &self.options.time_source {
+            TimeSource::MaxTs => table.max_ts(),
+            TimeSource::Key(extractor) => extractor
+                .extract_time(table.first_key().key_ref())
+                .max(extractor.extract_time(table.last_key().key_ref())),
+        }
+    }
+
+    /// The window of a tier, decided by the newest data in the tier.
+    fn tier_window(&self, snapshot: &LsmStorageState, tier: &[usize]) -> u64 {
+        tier.iter()
+            .map(|id| self.table_time(&snapshot.sstables[id]))
+            .max()
+            .unwrap_or_default()
+            / self.options.window_size
+    }
+
+    fn tier_size(snapshot: &LsmStorageState, tier: &[usize]) -> u64 {
+        tier.iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum()
+    }
+
+    /// Group the indices of the tiers in `snapshot.levels` by window, from the newest tier to the
+    /// oldest tier in each window.
+    fn tiers_by_window(&self, snapshot: &LsmStorageState) -> BTreeMap<u64, Vec<usize>> {
+        let mut windows = BTreeMap::<u64, Vec<usize>>::new();
+        for (idx, (_, tier)) in snapshot.levels.iter().enumerate() {
+            windows
+                .entry(self.tier_window(snapshot, tier))
+                .or_default()
+                .push(idx);
+        }
+        windows
+    }
+
+    /// Find the tiers of a similar size in the active window, from the smallest ones. Returns
+    /// `None` if no group of at least `min_merge_width` tiers is found.
+    fn size_tiered_bucket(
+        &self,
+        snapshot: &LsmStorageState,
+        tiers: &[usize],
+    ) -> Option<Vec<usize>> {
+        let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
+        let mut tiers = tiers
+            .iter()
+            .map(|idx| (Self::tier_size(snapshot, &snapshot.levels[*idx].1), *idx))
+            .collect::<Vec<_>>();
+        tiers.sort();
+        let mut begin = 0;
+        for end in 0..tiers.len() {
+            while tiers[end].0 as f64 > tiers[begin].0.max(1) as f64 * size_ratio_trigger {
+                begin += 1;
+            }
+            if end + 1 - begin >= self.options.min_merge_width.max(2) {
+                let mut bucket = tiers[begin..=end]
+                    .iter()
+                    .map(|(_, idx)| *idx)
+                    .collect::<Vec<_>>();
+                bucket.sort();
+                return Some(bucket);
+            }
+        }
+        None
+    }
+
+    /// Estimate the bytes compaction needs to rewrite, which are the tiers of the closed windows
+    /// with more than one tier, and the tiers of the active window once there are
+    /// `min_merge_width` of them.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let windows = self.tiers_by_window(snapshot);
+        let Some(active_window) = windows.keys().next_back().copied() else {
+            return 0;
+        };
+        windows
+            .iter()
+            .filter(|(window, tiers)| {
+                let expired = self
+                    .options
+                    .max_windows
+                    .is_some_and(|max_windows| **window + max_windows as u64 <= active_window);
+                let threshold = if **window == active_window {
+                    self.options.min_merge_width.max(2)
+                } else {
+                    2
+                };
+                !expired && tiers.len() >= threshold
+            })
+            .flat_map(|(_, tiers)| tiers)
+            .map(|idx| Self::tier_size(snapshot, &snapshot.levels[*idx].1))
+            .sum()
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<TimeWindowCompactionTask> {
+        self.generate_compaction_task_skipping(snapshot, &HashSet::new())
+    }
+
+    /// Returns `None` if no compaction needs to be scheduled. The tiers with SSTs in
+    /// `compacting_sstables`, which other tasks are compacting, are never picked, and a closed
+    /// window is only merged once none of its tiers is being compacted.
+    pub fn generate_compaction_task_skipping(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_sstables: &HashSet<usize>,
+    ) -> Option<TimeWindowCompactionTask> {
+        assert!(
+            snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in time-window compaction"
+        );
+        let is_free = |idx: &usize| {
+            !snapshot.levels[*idx]
+                .1
+                .iter()
+                .any(|x| compacting_sstables.contains(x))
+        };
+        let windows = self.tiers_by_window(snapshot);
+        let active_window = windows.keys().next_back().copied()?;
+
+        // expiring a window drops its tiers without rewriting them
+        if let Some(max_windows) = self.options.max_windows {
+            let mut dropped = windows
+                .range(..(active_window + 1).saturating_sub(max_windows as u64))
+                .flat_map(|(_, tiers)| tiers.iter().copied())
+                .filter(is_free)
+                .collect::<Vec<_>>();
+            dropped.sort();
+            if !dropped.is_empty() {
+                println!(
+                    "time-window compaction triggered by expired windows: {} tiers",
+                    dropped.len()
+                );
+                return Some(self.task(snapshot, &[], &dropped));
+            }
+        }
+
+        let active_tiers = windows[&active_window]
+            .iter()
+            .copied()
+            .filter(is_free)
+            .collect::<Vec<_>>();
+        if let Some(bucket) = self.size_tiered_bucket(snapshot, &active_tiers) {
+            println!(
+                "time-window compaction triggered in active window {}: {} tiers",
+                active_window,
+                bucket.len()
+            );
+            return Some(self.task(snapshot, &bucket, &[]));
+        }
+
+        // merge a closed window into one tier, from the newest closed window
+        for (window, tiers) in windows.range(..active_window).rev() {
+            if tiers.len() >= 2 && tiers.iter().all(is_free) {
+                println!(
+                    "time-window compaction triggered by closed window {}: {} tiers",
+                    window,
+                    tiers.len()
+                );
+                return Some(self.task(snapshot, tiers, &[]));
+            }
+        }
+        None
+    }
+
+    /// Create a task from the indices of the tiers to merge and to drop.
+    fn task(
+        &self,
+        snapshot: &LsmStorageState,
+        merged: &[usize],
+        dropped: &[usize],
+    ) -> TimeWindowCompactionTask {
+        let tiers = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|idx| snapshot.levels[*idx].clone())
+                .collect::<Vec<_>>()
+        };
+        // tombstones can only be removed if no older tier is left out of the task
+        let bottom_tier_included = merged.first().is_some_and(|first| {
+            let merged = merged.iter().collect::<HashSet<_>>();
+            (*first..snapshot.levels.len()).all(|idx| merged.contains(&idx))
+        });
+        TimeWindowCompactionTask {
+            tiers: tiers(merged),
+            dropped_tiers: tiers(dropped),
+            bottom_tier_included,
+        }
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &TimeWindowCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        assert!(
+            snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in time-window compaction"
+        );
+        let mut snapshot = snapshot.clone();
+        let merged = task
+            .tiers
+            .iter()
+            .map(|(x, y)| (*x, y))
+            .collect::<BTreeMap<_, _>>();
+        let dropped = task
+            .dropped_tiers
+            .iter()
+            .map(|(x, y)| (*x, y))
+            .collect::<BTreeMap<_, _>>();
+        // the new tier takes the place of the oldest merged tier
+        let last_merged = snapshot
+            .levels
+            .iter()
+            .rposition(|(tier_id, _)| merged.contains_key(tier_id));
+        let mut levels = Vec::new();
+        let mut files_to_remove = Vec::new();
+        for (idx, (tier_id, files)) in snapshot.levels.iter().enumerate() {
+            if let Some(ffiles) = merged.get(tier_id).or_else(|| dropped.get(tier_id)) {
+                assert_eq!(*ffiles, files, "file changed after issuing compaction task");
+                files_to_remove.extend(files.iter().copied());
+            } else {
+                levels.push((*tier_id, files.clone()));
+            }
+            if Some(idx) == last_merged && !output.is_empty() {
+                levels.push((output[0], output.to_vec()));
+            }
+        }
+        assert_eq!(
+            levels.len() + merged.len() + dropped.len(),
+            snapshot.levels.len() + usize::from(!output.is_empty()),
+            "some tiers not found??"
+        );
+        snapshot.levels = levels;
+        (snapshot, files_to_remove)
+    }
+}
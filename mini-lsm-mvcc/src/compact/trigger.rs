@@ -0,0 +1,45 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::table::SsTable;
+
+/// Compaction triggers for single SSTs, which run after the size-based triggers of the leveled,
+/// simple leveled and tiered compaction find nothing to compact.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompactionTriggerOptions {
+    /// Push an SST down to the next level once more than this fraction of its entries are deletes,
+    /// disabled if `None`. SSTs in the bottom level are left alone, as their deletes are removed
+    /// as soon as they are below the watermark.
+    pub tombstone_ratio: Option<f64>,
+    /// Push an SST down to the next level once it was written longer ago than this, or rewrite it
+    /// in the bottom level to remove the versions below the watermark, disabled if `None`.
+    pub max_sst_age: Option<Duration>,
+}
+
+impl CompactionTriggerOptions {
+    /// Whether any trigger is set. The controllers do not look at the SSTs otherwise.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.tombstone_ratio.is_some() || self.max_sst_age.is_some()
+    }
+
+    /// Whether an SST needs to be compacted at `now`. `is_bottom_level` tells if the SST is in the
+    /// bottom level, where only the age is checked.
+    pub(crate) fn is_triggered(
+        &self,
+        table: &SsTable,
+        is_bottom_level: bool,
+        now: SystemTime,
+    ) -> bool {
+        let too_many_deletes = !is_bottom_level
+            && table.num_entries() > 0
+            && self.tombstone_ratio.is_some_and(|ratio| {
+                table.num_deletes() as f64 / table.num_entries() as f64 > ratio
+            });
+        let too_old = self.max_sst_age.is_some_and(|max_age| {
+            now.duration_since(table.created_at())
+                .is_ok_and(|age| age > max_age)
+        });
+        too_many_deletes || too_old
+    }
+}
@@ -23,4 +23,9 @@ pub trait StorageIterator {
     fn num_active_iterators(&self) -> usize {
         1
     }
+
+    /// Whether the current value is a pointer into the value log instead of the value itself.
+    fn is_value_pointer(&self) -> bool {
+        false
+    }
 }
@@ -1,10 +1,12 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Receiver;
 
 use crate::{
     key::KeySlice,
-    table::{SsTable, SsTableIterator},
+    table::{ReadOptions, SsTable, SsTableIterator},
 };
 
 use super::StorageIterator;
@@ -15,6 +17,9 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    read_options: ReadOptions,
+    /// The iterators of the SSTs from `next_sst_idx` on being opened in the background.
+    prefetch: VecDeque<Receiver<Result<SsTableIterator>>>,
 }
 
 impl SstConcatIterator {
@@ -30,26 +35,47 @@ impl SstConcatIterator {
     }
 
     pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_and_seek_to_first_with_options(sstables, ReadOptions::default())
+    }
+
+    pub fn create_and_seek_to_first_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        read_options: ReadOptions,
+    ) -> Result<Self> {
         Self::check_sst_valid(&sstables);
         if sstables.is_empty() {
             return Ok(Self {
                 current: None,
                 next_sst_idx: 0,
                 sstables,
+                read_options,
+                prefetch: VecDeque::new(),
             });
         }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_first(
+            current: Some(SsTableIterator::create_and_seek_to_first_with_options(
                 sstables[0].clone(),
+                read_options.clone(),
             )?),
             next_sst_idx: 1,
             sstables,
+            read_options,
+            prefetch: VecDeque::new(),
         };
+        iter.start_prefetch();
         iter.move_until_valid()?;
         Ok(iter)
     }
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_to_key_with_options(sstables, key, ReadOptions::default())
+    }
+
+    pub fn create_and_seek_to_key_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        read_options: ReadOptions,
+    ) -> Result<Self> {
         Self::check_sst_valid(&sstables);
         let idx: usize = sstables
             .partition_point(|table| table.first_key().as_key_slice() <= key)
@@ -59,20 +85,55 @@ impl SstConcatIterator {
                 current: None,
                 next_sst_idx: sstables.len(),
                 sstables,
+                read_options,
+                prefetch: VecDeque::new(),
             });
         }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
+            current: Some(SsTableIterator::create_and_seek_to_key_with_options(
                 sstables[idx].clone(),
                 key,
+                read_options.clone(),
             )?),
             next_sst_idx: idx + 1,
             sstables,
+            read_options,
+            prefetch: VecDeque::new(),
         };
+        iter.start_prefetch();
         iter.move_until_valid()?;
         Ok(iter)
     }
 
+    /// Start opening the next `prefetch_tables` SSTs from `next_sst_idx` on the prefetch pool, if
+    /// they are not being opened already.
+    fn start_prefetch(&mut self) {
+        let Some(pool) = &self.read_options.prefetch_pool else {
+            return;
+        };
+        while self.prefetch.len() < self.read_options.prefetch_tables
+            && self.next_sst_idx + self.prefetch.len() < self.sstables.len()
+        {
+            let table = self.sstables[self.next_sst_idx + self.prefetch.len()].clone();
+            let read_options = self.read_options.clone();
+            self.prefetch.push_back(pool.spawn(move || {
+                SsTableIterator::create_and_seek_to_first_with_options(table, read_options)
+            }));
+        }
+    }
+
+    fn open_next_table(&mut self) -> Result<SsTableIterator> {
+        match self.prefetch.pop_front() {
+            Some(rx) => rx
+                .recv()
+                .map_err(|_| anyhow!("failed to prefetch the next SST"))?,
+            None => SsTableIterator::create_and_seek_to_first_with_options(
+                self.sstables[self.next_sst_idx].clone(),
+                self.read_options.clone(),
+            ),
+        }
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
@@ -81,10 +142,9 @@ impl SstConcatIterator {
             if self.next_sst_idx >= self.sstables.len() {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
-                    self.sstables[self.next_sst_idx].clone(),
-                )?);
+                self.current = Some(self.open_next_table()?);
                 self.next_sst_idx += 1;
+                self.start_prefetch();
             }
         }
         Ok(())
@@ -102,6 +162,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.current.as_ref().unwrap().is_value_pointer()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
@@ -90,6 +90,10 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         self.current.as_ref().unwrap().1.value()
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.current.as_ref().unwrap().1.is_value_pointer()
+    }
+
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
@@ -1,4 +1,6 @@
+pub mod background;
 pub mod block;
+pub mod block_cache;
 pub mod compact;
 pub mod debug;
 pub mod iterators;
@@ -8,8 +10,13 @@ pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
 pub mod mvcc;
+pub mod prefetch;
+pub mod rate_limiter;
 pub mod table;
+pub mod value_log;
 pub mod wal;
+pub mod write_buffer_manager;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
@@ -1,4 +1,5 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use bytes::Bytes;
@@ -9,9 +10,10 @@ use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::mem_table::MemTableIterator;
 use crate::table::SsTableIterator;
+use crate::value_log::{ValueLog, ValuePointer};
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
-type LsmIteratorInner = TwoMergeIterator<
+pub(crate) type LsmIteratorInner = TwoMergeIterator<
     TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
     MergeIterator<SstConcatIterator>,
 >;
@@ -22,6 +24,9 @@ pub struct LsmIterator {
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    value_log: Arc<ValueLog>,
+    /// The current value read from the value log, if the current value is a value pointer.
+    resolved_value: Option<Bytes>,
 }
 
 impl LsmIterator {
@@ -29,6 +34,7 @@ impl LsmIterator {
         iter: LsmIteratorInner,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        value_log: Arc<ValueLog>,
     ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
@@ -36,6 +42,8 @@ impl LsmIterator {
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            value_log,
+            resolved_value: None,
         };
         iter.move_to_key()?;
         Ok(iter)
@@ -81,6 +89,12 @@ impl LsmIterator {
                 break;
             }
         }
+        // only read the value log for the version the iterator lands on
+        self.resolved_value = None;
+        if self.is_valid && self.inner.is_valid() && self.inner.is_value_pointer() {
+            let pointer = ValuePointer::decode(self.inner.value())?;
+            self.resolved_value = Some(self.value_log.read(pointer)?);
+        }
         Ok(())
     }
 }
@@ -97,7 +111,10 @@ impl StorageIterator for LsmIterator {
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.resolved_value {
+            Some(value) => value,
+            None => self.inner.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
@@ -129,7 +146,10 @@ impl<I: StorageIterator> FusedIterator<I> {
 }
 
 impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
-    type KeyType<'a> = I::KeyType<'a> where Self: 'a;
+    type KeyType<'a>
+        = I::KeyType<'a>
+    where
+        Self: 'a;
 
     fn is_valid(&self) -> bool {
         !self.has_errored && self.iter.is_valid()
@@ -149,6 +169,10 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.iter.is_value_pointer()
+    }
+
     fn next(&mut self) -> Result<()> {
         // only move when the iterator is valid and not errored
         if self.has_errored {
@@ -1,32 +1,45 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
+use serde::{Deserialize, Serialize};
 
-use crate::block::Block;
+use crate::background::BackgroundWork;
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionOptions, CompactionTriggerOptions, LeveledCompactionOptions,
+    SimpleLeveledCompactionOptions, SstPartitioner, TieredCompactionLimits,
 };
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::key::{self, KeySlice};
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmIteratorInner};
 use crate::manifest::{Manifest, ManifestRecord};
-use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
+use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable, MemTableRepType};
 use crate::mvcc::txn::{Transaction, TxnIterator};
 use crate::mvcc::LsmMvccInner;
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
-
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+use crate::prefetch::PrefetchPool;
+use crate::rate_limiter::{IoPriority, RateLimiter, RateLimiterHandle};
+use crate::table::{
+    FileObject, ReadOptions, SsTable, SsTableBuilder, SsTableIterator, TableCache, TableCacheStats,
+};
+use crate::value_log::{ValueLog, ValueLogBuilder, ValueLogOptions, ValuePointer};
+use crate::write_buffer_manager::{WriteBufferHandle, WriteBufferManager};
+use crate::write_stall::{WriteController, WriteStallOptions, WriteStallStats};
+
+pub use crate::block_cache::{BlockCache, BlockCacheStats, SecondaryCacheOptions};
+pub use crate::compact::{
+    CompactionFilter, CompactionFilterContext, CompactionFilterDecision, CompactionFilterFactory,
+    CompactionKeyFilter,
+};
 
 /// Represents the state of the storage engine.
 #[derive(Clone)]
@@ -50,18 +63,20 @@ pub enum WriteBatchRecord<T: AsRef<[u8]>> {
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
+    pub(crate) fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
                 ..=*max_levels)
                 .map(|level| (level, Vec::new()))
                 .collect::<Vec<_>>(),
-            CompactionOptions::Tiered(_) => Vec::new(),
+            CompactionOptions::Tiered(_)
+            | CompactionOptions::Fifo(_)
+            | CompactionOptions::TimeWindow(_) => Vec::new(),
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
-            memtable: Arc::new(MemTable::create(0)),
+            memtable: Arc::new(MemTable::create_with_rep(0, options.memtable_rep)),
             imm_memtables: Vec::new(),
             l0_sstables: Vec::new(),
             levels,
@@ -78,12 +93,104 @@ pub struct LsmStorageOptions {
     pub target_sst_size: usize,
     // Maximum number of memtables in memory, flush to L0 when exceeding this limit
     pub num_memtable_limit: usize,
+    // The memtable implementation
+    pub memtable_rep: MemTableRepType,
     pub compaction_options: CompactionOptions,
+    // Bound the tiers and the bytes merged by one tiered compaction
+    pub tiered_compaction_limits: TieredCompactionLimits,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Read SSTs through memory-mapped files instead of `pread`
+    pub enable_mmap: bool,
+    // Block cache capacity in bytes
+    pub block_cache_capacity: u64,
+    // Use a block cache shared with other instances instead of creating one, which ignores
+    // `block_cache_capacity`
+    pub block_cache: Option<Arc<BlockCache>>,
+    // Keep the blocks evicted from the block cache in a compressed secondary cache, ignored if
+    // `block_cache` is set
+    pub secondary_block_cache: Option<SecondaryCacheOptions>,
+    // Maximum number of SST files kept open, SSTs are opened on demand if set, otherwise all SSTs
+    // are kept open
+    pub max_open_files: Option<usize>,
+    // Cache the index and the bloom filter of SSTs in the block cache instead of keeping them
+    // with the open files, only used with `max_open_files`
+    pub cache_index_and_filter_blocks: bool,
+    // Keep the index and the bloom filter of L0 SSTs in memory, only used with `max_open_files`
+    pub pin_l0_index_and_filter_blocks: bool,
+    // Number of blocks read in one I/O by scans, readahead and prefetching the next SST of a level
+    // are disabled if it is not greater than 1
+    pub readahead_blocks: usize,
+    // Number of blocks read in one I/O by compaction, which never fills the block cache
+    pub compaction_readahead_blocks: usize,
+    // Number of SSTs after the current one of a sorted run opened in the background by the reads
+    // with readahead
+    pub prefetch_tables: usize,
+    // Number of threads opening the prefetched SSTs, shared by all reads of the storage
+    pub prefetch_threads: usize,
+    // Store large values in the value log instead of SSTs, disabled if `None`
+    pub value_log: Option<ValueLogOptions>,
+    // Slow down and stop writes when flush or compaction falls behind, disabled if `None`
+    pub write_stall: Option<WriteStallOptions>,
+    // Limit the memtables of this and other instances sharing the manager to a total memory budget
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
+    // Split a compaction into up to this many key ranges compacted in parallel, disabled if it is
+    // not greater than 1
+    pub max_subcompactions: usize,
+    // Maximum number of compaction tasks running at the same time on disjoint SSTs, which is also
+    // the number of background compaction threads
+    pub max_background_compactions: usize,
+    // Limit the bytes per second written by flush and compaction and read by compaction, shared
+    // with other instances using the same limiter, unlimited if `None`
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Compact SSTs with too many deletes or written too long ago, used by leveled, simple leveled
+    // and tiered compaction
+    pub compaction_triggers: CompactionTriggerOptions,
+    // Cut an output SST of leveled and simple leveled compaction once it overlaps more than this
+    // many bytes of the level below the output level, so that compacting it later stays small,
+    // disabled if `None`
+    pub max_grandparent_overlap_bytes: Option<u64>,
+    // Cut the output SSTs of compaction at the key boundaries chosen by the partitioner, flushes
+    // write one SST regardless
+    pub sst_partitioner: Option<Arc<dyn SstPartitioner>>,
 }
 
 impl LsmStorageOptions {
+    /// The options of the week 1 tests, which the other defaults start from. Every option added
+    /// on top of the course options is only set here.
+    fn defaults() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20,
+            compaction_options: CompactionOptions::NoCompaction,
+            tiered_compaction_limits: TieredCompactionLimits::default(),
+            enable_wal: false,
+            num_memtable_limit: 50,
+            memtable_rep: MemTableRepType::SkipList,
+            serializable: false,
+            enable_mmap: false,
+            block_cache_capacity: 64 << 20, // 64MB
+            block_cache: None,
+            secondary_block_cache: None,
+            max_open_files: None,
+            cache_index_and_filter_blocks: false,
+            pin_l0_index_and_filter_blocks: false,
+            readahead_blocks: 1,
+            compaction_readahead_blocks: 8,
+            prefetch_tables: 1,
+            prefetch_threads: 2,
+            value_log: None,
+            write_stall: None,
+            write_buffer_manager: None,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            rate_limiter: None,
+            compaction_triggers: CompactionTriggerOptions::default(),
+            max_grandparent_overlap_bytes: None,
+            sst_partitioner: None,
+        }
+    }
+
     pub fn default_for_week1_test() -> Self {
         Self {
             block_size: 4096,
@@ -92,6 +199,7 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            ..Self::defaults()
         }
     }
 
@@ -102,7 +210,9 @@ impl LsmStorageOptions {
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
             num_memtable_limit: 2,
+            memtable_rep: MemTableRepType::SkipList,
             serializable: false,
+            ..Self::defaults()
         }
     }
 
@@ -113,9 +223,143 @@ impl LsmStorageOptions {
             compaction_options,
             enable_wal: false,
             num_memtable_limit: 2,
+            memtable_rep: MemTableRepType::SkipList,
             serializable: false,
+            ..Self::defaults()
         }
     }
+
+    /// Whether SSTs are compacted in the background.
+    pub(crate) fn is_compaction_enabled(&self) -> bool {
+        !matches!(self.compaction_options, CompactionOptions::NoCompaction)
+    }
+}
+
+/// The most key ranges a compaction is split into, each one compacted by its own thread.
+const MAX_SUBCOMPACTIONS: usize = 64;
+
+/// The options changed by `set_options`, each one left unchanged if `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MutableOptions {
+    pub target_sst_size: Option<usize>,
+    pub num_memtable_limit: Option<usize>,
+    // The parameters of the compaction strategy, which cannot be changed to another strategy or
+    // another number of levels
+    pub compaction_options: Option<CompactionOptions>,
+    pub tiered_compaction_limits: Option<TieredCompactionLimits>,
+    pub compaction_triggers: Option<CompactionTriggerOptions>,
+    pub max_subcompactions: Option<usize>,
+    pub max_background_compactions: Option<usize>,
+}
+
+impl MutableOptions {
+    /// Take every mutable option from `options`, as kept in the options file.
+    fn of(options: &LsmStorageOptions) -> Self {
+        Self {
+            target_sst_size: Some(options.target_sst_size),
+            num_memtable_limit: Some(options.num_memtable_limit),
+            compaction_options: Some(options.compaction_options.clone()),
+            tiered_compaction_limits: Some(options.tiered_compaction_limits.clone()),
+            compaction_triggers: Some(options.compaction_triggers.clone()),
+            max_subcompactions: Some(options.max_subcompactions),
+            max_background_compactions: Some(options.max_background_compactions),
+        }
+    }
+
+    /// Apply the changes to a copy of `options`, or fail if a new value is invalid.
+    fn apply(self, options: &LsmStorageOptions) -> Result<LsmStorageOptions> {
+        let mut new_options = options.clone();
+        if let Some(target_sst_size) = self.target_sst_size {
+            if target_sst_size == 0 {
+                bail!("target_sst_size must be positive");
+            }
+            new_options.target_sst_size = target_sst_size;
+        }
+        if let Some(num_memtable_limit) = self.num_memtable_limit {
+            if num_memtable_limit == 0 {
+                bail!("num_memtable_limit must be positive");
+            }
+            new_options.num_memtable_limit = num_memtable_limit;
+        }
+        if let Some(compaction_options) = self.compaction_options {
+            validate_compaction_options(&options.compaction_options, &compaction_options)?;
+            new_options.compaction_options = compaction_options;
+        }
+        if let Some(limits) = self.tiered_compaction_limits {
+            if limits.max_merge_width.is_some_and(|width| width < 2) {
+                bail!("max_merge_width must be at least 2");
+            }
+            if limits.max_compaction_bytes == Some(0) {
+                bail!("max_compaction_bytes must be positive");
+            }
+            new_options.tiered_compaction_limits = limits;
+        }
+        if let Some(compaction_triggers) = self.compaction_triggers {
+            if compaction_triggers
+                .tombstone_ratio
+                .is_some_and(|ratio| !(0.0..=1.0).contains(&ratio))
+            {
+                bail!("tombstone_ratio must be between 0 and 1");
+            }
+            new_options.compaction_triggers = compaction_triggers;
+        }
+        if let Some(max_subcompactions) = self.max_subcompactions {
+            if !(1..=MAX_SUBCOMPACTIONS).contains(&max_subcompactions) {
+                bail!(
+                    "max_subcompactions must be between 1 and {}",
+                    MAX_SUBCOMPACTIONS
+                );
+            }
+            new_options.max_subcompactions = max_subcompactions;
+        }
+        if let Some(max_background_compactions) = self.max_background_compactions {
+            if max_background_compactions == 0 {
+                bail!("max_background_compactions must be positive");
+            }
+            new_options.max_background_compactions = max_background_compactions;
+        }
+        Ok(new_options)
+    }
+}
+
+/// Check that the compaction options can replace the current ones, which keeps the levels in the
+/// state and the manifest meaningful.
+fn validate_compaction_options(current: &CompactionOptions, new: &CompactionOptions) -> Result<()> {
+    match (current, new) {
+        (CompactionOptions::Leveled(current), CompactionOptions::Leveled(new)) => {
+            if new.max_levels != current.max_levels {
+                bail!("max_levels of leveled compaction cannot be changed");
+            }
+            if new.level_size_multiplier <= 1 {
+                bail!("level_size_multiplier must be greater than 1");
+            }
+            if new.level0_file_num_compaction_trigger == 0 {
+                bail!("level0_file_num_compaction_trigger must be positive");
+            }
+        }
+        (CompactionOptions::Simple(current), CompactionOptions::Simple(new)) => {
+            if new.max_levels != current.max_levels {
+                bail!("max_levels of simple leveled compaction cannot be changed");
+            }
+            if new.level0_file_num_compaction_trigger == 0 {
+                bail!("level0_file_num_compaction_trigger must be positive");
+            }
+        }
+        (CompactionOptions::Tiered(_), CompactionOptions::Tiered(new)) => {
+            if new.num_tiers == 0 {
+                bail!("num_tiers must be positive");
+            }
+        }
+        (CompactionOptions::Fifo(_), CompactionOptions::Fifo(_)) => {}
+        (CompactionOptions::TimeWindow(_), CompactionOptions::TimeWindow(new)) => {
+            if new.window_size == 0 {
+                bail!("window_size must be positive");
+            }
+        }
+        (CompactionOptions::NoCompaction, CompactionOptions::NoCompaction) => {}
+        _ => bail!("the compaction strategy cannot be changed"),
+    }
+    Ok(())
 }
 
 fn range_overlap(
@@ -149,9 +393,24 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
-#[derive(Clone, Debug)]
-pub enum CompactionFilter {
-    Prefix(Bytes),
+/// The options in effect and the compaction controller built from them, which `set_options`
+/// replaces together.
+pub(crate) struct CurrentOptions {
+    pub(crate) options: LsmStorageOptions,
+    pub(crate) compaction_controller: CompactionController,
+}
+
+/// Publishes the commit timestamp of a write batch when dropped. The timestamp must be published
+/// even if writing the batch fails or panics, or later batches never become visible.
+struct CommitTsGuard<'a> {
+    mvcc: &'a LsmMvccInner,
+    ts: u64,
+}
+
+impl Drop for CommitTsGuard<'_> {
+    fn drop(&mut self) {
+        self.mvcc.update_commit_ts(self.ts);
+    }
 }
 
 /// The storage interface of the LSM tree.
@@ -160,12 +419,28 @@ pub(crate) struct LsmStorageInner {
     pub(crate) state_lock: Mutex<()>,
     path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
+    pub(crate) table_cache: Option<Arc<TableCache>>,
+    /// The threads opening the SSTs prefetched by the reads with readahead, started by the first
+    /// of these reads.
+    pub(crate) prefetch_pool: OnceLock<Arc<PrefetchPool>>,
     next_sst_id: AtomicUsize,
+    /// The options passed to `open`. The ones that `set_options` can change are read from
+    /// `current_options` instead.
     pub(crate) options: Arc<LsmStorageOptions>,
-    pub(crate) compaction_controller: CompactionController,
+    current_options: ArcSwap<CurrentOptions>,
+    /// SSTs taken by running compaction tasks, which other tasks must not take. Only changed
+    /// while holding `state_lock`.
+    pub(crate) compacting_sstables: Mutex<HashSet<usize>>,
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
-    pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) compaction_filters: Arc<Mutex<Vec<Arc<dyn CompactionFilterFactory>>>>,
+    pub(crate) value_log: Arc<ValueLog>,
+    pub(crate) write_controller: WriteController,
+    pub(crate) write_buffer: Option<WriteBufferHandle>,
+    /// The share of the instance in `options.rate_limiter`, which reports the pending compaction
+    /// bytes of the instance.
+    rate_limiter: Option<RateLimiterHandle>,
+    pub(crate) background_work: BackgroundWork,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -179,10 +454,15 @@ pub struct MiniLsm {
     compaction_notifier: crossbeam_channel::Sender<()>,
     /// The handle for the compaction thread. (In week 2)
     compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Notifies the value log GC thread to stop working.
+    value_log_gc_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the value log GC thread.
+    value_log_gc_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl Drop for MiniLsm {
     fn drop(&mut self) {
+        self.value_log_gc_notifier.send(()).ok();
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
     }
@@ -190,10 +470,19 @@ impl Drop for MiniLsm {
 
 impl MiniLsm {
     pub fn close(&self) -> Result<()> {
+        self.inner.write_controller.close();
         self.inner.sync_dir()?;
+        self.value_log_gc_notifier.send(()).ok();
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
 
+        let mut value_log_gc_thread = self.value_log_gc_thread.lock();
+        if let Some(value_log_gc_thread) = value_log_gc_thread.take() {
+            value_log_gc_thread
+                .join()
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        }
+
         let mut compaction_thread = self.compaction_thread.lock();
         if let Some(compaction_thread) = compaction_thread.take() {
             compaction_thread
@@ -234,23 +523,54 @@ impl MiniLsm {
 
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
-    pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
-        let inner = Arc::new(LsmStorageInner::open(path, options)?);
+    pub fn open(
+        path: impl AsRef<Path>,
+        options: impl Into<LsmStorageOptions>,
+    ) -> Result<Arc<Self>> {
+        let inner = Arc::new(LsmStorageInner::open(path, options.into())?);
         let (tx1, rx) = crossbeam_channel::unbounded();
         let compaction_thread = inner.spawn_compaction_thread(rx)?;
         let (tx2, rx) = crossbeam_channel::unbounded();
         let flush_thread = inner.spawn_flush_thread(rx)?;
+        let (tx3, rx) = crossbeam_channel::unbounded();
+        let value_log_gc_thread = inner.spawn_value_log_gc_thread(rx)?;
         Ok(Arc::new(Self {
             inner,
             flush_notifier: tx2,
             flush_thread: Mutex::new(flush_thread),
             compaction_notifier: tx1,
             compaction_thread: Mutex::new(compaction_thread),
+            value_log_gc_notifier: tx3,
+            value_log_gc_thread: Mutex::new(value_log_gc_thread),
         }))
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
-        self.inner.add_compaction_filter(compaction_filter)
+    /// Add a compaction filter, which applies to the compactions started afterwards.
+    pub fn add_compaction_filter(&self, factory: impl CompactionFilterFactory + 'static) {
+        self.inner.add_compaction_filter(Arc::new(factory))
+    }
+
+    /// Stop starting flushes, compactions and value log GCs in the background until
+    /// `resume_background_work` is called. The work already running is finished. Writes stall
+    /// once the memtables are full, and writes stopped by a write stall wait for the resume.
+    pub fn pause_background_work(&self) {
+        self.inner.background_work.pause();
+    }
+
+    pub fn resume_background_work(&self) {
+        self.inner.background_work.resume();
+    }
+
+    /// Change options without reopening the storage, see [`MutableOptions`]. The new options
+    /// are persisted in the options file, and replace the ones given to the next `open`.
+    pub fn set_options(&self, options: MutableOptions) -> Result<()> {
+        self.inner.set_options(options)
+    }
+
+    /// Abort the running compactions at the next block boundary and remove the SSTs they wrote,
+    /// which leaves the LSM tree as before the compactions. Returns once they have stopped.
+    pub fn cancel_running_compaction(&self) {
+        self.inner.background_work.cancel_running_compactions();
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
@@ -296,6 +616,35 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    /// Manually compact the SSTs overlapping a key range, see
+    /// [`LsmStorageInner::compact_range`]. It is safe to call while background compactions run.
+    pub fn compact_range(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        target_level: Option<usize>,
+    ) -> Result<()> {
+        self.inner.compact_range(lower, upper, target_level)
+    }
+
+    /// Garbage-collect all value log files with discarded values.
+    pub fn force_value_log_gc(&self) -> Result<()> {
+        self.inner.force_value_log_gc()
+    }
+
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.inner.block_cache.stats()
+    }
+
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        self.inner.write_controller.stats()
+    }
+
+    /// Get the table cache statistics, or `None` if all SSTs are kept open.
+    pub fn table_cache_stats(&self) -> Option<TableCacheStats> {
+        self.inner.table_cache.as_ref().map(|x| x.stats())
+    }
 }
 
 impl LsmStorageInner {
@@ -308,6 +657,11 @@ impl LsmStorageInner {
         self.mvcc.as_ref().unwrap()
     }
 
+    /// Load the options in effect. An operation loads them once and passes them down.
+    pub(crate) fn current_options(&self) -> Arc<CurrentOptions> {
+        self.current_options.load_full()
+    }
+
     pub(crate) fn manifest(&self) -> &Manifest {
         self.manifest.as_ref().unwrap()
     }
@@ -315,24 +669,27 @@ impl LsmStorageInner {
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
         let path = path.as_ref();
+        let options = Self::load_options(path, options)?;
+        let mut state = LsmStorageState::create(&options);
         let mut next_sst_id = 1;
-        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
+        let block_cache = Arc::new(match &options.block_cache {
+            Some(block_cache) => block_cache.share(),
+            None => match &options.secondary_block_cache {
+                Some(secondary_options) => BlockCache::new_with_secondary_cache(
+                    options.block_cache_capacity,
+                    secondary_options,
+                )?,
+                None => BlockCache::new(options.block_cache_capacity),
+            },
+        });
+        let table_cache = options
+            .max_open_files
+            .map(|max_open_files| Arc::new(TableCache::new(max_open_files)));
         let manifest;
+        let value_log = Arc::new(ValueLog::new(path, options.enable_mmap));
 
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
-            ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
-        };
+        let compaction_controller = CompactionController::new(&options);
 
         if !path.exists() {
             std::fs::create_dir_all(path).context("failed to create DB dir")?;
@@ -341,8 +698,9 @@ impl LsmStorageInner {
         let mut last_commit_ts = 0;
         if !manifest_path.exists() {
             if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
+                state.memtable = Arc::new(MemTable::create_with_wal_and_rep(
                     state.memtable.id(),
+                    options.memtable_rep,
                     Self::path_of_wal_static(path, state.memtable.id()),
                 )?);
             }
@@ -351,6 +709,9 @@ impl LsmStorageInner {
         } else {
             let (m, records) = Manifest::recover(&manifest_path)?;
             let mut memtables = BTreeSet::new();
+            let mut gc_inputs = Vec::new();
+            // the value log files written with the SSTs and by the GCs in the manifest
+            let mut recorded_vlogs = HashSet::new();
             for record in records {
                 match record {
                     ManifestRecord::Flush(sst_id) => {
@@ -362,6 +723,7 @@ impl LsmStorageInner {
                             state.levels.insert(0, (sst_id, vec![sst_id]));
                         }
                         next_sst_id = next_sst_id.max(sst_id);
+                        recorded_vlogs.insert(sst_id);
                     }
                     ManifestRecord::NewMemtable(x) => {
                         next_sst_id = next_sst_id.max(x);
@@ -374,6 +736,40 @@ impl LsmStorageInner {
                         state = new_state;
                         next_sst_id =
                             next_sst_id.max(output.iter().max().copied().unwrap_or_default());
+                        recorded_vlogs.extend(output);
+                    }
+                    ManifestRecord::ValueLogGc(input, output) => {
+                        value_log.apply_gc(input, output);
+                        next_sst_id = next_sst_id.max(output.unwrap_or_default());
+                        recorded_vlogs.extend(output);
+                        gc_inputs.push(input);
+                    }
+                    ManifestRecord::ValueLogDiscard(discarded) => {
+                        value_log.add_discarded(&discarded);
+                    }
+                }
+            }
+
+            // remove the value log files left by GCs interrupted before removing the inputs
+            for input in gc_inputs {
+                let path = Self::path_of_vlog_static(path, input);
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            // value log files are created before the SSTs referring to them are recorded in the
+            // manifest, so the files of the flushes, compactions and GCs interrupted before that
+            // are referenced by no SST
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                if let Some(id) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".vlog"))
+                    .and_then(|id| id.parse::<usize>().ok())
+                {
+                    if !recorded_vlogs.contains(&id) {
+                        std::fs::remove_file(entry.path())?;
                     }
                 }
             }
@@ -386,17 +782,43 @@ impl LsmStorageInner {
                 .chain(state.levels.iter().flat_map(|(_, files)| files))
             {
                 let table_id = *table_id;
-                let sst = SsTable::open(
-                    table_id,
-                    Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
-                        .context("failed to open SST")?,
-                )?;
+                let sst_path = Self::path_of_sst_static(path, table_id);
+                let sst = match &table_cache {
+                    Some(table_cache) => SsTable::open_lazy(
+                        table_id,
+                        Some(block_cache.clone()),
+                        table_cache.clone(),
+                        &sst_path,
+                        options.enable_mmap,
+                        options.cache_index_and_filter_blocks,
+                    ),
+                    None => SsTable::open(
+                        table_id,
+                        Some(block_cache.clone()),
+                        FileObject::open_with_mode(&sst_path, options.enable_mmap)?,
+                    ),
+                }
+                .context("failed to open SST")?;
+                let sst = if table_cache.is_some()
+                    && options.pin_l0_index_and_filter_blocks
+                    && state.l0_sstables.contains(&table_id)
+                {
+                    sst.pin_index()?
+                } else {
+                    sst
+                };
                 last_commit_ts = last_commit_ts.max(sst.max_ts());
                 state.sstables.insert(table_id, Arc::new(sst));
                 sst_cnt += 1;
             }
             println!("{} SSTs opened", sst_cnt);
+            // range compactions replayed before the SSTs were opened append to the levels
+            if compaction_controller.flush_to_l0() {
+                let sstables = &state.sstables;
+                for (_, ssts) in &mut state.levels {
+                    ssts.sort_by(|x, y| sstables[x].first_key().cmp(sstables[y].first_key()));
+                }
+            }
 
             next_sst_id += 1;
 
@@ -404,27 +826,27 @@ impl LsmStorageInner {
             if options.enable_wal {
                 let mut wal_cnt = 0;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
+                    let memtable = MemTable::recover_from_wal_with_rep(
+                        *id,
+                        options.memtable_rep,
+                        Self::path_of_wal_static(path, *id),
+                    )?;
+                    memtable.freeze();
+                    last_commit_ts = last_commit_ts.max(memtable.max_ts()?);
                     if !memtable.is_empty() {
                         state.imm_memtables.insert(0, Arc::new(memtable));
                         wal_cnt += 1;
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
+                state.memtable = Arc::new(MemTable::create_with_wal_and_rep(
                     next_sst_id,
+                    options.memtable_rep,
                     Self::path_of_wal_static(path, next_sst_id),
                 )?);
             } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                state.memtable =
+                    Arc::new(MemTable::create_with_rep(next_sst_id, options.memtable_rep));
             }
             m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
             next_sst_id += 1;
@@ -436,21 +858,40 @@ impl LsmStorageInner {
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
             block_cache,
+            table_cache,
+            prefetch_pool: OnceLock::new(),
             next_sst_id: AtomicUsize::new(next_sst_id),
-            compaction_controller,
+            current_options: ArcSwap::from_pointee(CurrentOptions {
+                options: options.clone(),
+                compaction_controller,
+            }),
+            compacting_sstables: Mutex::new(HashSet::new()),
             manifest: Some(manifest),
-            options: options.into(),
+            options: Arc::new(options.clone()),
             mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            value_log,
+            write_controller: WriteController::new(options.write_stall.clone()),
+            background_work: BackgroundWork::default(),
+            write_buffer: options
+                .write_buffer_manager
+                .as_ref()
+                .map(|manager| manager.register()),
+            rate_limiter: options
+                .rate_limiter
+                .as_ref()
+                .map(|rate_limiter| rate_limiter.register()),
         };
         storage.sync_dir()?;
+        storage.update_write_stall();
+        storage.update_write_buffer_usage();
 
         Ok(storage)
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+    pub fn add_compaction_filter(&self, factory: Arc<dyn CompactionFilterFactory>) {
         let mut compaction_filters = self.compaction_filters.lock();
-        compaction_filters.push(compaction_filter);
+        compaction_filters.push(factory);
     }
 
     pub fn sync(&self) -> Result<()> {
@@ -463,118 +904,144 @@ impl LsmStorageInner {
         txn.get(key)
     }
 
+    /// Get the newest version of a key visible at `read_ts`. The sources are probed from the
+    /// newest to the oldest, and a table is only read if it may hold a newer version than the one
+    /// found so far, which is checked with the largest timestamp of the table. Concurrent writes
+    /// can put a version into a newer memtable than a version with a larger timestamp, so the
+    /// memtables are always all probed.
     pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
-        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
-            memtable_iters.push(Box::new(memtable.scan(
-                Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+        let lookup_key = KeySlice::from_slice(key, read_ts);
+        // the timestamp, the value and whether the value is a value pointer
+        let mut found: Option<(u64, Bytes, bool)> = None;
+
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            let iter = memtable.scan(
+                Bound::Included(lookup_key),
                 Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-            )));
+            );
+            if iter.is_valid()
+                && found
+                    .as_ref()
+                    .is_none_or(|(ts, _, _)| iter.key().ts() > *ts)
+            {
+                found = Some((iter.key().ts(), Bytes::copy_from_slice(iter.value()), false));
+            }
         }
-        let memtable_iter = MergeIterator::create(memtable_iters);
-
-        let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
 
-        let keep_table = |key: &[u8], table: &SsTable| {
-            if key_within(
-                key,
-                table.first_key().as_key_slice(),
-                table.last_key().as_key_slice(),
-            ) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        return true;
-                    }
-                } else {
-                    return true;
+        let mut probe_table = |table: &Arc<SsTable>| -> Result<()> {
+            if found
+                .as_ref()
+                .is_some_and(|(ts, _, _)| *ts >= table.max_ts())
+                || !key_within(
+                    key,
+                    table.first_key().as_key_slice(),
+                    table.last_key().as_key_slice(),
+                )
+                || !table.may_contain(key)?
+            {
+                return Ok(());
+            }
+            let iter = SsTableIterator::create_and_seek_to_key(table.clone(), lookup_key)?;
+            if iter.is_valid() && iter.key().key_ref() == key {
+                let ts = iter.key().ts();
+                if found.as_ref().is_none_or(|(found_ts, _, _)| ts > *found_ts) {
+                    found = Some((
+                        ts,
+                        Bytes::copy_from_slice(iter.value()),
+                        iter.is_value_pointer(),
+                    ));
                 }
             }
-            false
+            Ok(())
         };
 
         for table in snapshot.l0_sstables.iter() {
-            let table = snapshot.sstables[table].clone();
-            if keep_table(key, &table) {
-                l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                    table,
-                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                )?));
-            }
+            probe_table(&snapshot.sstables[table])?;
         }
-        let l0_iter = MergeIterator::create(l0_iters);
-        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
         for (_, level_sst_ids) in &snapshot.levels {
-            let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
-            for table in level_sst_ids {
-                let table = snapshot.sstables[table].clone();
-                if keep_table(key, &table) {
-                    level_ssts.push(table);
-                }
+            // the SSTs of a level are sorted and do not overlap
+            let idx = level_sst_ids
+                .partition_point(|table| snapshot.sstables[table].last_key().key_ref() < key);
+            if let Some(table) = level_sst_ids.get(idx) {
+                probe_table(&snapshot.sstables[table])?;
             }
-            let level_iter = SstConcatIterator::create_and_seek_to_key(
-                level_ssts,
-                KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-            )?;
-            level_iters.push(Box::new(level_iter));
         }
 
-        let iter = LsmIterator::new(
-            TwoMergeIterator::create(
-                TwoMergeIterator::create(memtable_iter, l0_iter)?,
-                MergeIterator::create(level_iters),
-            )?,
-            Bound::Unbounded,
-            read_ts,
-        )?;
-
-        if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        match found {
+            Some((_, value, _)) if value.is_empty() => Ok(None),
+            Some((_, value, true)) => Ok(Some(self.value_log.read(ValuePointer::decode(&value)?)?)),
+            Some((_, value, false)) => Ok(Some(value)),
+            None => Ok(None),
         }
-        Ok(None)
     }
 
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
-        let ts = self.mvcc().latest_commit_ts() + 1;
+        // a batch failing the checks does not take a timestamp
+        for record in batch {
+            match record {
+                WriteBatchRecord::Del(key) | WriteBatchRecord::Put(key, _)
+                    if key.as_ref().is_empty() =>
+                {
+                    bail!("key cannot be empty")
+                }
+                WriteBatchRecord::Put(_, value) if value.as_ref().is_empty() => {
+                    bail!("value cannot be empty")
+                }
+                _ => {}
+            }
+        }
+        self.write_controller.wait_for_write()?;
+        let result = {
+            let commit_ts = CommitTsGuard {
+                mvcc: self.mvcc(),
+                ts: self.mvcc().allocate_commit_ts(),
+            };
+            self.write_batch_at_ts(batch, commit_ts.ts)
+                .map(|_| commit_ts.ts)
+        };
+        self.update_write_buffer_usage();
+        result
+    }
+
+    /// Insert a batch into the memtable at `ts`. Batches at different timestamps are inserted
+    /// concurrently.
+    fn write_batch_at_ts<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        ts: u64,
+    ) -> Result<()> {
+        let target_sst_size = self.current_options().options.target_sst_size;
         for record in batch {
             match record {
                 WriteBatchRecord::Del(key) => {
                     let key = key.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
                     let size;
                     {
                         let guard = self.state.read();
                         guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
                         size = guard.memtable.approximate_size();
                     }
-                    self.try_freeze(size)?;
+                    self.try_freeze(size, target_sst_size)?;
                 }
                 WriteBatchRecord::Put(key, value) => {
                     let key = key.as_ref();
                     let value = value.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
                     let size;
                     {
                         let guard = self.state.read();
                         guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
                         size = guard.memtable.approximate_size();
                     }
-                    self.try_freeze(size)?;
+                    self.try_freeze(size, target_sst_size)?;
                 }
             }
         }
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        Ok(())
     }
 
     pub fn write_batch<T: AsRef<[u8]>>(
@@ -624,12 +1091,12 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
-        if estimated_size >= self.options.target_sst_size {
+    fn try_freeze(&self, estimated_size: usize, target_sst_size: usize) -> Result<()> {
+        if estimated_size >= target_sst_size {
             let state_lock = self.state_lock.lock();
             let guard = self.state.read();
             // the memtable could have already been frozen, check again to ensure we really need to freeze
-            if guard.memtable.approximate_size() >= self.options.target_sst_size {
+            if guard.memtable.approximate_size() >= target_sst_size {
                 drop(guard);
                 self.force_freeze_memtable(&state_lock)?;
             }
@@ -637,6 +1104,171 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Report the memory used by the memtables to the write buffer manager.
+    pub(crate) fn update_write_buffer_usage(&self) {
+        let Some(write_buffer) = &self.write_buffer else {
+            return;
+        };
+        let usage = {
+            let guard = self.state.read();
+            guard.memtable.approximate_size()
+                + guard
+                    .imm_memtables
+                    .iter()
+                    .map(|x| x.approximate_size())
+                    .sum::<usize>()
+        };
+        write_buffer.update_usage(usage);
+    }
+
+    /// Replace the options set in `options`. The flushes, compactions and writes started
+    /// afterwards use the new options, the running ones finish with the old options.
+    pub fn set_options(&self, options: MutableOptions) -> Result<()> {
+        {
+            let _state_lock = self.state_lock.lock();
+            let new_options = options.apply(&self.current_options().options)?;
+            self.persist_options(&new_options)?;
+            let compaction_controller = CompactionController::new(&new_options);
+            self.current_options.store(Arc::new(CurrentOptions {
+                options: new_options,
+                compaction_controller,
+            }));
+        }
+        // the new limits may start or stop a write stall
+        self.update_write_stall();
+        Ok(())
+    }
+
+    /// Apply the options persisted in the options file by `set_options`, if any, on top of the
+    /// options given to `open`.
+    fn load_options(path: &Path, options: LsmStorageOptions) -> Result<LsmStorageOptions> {
+        let options_path = path.join("OPTIONS");
+        if !options_path.exists() {
+            return Ok(options);
+        }
+        let mut persisted: MutableOptions = serde_json::from_slice(&std::fs::read(options_path)?)
+            .context("failed to parse the options file")?;
+        if let (
+            Some(CompactionOptions::TimeWindow(persisted)),
+            CompactionOptions::TimeWindow(given),
+        ) = (
+            &mut persisted.compaction_options,
+            &options.compaction_options,
+        ) {
+            persisted.time_source = given.time_source.clone();
+        }
+        persisted.apply(&options)
+    }
+
+    /// Write the mutable options to the options file, replacing it at once.
+    fn persist_options(&self, options: &LsmStorageOptions) -> Result<()> {
+        let tmp_path = self.path.join("OPTIONS.tmp");
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, &MutableOptions::of(options))?;
+        file.sync_all()?;
+        std::fs::rename(tmp_path, self.path.join("OPTIONS"))?;
+        self.sync_dir()
+    }
+
+    /// Recompute the write stall condition from the current state, and adapt the rate of an
+    /// auto-tuned rate limiter to the compaction backlog.
+    pub(crate) fn update_write_stall(&self) {
+        let snapshot = self.state.read().clone();
+        let current = self.current_options();
+        let compaction = current.options.is_compaction_enabled();
+        let pending_compaction_bytes = current
+            .compaction_controller
+            .estimate_pending_compaction_bytes(&snapshot);
+        self.write_controller.update(
+            snapshot.imm_memtables.len(),
+            snapshot.l0_sstables.len(),
+            pending_compaction_bytes,
+            compaction,
+        );
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.update_pending_compaction_bytes(pending_compaction_bytes);
+        }
+    }
+
+    /// The prefetch pool for the reads with `readahead_blocks`, or `None` without readahead.
+    fn prefetch_pool(&self, readahead_blocks: usize) -> Option<Arc<PrefetchPool>> {
+        (readahead_blocks > 1).then(|| {
+            self.prefetch_pool
+                .get_or_init(|| Arc::new(PrefetchPool::new(self.options.prefetch_threads)))
+                .clone()
+        })
+    }
+
+    /// The options of reading SSTs for scans.
+    pub(crate) fn scan_read_options(&self) -> ReadOptions {
+        ReadOptions {
+            fill_cache: true,
+            readahead_blocks: self.options.readahead_blocks,
+            prefetch_tables: self.options.prefetch_tables,
+            prefetch_pool: self.prefetch_pool(self.options.readahead_blocks),
+            rate_limiter: None,
+        }
+    }
+
+    /// The options of reading SSTs for compaction, which keep the compaction inputs out of the
+    /// block cache and read at the pace of the rate limiter.
+    pub(crate) fn compaction_read_options(&self) -> ReadOptions {
+        ReadOptions {
+            fill_cache: false,
+            readahead_blocks: self.options.compaction_readahead_blocks,
+            prefetch_tables: self.options.prefetch_tables,
+            prefetch_pool: self.prefetch_pool(self.options.compaction_readahead_blocks),
+            rate_limiter: self.options.rate_limiter.clone(),
+        }
+    }
+
+    /// Create an SST builder configured by the storage options. The SST is written at `priority`
+    /// if there is a rate limiter. The value log file of the SST shares the id of the SST, so that
+    /// it is recorded in the manifest together with the SST.
+    pub(crate) fn new_sst_builder(&self, sst_id: usize, priority: IoPriority) -> SsTableBuilder {
+        let mut builder =
+            SsTableBuilder::new(self.options.block_size).with_mmap(self.options.enable_mmap);
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            builder = builder.with_rate_limiter(rate_limiter.clone(), priority);
+        }
+        let Some(options) = &self.options.value_log else {
+            return builder;
+        };
+        let mut value_log = ValueLogBuilder::new(sst_id, self.path_of_vlog(sst_id));
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            value_log = value_log.with_rate_limiter(rate_limiter.clone(), priority);
+        }
+        builder.with_value_log(value_log, options.value_threshold)
+    }
+
+    /// Build an SST created by `new_sst_builder`. The file of the SST is handed to the table cache,
+    /// and the index is pinned if the SST goes to L0 and pinning is enabled.
+    pub(crate) fn build_sst(
+        &self,
+        builder: SsTableBuilder,
+        sst_id: usize,
+        to_l0: bool,
+    ) -> Result<Arc<SsTable>> {
+        let sst = builder.build(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(sst_id),
+        )?;
+        let Some(table_cache) = &self.table_cache else {
+            return Ok(Arc::new(sst));
+        };
+        let sst = sst.into_lazy(
+            table_cache.clone(),
+            self.path_of_sst(sst_id),
+            self.options.enable_mmap,
+            self.options.cache_index_and_filter_blocks,
+        )?;
+        if to_l0 && self.options.pin_l0_index_and_filter_blocks {
+            return Ok(Arc::new(sst.pin_index()?));
+        }
+        Ok(Arc::new(sst))
+    }
+
     pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.sst", id))
     }
@@ -645,6 +1277,14 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
+    pub(crate) fn path_of_vlog_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.vlog", id))
+    }
+
+    pub(crate) fn path_of_vlog(&self, id: usize) -> PathBuf {
+        Self::path_of_vlog_static(&self.path, id)
+    }
+
     pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
@@ -669,6 +1309,8 @@ impl LsmStorageInner {
         *guard = Arc::new(snapshot);
 
         drop(guard);
+        self.update_write_stall();
+        old_memtable.freeze();
         old_memtable.sync_wal()?;
 
         Ok(())
@@ -678,12 +1320,16 @@ impl LsmStorageInner {
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
         let memtable_id = self.next_sst_id();
         let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
+            Arc::new(MemTable::create_with_wal_and_rep(
                 memtable_id,
+                self.options.memtable_rep,
                 self.path_of_wal(memtable_id),
             )?)
         } else {
-            Arc::new(MemTable::create(memtable_id))
+            Arc::new(MemTable::create_with_rep(
+                memtable_id,
+                self.options.memtable_rep,
+            ))
         };
 
         self.freeze_memtable_with_memtable(memtable)?;
@@ -705,21 +1351,18 @@ impl LsmStorageInner {
 
         {
             let guard = self.state.read();
-            flush_memtable = guard
-                .imm_memtables
-                .last()
-                .expect("no imm memtables!")
-                .clone();
+            // the flush thread may have flushed it before we got the lock
+            let Some(memtable) = guard.imm_memtables.last() else {
+                return Ok(());
+            };
+            flush_memtable = memtable.clone();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
-        flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+        let mut builder = self.new_sst_builder(sst_id, IoPriority::High);
+        flush_memtable.flush(&mut builder)?;
+        let flush_to_l0 = self.current_options().compaction_controller.flush_to_l0();
+        let sst = self.build_sst(builder, sst_id, flush_to_l0)?;
 
         // Add the flushed L0 table to the list.
         {
@@ -729,7 +1372,7 @@ impl LsmStorageInner {
             let mem = snapshot.imm_memtables.pop().unwrap();
             assert_eq!(mem.id(), sst_id);
             // Add L0 table
-            if self.compaction_controller.flush_to_l0() {
+            if flush_to_l0 {
                 // In leveled compaction or no compaction, simply flush to L0
                 snapshot.l0_sstables.insert(0, sst_id);
             } else {
@@ -750,6 +1393,8 @@ impl LsmStorageInner {
             .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
 
         self.sync_dir()?;
+        self.update_write_stall();
+        self.update_write_buffer_usage();
 
         Ok(())
     }
@@ -774,10 +1419,26 @@ impl LsmStorageInner {
         upper: Bound<&[u8]>,
         read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
+        Ok(FusedIterator::new(LsmIterator::new(
+            self.scan_raw(lower, upper)?,
+            map_bound(upper),
+            read_ts,
+            self.value_log.clone(),
+        )?))
+    }
+
+    /// Create an iterator over all versions of the keys in a range. The upper bound only prunes
+    /// the tables to read and must be checked by the caller.
+    pub(crate) fn scan_raw(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<LsmIteratorInner> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
+        let read_options = self.scan_read_options();
 
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(
@@ -802,21 +1463,26 @@ impl LsmStorageInner {
                 table.last_key().as_key_slice(),
             ) {
                 let iter = match lower {
-                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
+                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key_with_options(
                         table,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        read_options.clone(),
                     )?,
                     Bound::Excluded(key) => {
-                        let mut iter = SsTableIterator::create_and_seek_to_key(
+                        let mut iter = SsTableIterator::create_and_seek_to_key_with_options(
                             table,
                             KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                            read_options.clone(),
                         )?;
                         while iter.is_valid() && iter.key().key_ref() == key {
                             iter.next()?;
                         }
                         iter
                     }
-                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
+                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first_with_options(
+                        table,
+                        read_options.clone(),
+                    )?,
                 };
 
                 table_iters.push(Box::new(iter));
@@ -840,32 +1506,31 @@ impl LsmStorageInner {
             }
 
             let level_iter = match lower {
-                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key(
+                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key_with_options(
                     level_ssts,
                     KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    read_options.clone(),
                 )?,
                 Bound::Excluded(key) => {
-                    let mut iter = SstConcatIterator::create_and_seek_to_key(
+                    let mut iter = SstConcatIterator::create_and_seek_to_key_with_options(
                         level_ssts,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        read_options.clone(),
                     )?;
                     while iter.is_valid() && iter.key().key_ref() == key {
                         iter.next()?;
                     }
                     iter
                 }
-                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first_with_options(
+                    level_ssts,
+                    read_options.clone(),
+                )?,
             };
             level_iters.push(Box::new(level_iter));
         }
 
         let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
-        let iter = TwoMergeIterator::create(iter, MergeIterator::create(level_iters))?;
-
-        Ok(FusedIterator::new(LsmIterator::new(
-            iter,
-            map_bound(upper),
-            read_ts,
-        )?))
+        TwoMergeIterator::create(iter, MergeIterator::create(level_iters))
     }
 }
@@ -19,6 +19,10 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// A value log file is garbage-collected, and its live values are moved to the second file.
+    ValueLogGc(usize, Option<usize>),
+    /// Values are dropped by a compaction, with the bytes discarded from each value log file.
+    ValueLogDiscard(Vec<(usize, u64)>),
 }
 
 impl Manifest {
@@ -1,28 +1,110 @@
+mod arena;
+mod hash;
+mod skiplist;
+mod vector;
+
 use std::ops::Bound;
 use std::path::Path;
-use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::Bytes;
-use crossbeam_skiplist::map::Entry;
-use crossbeam_skiplist::SkipMap;
-use ouroboros::self_referencing;
+
+use arena::ArenaIterator;
+pub use arena::ArenaMemTable;
+pub use hash::HashMemTable;
+pub use skiplist::SkipListMemTable;
+use skiplist::SkipMapIterator;
+pub use vector::VectorMemTable;
 
 use crate::iterators::StorageIterator;
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
 use crate::table::SsTableBuilder;
 use crate::wal::Wal;
 
-/// A basic mem-table based on crossbeam-skiplist.
+/// The data structure holding the key-value pairs of a memtable. The WAL is handled by
+/// [`MemTable`], so the implementations only deal with the in-memory data.
+pub trait MemTableRep: Send + Sync {
+    /// Put a key-value pair. A later put of the same key and timestamp overwrites the value.
+    /// Returns an error if the implementation does not accept puts after `freeze`.
+    fn put(&self, key: KeySlice, value: &[u8]) -> Result<()>;
+
+    /// Get the value of a key at exactly the timestamp of `key`.
+    fn get(&self, key: KeySlice) -> Option<Bytes>;
+
+    /// Get an iterator over a range of keys.
+    fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator;
+
+    /// Called once the memtable becomes immutable. No more puts follow.
+    fn freeze(&self) {}
+
+    /// Add all key-value pairs to an SST builder in key order.
+    fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
+        let mut iter = self.scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            builder.add(iter.key(), iter.value());
+            iter.next()?;
+        }
+        Ok(())
+    }
+
+    /// The approximate memory usage of the key-value pairs in bytes.
+    fn approximate_size(&self) -> usize;
+
+    fn is_empty(&self) -> bool;
+}
+
+/// The memtable implementations to choose from in `LsmStorageOptions`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemTableRepType {
+    /// [`SkipListMemTable`]
+    #[default]
+    SkipList,
+    /// [`VectorMemTable`]
+    Vector,
+    /// [`HashMemTable`]
+    Hash,
+    /// [`ArenaMemTable`]
+    Arena,
+}
+
+impl MemTableRepType {
+    fn create(self) -> Box<dyn MemTableRep> {
+        match self {
+            MemTableRepType::SkipList => Box::<SkipListMemTable>::default(),
+            MemTableRepType::Vector => Box::<VectorMemTable>::default(),
+            MemTableRepType::Hash => Box::<HashMemTable>::default(),
+            MemTableRepType::Arena => Box::<ArenaMemTable>::default(),
+        }
+    }
+}
+
+/// A mem-table, which stores the key-value pairs in a [`MemTableRep`] and logs the writes to the
+/// WAL.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    rep: Box<dyn MemTableRep>,
     wal: Option<Wal>,
     id: usize,
-    approximate_size: Arc<AtomicUsize>,
+}
+
+/// Entries of a memtable sorted by key, with at most one entry per key.
+type SortedEntries = Arc<Vec<(KeyBytes, Bytes)>>;
+
+/// Sort the entries by key. For duplicate keys, the entry written last is kept.
+fn sort_entries(mut entries: Vec<(KeyBytes, Bytes)>) -> Vec<(KeyBytes, Bytes)> {
+    // the sort is stable, so the last written entry is the last one of the duplicates
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut sorted: Vec<(KeyBytes, Bytes)> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match sorted.last_mut() {
+            Some(last) if last.0 == entry.0 => *last = entry,
+            _ => sorted.push(entry),
+        }
+    }
+    sorted
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -61,42 +143,60 @@ pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySl
 impl MemTable {
     /// Create a new mem-table.
     pub fn create(id: usize) -> Self {
+        Self::create_with_rep(id, MemTableRepType::default())
+    }
+
+    /// Create a new mem-table with the given implementation.
+    pub fn create_with_rep(id: usize, rep: MemTableRepType) -> Self {
         Self {
             id,
-            map: Arc::new(SkipMap::new()),
+            rep: rep.create(),
             wal: None,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     /// Create a new mem-table with WAL
     pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        Self::create_with_wal_and_rep(id, MemTableRepType::default(), path)
+    }
+
+    /// Create a new mem-table with the given implementation and WAL
+    pub fn create_with_wal_and_rep(
+        id: usize,
+        rep: MemTableRepType,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
         Ok(Self {
             id,
-            map: Arc::new(SkipMap::new()),
+            rep: rep.create(),
             wal: Some(Wal::create(path.as_ref())?),
-            approximate_size: Arc::new(AtomicUsize::new(0)),
         })
     }
 
     /// Create a memtable from WAL
     pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        let map = Arc::new(SkipMap::new());
+        Self::recover_from_wal_with_rep(id, MemTableRepType::default(), path)
+    }
+
+    /// Create a memtable with the given implementation from WAL
+    pub fn recover_from_wal_with_rep(
+        id: usize,
+        rep: MemTableRepType,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let rep = rep.create();
         Ok(Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
-            map,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
+            wal: Some(Wal::recover(path.as_ref(), |key, value| {
+                rep.put(key, value)
+            })?),
+            rep,
         })
     }
 
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
-        let key_bytes = KeyBytes::from_bytes_with_ts(
-            Bytes::from_static(unsafe { std::mem::transmute(key.key_ref()) }),
-            key.ts(),
-        );
-        self.map.get(&key_bytes).map(|e| e.value().clone())
+        self.rep.get(key)
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -123,13 +223,7 @@ impl MemTable {
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        let estimated_size = key.raw_len() + value.len();
-        self.map.insert(
-            key.to_key_vec().into_key_bytes(),
-            Bytes::copy_from_slice(value),
-        );
-        self.approximate_size
-            .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+        self.rep.put(key, value)?;
         if let Some(ref wal) = self.wal {
             wal.put(key, value)?;
         }
@@ -145,23 +239,17 @@ impl MemTable {
 
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
-        let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
-        let mut iter = MemTableIteratorBuilder {
-            map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
-            item: (KeyBytes::new(), Bytes::new()),
-        }
-        .build();
-        iter.next().unwrap();
-        iter
+        self.rep.scan(lower, upper)
+    }
+
+    /// Mark the mem-table as immutable.
+    pub fn freeze(&self) {
+        self.rep.freeze()
     }
 
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
-        for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
-        }
-        Ok(())
+        self.rep.flush(builder)
     }
 
     pub fn id(&self) -> usize {
@@ -169,45 +257,61 @@ impl MemTable {
     }
 
     pub fn approximate_size(&self) -> usize {
-        self.approximate_size
-            .load(std::sync::atomic::Ordering::Relaxed)
+        self.rep.approximate_size()
+    }
+
+    /// Get the largest timestamp of the keys in the mem-table.
+    pub fn max_ts(&self) -> Result<u64> {
+        let mut iter = self.scan(Bound::Unbounded, Bound::Unbounded);
+        let mut max_ts = 0;
+        while iter.is_valid() {
+            max_ts = max_ts.max(iter.key().ts());
+            iter.next()?;
+        }
+        Ok(max_ts)
     }
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.rep.is_empty()
     }
 }
 
-type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
-    'a,
-    KeyBytes,
-    (Bound<KeyBytes>, Bound<KeyBytes>),
-    KeyBytes,
-    Bytes,
->;
-
-/// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
-/// chapter for more information.
-///
-/// This is part of week 1, day 2.
-#[self_referencing]
-pub struct MemTableIterator {
-    /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<KeyBytes, Bytes>>,
-    /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
-    #[borrows(map)]
-    #[not_covariant]
-    iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (KeyBytes, Bytes),
+enum MemTableIteratorInner {
+    SkipMap(SkipMapIterator),
+    Arena(ArenaIterator),
+    Sorted {
+        entries: SortedEntries,
+        idx: usize,
+        end: usize,
+    },
 }
 
+/// An iterator over a range of a mem-table.
+pub struct MemTableIterator(MemTableIteratorInner);
+
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
-        entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
+    /// Create an iterator over the entries within the bounds.
+    fn create_sorted(
+        entries: SortedEntries,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+    ) -> Self {
+        let begin = match lower {
+            Bound::Included(key) => entries.partition_point(|(k, _)| k.as_key_slice() < key),
+            Bound::Excluded(key) => entries.partition_point(|(k, _)| k.as_key_slice() <= key),
+            Bound::Unbounded => 0,
+        };
+        let end = match upper {
+            Bound::Included(key) => entries.partition_point(|(k, _)| k.as_key_slice() <= key),
+            Bound::Excluded(key) => entries.partition_point(|(k, _)| k.as_key_slice() < key),
+            Bound::Unbounded => entries.len(),
+        };
+        Self(MemTableIteratorInner::Sorted {
+            entries,
+            idx: begin,
+            end: end.max(begin),
+        })
     }
 }
 
@@ -215,20 +319,35 @@ impl StorageIterator for MemTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        match &self.0 {
+            MemTableIteratorInner::SkipMap(iter) => &iter.item().1[..],
+            MemTableIteratorInner::Arena(iter) => iter.value(),
+            MemTableIteratorInner::Sorted { entries, idx, .. } => &entries[*idx].1[..],
+        }
     }
 
     fn key(&self) -> KeySlice {
-        self.borrow_item().0.as_key_slice()
+        match &self.0 {
+            MemTableIteratorInner::SkipMap(iter) => iter.item().0.as_key_slice(),
+            MemTableIteratorInner::Arena(iter) => iter.key(),
+            MemTableIteratorInner::Sorted { entries, idx, .. } => entries[*idx].0.as_key_slice(),
+        }
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        match &self.0 {
+            MemTableIteratorInner::SkipMap(iter) => !iter.item().0.is_empty(),
+            MemTableIteratorInner::Arena(iter) => iter.is_valid(),
+            MemTableIteratorInner::Sorted { idx, end, .. } => idx < end,
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        self.with_mut(|x| *x.item = entry);
+        match &mut self.0 {
+            MemTableIteratorInner::SkipMap(iter) => iter.next(),
+            MemTableIteratorInner::Arena(iter) => iter.next(),
+            MemTableIteratorInner::Sorted { idx, .. } => *idx += 1,
+        }
         Ok(())
     }
 }
@@ -0,0 +1,385 @@
+use std::alloc::Layout;
+use std::ops::Bound;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::{map_key_bound, MemTableIterator, MemTableIteratorInner, MemTableRep};
+use crate::key::{KeyBytes, KeySlice};
+use crate::table::SsTableBuilder;
+
+/// The size of the chunks allocated by the arena. Entries larger than that get a chunk of their own.
+const CHUNK_SIZE: usize = 256 << 10;
+const ALIGN: usize = std::mem::align_of::<Node>();
+const MAX_HEIGHT: usize = 12;
+/// Each level of the skiplist has 1/BRANCHING of the nodes of the level below.
+const BRANCHING: u32 = 4;
+
+/// A bump allocator handing out memory from large chunks. The memory is only freed when the arena
+/// is dropped.
+struct Arena {
+    chunks: Vec<(*mut u8, Layout)>,
+    /// The bytes used in the last chunk.
+    offset: usize,
+    /// The bytes of all chunks, including the unused tail of each chunk.
+    allocated: usize,
+}
+
+impl Arena {
+    fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            offset: 0,
+            allocated: 0,
+        }
+    }
+
+    /// Allocate `size` bytes aligned to `ALIGN`.
+    fn alloc(&mut self, size: usize) -> *mut u8 {
+        let size = size.next_multiple_of(ALIGN);
+        let remaining = self
+            .chunks
+            .last()
+            .map_or(0, |(_, layout)| layout.size() - self.offset);
+        if size > remaining {
+            let layout = Layout::from_size_align(size.max(CHUNK_SIZE), ALIGN).unwrap();
+            let chunk = unsafe { std::alloc::alloc(layout) };
+            if chunk.is_null() {
+                std::alloc::handle_alloc_error(layout);
+            }
+            self.chunks.push((chunk, layout));
+            self.offset = 0;
+            self.allocated += layout.size();
+        }
+        let ptr = unsafe { self.chunks.last().unwrap().0.add(self.offset) };
+        self.offset += size;
+        ptr
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (chunk, layout) in self.chunks.drain(..) {
+            unsafe { std::alloc::dealloc(chunk, layout) };
+        }
+    }
+}
+
+/// A skiplist node in the arena. It is followed by `height` next pointers and the key.
+#[repr(C)]
+struct Node {
+    /// Points to the value in the arena, which is the length as a `u32` followed by the bytes.
+    value: AtomicPtr<u8>,
+    ts: u64,
+    key_len: u32,
+    height: u32,
+}
+
+impl Node {
+    fn size(height: usize, key_len: usize) -> usize {
+        std::mem::size_of::<Node>() + height * std::mem::size_of::<AtomicPtr<Node>>() + key_len
+    }
+
+    fn tower(node: *const Node) -> *mut AtomicPtr<Node> {
+        unsafe { node.add(1) as *mut AtomicPtr<Node> }
+    }
+
+    fn key_ptr(node: *const Node, height: usize) -> *mut u8 {
+        unsafe { Self::tower(node).add(height) as *mut u8 }
+    }
+
+    fn next(&self, level: usize) -> &AtomicPtr<Node> {
+        debug_assert!(level < self.height as usize);
+        unsafe { &*Self::tower(self).add(level) }
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        let key = Self::key_ptr(self, self.height as usize);
+        KeySlice::from_slice(
+            unsafe { std::slice::from_raw_parts(key, self.key_len as usize) },
+            self.ts,
+        )
+    }
+
+    fn value(&self) -> &[u8] {
+        unsafe {
+            let value = self.value.load(Ordering::Acquire);
+            let len = (value as *const u32).read();
+            std::slice::from_raw_parts(value.add(std::mem::size_of::<u32>()), len as usize)
+        }
+    }
+}
+
+struct WriterState {
+    arena: Arena,
+    rng: u32,
+}
+
+/// A skiplist living in an arena, in the style of LevelDB. Writes are serialized by the writer
+/// lock, while reads go without locking. Nodes are never removed.
+struct ArenaSkipList {
+    head: *const Node,
+    /// The height of the tallest node.
+    height: AtomicUsize,
+    writer: Mutex<WriterState>,
+    /// The bytes of the chunks of the arena, read without taking the writer lock.
+    memory_usage: AtomicUsize,
+}
+
+// The nodes are only mutated through atomics after they are published, and the arena outlives
+// every reference to them.
+unsafe impl Send for ArenaSkipList {}
+unsafe impl Sync for ArenaSkipList {}
+
+impl ArenaSkipList {
+    fn new() -> Self {
+        let mut arena = Arena::new();
+        let head = Self::alloc_node(&mut arena, MAX_HEIGHT, KeySlice::from_slice(b"", 0));
+        Self {
+            head,
+            height: AtomicUsize::new(1),
+            memory_usage: AtomicUsize::new(arena.allocated),
+            writer: Mutex::new(WriterState {
+                arena,
+                rng: 0xdead_beef,
+            }),
+        }
+    }
+
+    fn alloc_node(arena: &mut Arena, height: usize, key: KeySlice) -> *const Node {
+        let node = arena.alloc(Node::size(height, key.key_len())) as *mut Node;
+        unsafe {
+            node.write(Node {
+                value: AtomicPtr::new(ptr::null_mut()),
+                ts: key.ts(),
+                key_len: key.key_len() as u32,
+                height: height as u32,
+            });
+            let tower = Node::tower(node);
+            for level in 0..height {
+                tower.add(level).write(AtomicPtr::new(ptr::null_mut()));
+            }
+            ptr::copy_nonoverlapping(
+                key.key_ref().as_ptr(),
+                Node::key_ptr(node, height),
+                key.key_len(),
+            );
+        }
+        node
+    }
+
+    fn alloc_value(arena: &mut Arena, value: &[u8]) -> *mut u8 {
+        let ptr = arena.alloc(std::mem::size_of::<u32>() + value.len());
+        unsafe {
+            (ptr as *mut u32).write(value.len() as u32);
+            ptr::copy_nonoverlapping(
+                value.as_ptr(),
+                ptr.add(std::mem::size_of::<u32>()),
+                value.len(),
+            );
+        }
+        ptr
+    }
+
+    fn random_height(rng: &mut u32) -> usize {
+        let mut height = 1;
+        while height < MAX_HEIGHT {
+            // xorshift
+            *rng ^= *rng << 13;
+            *rng ^= *rng >> 17;
+            *rng ^= *rng << 5;
+            if !rng.is_multiple_of(BRANCHING) {
+                break;
+            }
+            height += 1;
+        }
+        height
+    }
+
+    /// Find the first node whose key is >= `key`, or > `key` if `inclusive` is false. Fills `prev`
+    /// with the last node before it on each level.
+    fn seek(
+        &self,
+        key: KeySlice,
+        inclusive: bool,
+        mut prev: Option<&mut [*const Node; MAX_HEIGHT]>,
+    ) -> *const Node {
+        let mut node = self.head;
+        let mut level = self.height.load(Ordering::Relaxed) - 1;
+        loop {
+            let next = unsafe { (*node).next(level).load(Ordering::Acquire) };
+            let before = !next.is_null() && {
+                let next_key = unsafe { (*next).key() };
+                next_key < key || (!inclusive && next_key == key)
+            };
+            if before {
+                node = next;
+            } else {
+                if let Some(prev) = prev.as_mut() {
+                    prev[level] = node;
+                }
+                if level == 0 {
+                    return next;
+                }
+                level -= 1;
+            }
+        }
+    }
+
+    fn first(&self) -> *const Node {
+        unsafe { (*self.head).next(0).load(Ordering::Acquire) }
+    }
+
+    fn put(&self, key: KeySlice, value: &[u8]) {
+        let mut writer = self.writer.lock();
+        let WriterState { arena, rng } = &mut *writer;
+        let mut prev = [ptr::null(); MAX_HEIGHT];
+        let found = self.seek(key, true, Some(&mut prev));
+        let value = Self::alloc_value(arena, value);
+        if !found.is_null() && unsafe { (*found).key() } == key {
+            // the old value stays in the arena until the memtable is dropped
+            unsafe { (*found).value.store(value, Ordering::Release) };
+        } else {
+            let height = Self::random_height(rng);
+            let current_height = self.height.load(Ordering::Relaxed);
+            if height > current_height {
+                for level in prev.iter_mut().take(height).skip(current_height) {
+                    *level = self.head;
+                }
+                self.height.store(height, Ordering::Relaxed);
+            }
+            let node = Self::alloc_node(arena, height, key);
+            unsafe {
+                (*node).value.store(value, Ordering::Relaxed);
+                for (level, prev) in prev.iter().enumerate().take(height) {
+                    let next = (**prev).next(level).load(Ordering::Relaxed);
+                    (*node).next(level).store(next, Ordering::Relaxed);
+                    // publish the node after it is fully initialized
+                    (**prev)
+                        .next(level)
+                        .store(node as *mut Node, Ordering::Release);
+                }
+            }
+        }
+        self.memory_usage.store(arena.allocated, Ordering::Relaxed);
+    }
+}
+
+/// A memtable allocating the skiplist nodes, keys and values from large chunks. The reported size
+/// is the memory of the chunks, including the node overhead and the unused tail of each chunk,
+/// and dropping the memtable frees the chunks at once.
+pub struct ArenaMemTable {
+    list: Arc<ArenaSkipList>,
+}
+
+impl Default for ArenaMemTable {
+    fn default() -> Self {
+        Self {
+            list: Arc::new(ArenaSkipList::new()),
+        }
+    }
+}
+
+impl MemTableRep for ArenaMemTable {
+    fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.list.put(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        let node = self.list.seek(key, true, None);
+        if node.is_null() {
+            return None;
+        }
+        let node = unsafe { &*node };
+        (node.key() == key).then(|| Bytes::copy_from_slice(node.value()))
+    }
+
+    fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        let node = match lower {
+            Bound::Included(key) => self.list.seek(key, true, None),
+            Bound::Excluded(key) => self.list.seek(key, false, None),
+            Bound::Unbounded => self.list.first(),
+        };
+        let mut iter = ArenaIterator {
+            _list: self.list.clone(),
+            node,
+            upper: map_key_bound(upper),
+        };
+        iter.check_upper_bound();
+        MemTableIterator(MemTableIteratorInner::Arena(iter))
+    }
+
+    fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
+        let mut node = self.list.first();
+        while let Some(current) = unsafe { node.as_ref() } {
+            builder.add(current.key(), current.value());
+            node = current.next(0).load(Ordering::Acquire);
+        }
+        Ok(())
+    }
+
+    fn approximate_size(&self) -> usize {
+        self.list.memory_usage.load(Ordering::Relaxed)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.first().is_null()
+    }
+}
+
+/// An iterator over a range of an [`ArenaMemTable`], which keeps the arena alive.
+pub struct ArenaIterator {
+    /// Keeps the arena alive.
+    _list: Arc<ArenaSkipList>,
+    /// The current node, null if the iterator is exhausted.
+    node: *const Node,
+    upper: Bound<KeyBytes>,
+}
+
+// The iterator only reads nodes of the arena it keeps alive.
+unsafe impl Send for ArenaIterator {}
+unsafe impl Sync for ArenaIterator {}
+
+impl ArenaIterator {
+    fn current(&self) -> &Node {
+        debug_assert!(!self.node.is_null());
+        unsafe { &*self.node }
+    }
+
+    fn check_upper_bound(&mut self) {
+        if self.node.is_null() {
+            return;
+        }
+        let key = self.current().key();
+        let within = match &self.upper {
+            Bound::Included(upper) => key <= upper.as_key_slice(),
+            Bound::Excluded(upper) => key < upper.as_key_slice(),
+            Bound::Unbounded => true,
+        };
+        if !within {
+            self.node = ptr::null();
+        }
+    }
+
+    pub(super) fn key(&self) -> KeySlice<'_> {
+        self.current().key()
+    }
+
+    pub(super) fn value(&self) -> &[u8] {
+        self.current().value()
+    }
+
+    pub(super) fn is_valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    pub(super) fn next(&mut self) {
+        self.node = self.current().next(0).load(Ordering::Acquire);
+        self.check_upper_bound();
+    }
+}
@@ -0,0 +1,116 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use super::{sort_entries, MemTableIterator, MemTableRep, SortedEntries};
+use crate::key::{KeyBytes, KeySlice};
+
+/// A memtable indexing the versions of each key by a hash map. Reads of a single key, including
+/// the point lookups of the storage engine, do not touch other keys, while range scans sort the
+/// whole memtable until it is frozen.
+#[derive(Default)]
+pub struct HashMemTable {
+    /// Maps a key to its versions, latest first.
+    map: RwLock<HashMap<Bytes, BTreeMap<Reverse<u64>, Bytes>>>,
+    /// All entries sorted when the memtable is frozen.
+    sorted: OnceLock<SortedEntries>,
+    approximate_size: AtomicUsize,
+}
+
+impl HashMemTable {
+    /// All entries sorted, which are cached once the memtable is frozen.
+    fn sorted_entries(&self) -> SortedEntries {
+        if let Some(entries) = self.sorted.get() {
+            return entries.clone();
+        }
+        Self::sort_map(&self.map.read())
+    }
+
+    fn sort_map(map: &HashMap<Bytes, BTreeMap<Reverse<u64>, Bytes>>) -> SortedEntries {
+        let entries = map
+            .iter()
+            .flat_map(|(key, versions)| {
+                versions.iter().map(|(ts, value)| {
+                    (
+                        KeyBytes::from_bytes_with_ts(key.clone(), ts.0),
+                        value.clone(),
+                    )
+                })
+            })
+            .collect();
+        Arc::new(sort_entries(entries))
+    }
+}
+
+impl MemTableRep for HashMemTable {
+    fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        let estimated_size = key.raw_len() + value.len();
+        let mut map = self.map.write();
+        // the sorted entries cached by `freeze` would miss the put
+        if self.sorted.get().is_some() {
+            bail!("put into a frozen memtable");
+        }
+        map.entry(Bytes::copy_from_slice(key.key_ref()))
+            .or_default()
+            .insert(Reverse(key.ts()), Bytes::copy_from_slice(value));
+        self.approximate_size
+            .fetch_add(estimated_size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        self.map
+            .read()
+            .get(key.key_ref())?
+            .get(&Reverse(key.ts()))
+            .cloned()
+    }
+
+    fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        let point_key = match (lower, upper) {
+            (
+                Bound::Included(lower) | Bound::Excluded(lower),
+                Bound::Included(upper) | Bound::Excluded(upper),
+            ) if lower.key_ref() == upper.key_ref() => Some(lower.key_ref()),
+            _ => None,
+        };
+        let Some(point_key) = point_key else {
+            return MemTableIterator::create_sorted(self.sorted_entries(), lower, upper);
+        };
+        // all versions in the range belong to one key, which are already sorted
+        let entries = match self.map.read().get(point_key) {
+            Some(versions) => versions
+                .iter()
+                .map(|(ts, value)| {
+                    (
+                        KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(point_key), ts.0),
+                        value.clone(),
+                    )
+                })
+                .filter(|(key, _)| (lower, upper).contains(&key.as_key_slice()))
+                .collect(),
+            None => Vec::new(),
+        };
+        MemTableIterator::create_sorted(Arc::new(entries), Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn freeze(&self) {
+        // no put can slip in between sorting and caching the entries
+        let map = self.map.write();
+        self.sorted.get_or_init(|| Self::sort_map(&map));
+    }
+
+    fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.read().is_empty()
+    }
+}
@@ -0,0 +1,109 @@
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use crossbeam_skiplist::map::Entry;
+use crossbeam_skiplist::SkipMap;
+use ouroboros::self_referencing;
+
+use super::{map_key_bound, MemTableIterator, MemTableIteratorInner, MemTableRep};
+use crate::key::{KeyBytes, KeySlice};
+use crate::table::SsTableBuilder;
+
+/// A memtable based on crossbeam-skiplist, which supports concurrent reads and writes.
+#[derive(Default)]
+pub struct SkipListMemTable {
+    map: Arc<SkipMap<KeyBytes, Bytes>>,
+    approximate_size: AtomicUsize,
+}
+
+impl MemTableRep for SkipListMemTable {
+    fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        let estimated_size = key.raw_len() + value.len();
+        self.map.insert(
+            key.to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(value),
+        );
+        self.approximate_size
+            .fetch_add(estimated_size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        let key_bytes = KeyBytes::from_bytes_with_ts(
+            Bytes::from_static(unsafe { std::mem::transmute(key.key_ref()) }),
+            key.ts(),
+        );
+        self.map.get(&key_bytes).map(|e| e.value().clone())
+    }
+
+    fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
+        let mut iter = SkipMapIteratorBuilder {
+            map: self.map.clone(),
+            iter_builder: |map| map.range((lower, upper)),
+            item: (KeyBytes::new(), Bytes::new()),
+        }
+        .build();
+        iter.next();
+        MemTableIterator(MemTableIteratorInner::SkipMap(iter))
+    }
+
+    fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
+        for entry in self.map.iter() {
+            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+        }
+        Ok(())
+    }
+
+    fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    KeyBytes,
+    (Bound<KeyBytes>, Bound<KeyBytes>),
+    KeyBytes,
+    Bytes,
+>;
+
+/// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
+/// chapter for more information.
+///
+/// This is part of week 1, day 2.
+#[self_referencing]
+pub struct SkipMapIterator {
+    /// Stores a reference to the skipmap.
+    map: Arc<SkipMap<KeyBytes, Bytes>>,
+    /// Stores a skipmap iterator that refers to the lifetime of `SkipMapIterator` itself.
+    #[borrows(map)]
+    #[not_covariant]
+    iter: SkipMapRangeIter<'this>,
+    /// Stores the current key-value pair.
+    item: (KeyBytes, Bytes),
+}
+
+impl SkipMapIterator {
+    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
+        entry
+            .map(|x| (x.key().clone(), x.value().clone()))
+            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
+    }
+
+    pub(super) fn item(&self) -> &(KeyBytes, Bytes) {
+        self.borrow_item()
+    }
+
+    pub(super) fn next(&mut self) {
+        let entry = self.with_iter_mut(|iter| SkipMapIterator::entry_to_item(iter.next()));
+        self.with_mut(|x| *x.item = entry);
+    }
+}
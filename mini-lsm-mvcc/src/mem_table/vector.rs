@@ -0,0 +1,106 @@
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use super::{sort_entries, MemTableIterator, MemTableRep, SortedEntries};
+use crate::key::{KeyBytes, KeySlice};
+
+enum VectorState {
+    /// Entries in the order they are written, and the entries sorted on the first read after the
+    /// last write.
+    Unsorted {
+        entries: Vec<(KeyBytes, Bytes)>,
+        sorted: OnceLock<SortedEntries>,
+    },
+    /// Entries sorted when the memtable is frozen.
+    Sorted(SortedEntries),
+}
+
+impl VectorState {
+    /// The entries sorted by key, which are shared by the reads until the next write.
+    fn sorted(&self) -> SortedEntries {
+        match self {
+            VectorState::Unsorted { entries, sorted } => sorted
+                .get_or_init(|| Arc::new(sort_entries(entries.clone())))
+                .clone(),
+            VectorState::Sorted(entries) => entries.clone(),
+        }
+    }
+}
+
+/// A memtable appending the entries to a vector, which is sorted when the memtable is frozen. Writes
+/// are cheap, while the first read of the mutable memtable after a write sorts a copy of the whole
+/// vector, so it suits bulk loads which rarely read until the memtable is frozen.
+pub struct VectorMemTable {
+    state: RwLock<VectorState>,
+    approximate_size: AtomicUsize,
+}
+
+impl Default for VectorMemTable {
+    fn default() -> Self {
+        Self {
+            state: RwLock::new(VectorState::Unsorted {
+                entries: Vec::new(),
+                sorted: OnceLock::new(),
+            }),
+            approximate_size: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableRep for VectorMemTable {
+    fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        let estimated_size = key.raw_len() + value.len();
+        match &mut *self.state.write() {
+            VectorState::Unsorted { entries, sorted } => {
+                entries.push((
+                    key.to_key_vec().into_key_bytes(),
+                    Bytes::copy_from_slice(value),
+                ));
+                sorted.take();
+            }
+            VectorState::Sorted(_) => bail!("put into a frozen memtable"),
+        }
+        self.approximate_size
+            .fetch_add(estimated_size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        let entries = self.state.read().sorted();
+        entries
+            .binary_search_by(|(k, _)| k.as_key_slice().cmp(&key))
+            .ok()
+            .map(|idx| entries[idx].1.clone())
+    }
+
+    fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        MemTableIterator::create_sorted(self.state.read().sorted(), lower, upper)
+    }
+
+    fn freeze(&self) {
+        let mut state = self.state.write();
+        if let VectorState::Unsorted { entries, sorted } = &mut *state {
+            let entries = match sorted.take() {
+                Some(sorted) => sorted,
+                None => Arc::new(sort_entries(std::mem::take(entries))),
+            };
+            *state = VectorState::Sorted(entries);
+        }
+    }
+
+    fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    fn is_empty(&self) -> bool {
+        match &*self.state.read() {
+            VectorState::Unsorted { entries, .. } => entries.is_empty(),
+            VectorState::Sorted(entries) => entries.is_empty(),
+        }
+    }
+}
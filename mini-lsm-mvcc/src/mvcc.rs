@@ -5,12 +5,12 @@ pub mod txn;
 pub mod watermark;
 
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     sync::{atomic::AtomicBool, Arc},
 };
 
 use crossbeam_skiplist::SkipMap;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 
 use crate::lsm_storage::LsmStorageInner;
 
@@ -24,8 +24,18 @@ pub(crate) struct CommittedTxnData {
     pub(crate) commit_ts: u64,
 }
 
+/// The commit timestamps handed out to write batches which are not visible yet.
+struct CommitPipeline {
+    /// The last commit timestamp allocated.
+    allocated_ts: u64,
+    /// Batches which have finished writing, but wait for an earlier batch to finish.
+    finished: BTreeSet<u64>,
+}
+
 pub(crate) struct LsmMvccInner {
-    pub(crate) write_lock: Mutex<()>,
+    pipeline: Mutex<CommitPipeline>,
+    /// Notified when the latest commit timestamp moves forward.
+    published: Condvar,
     pub(crate) commit_lock: Mutex<()>,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
@@ -34,7 +44,11 @@ pub(crate) struct LsmMvccInner {
 impl LsmMvccInner {
     pub fn new(initial_ts: u64) -> Self {
         Self {
-            write_lock: Mutex::new(()),
+            pipeline: Mutex::new(CommitPipeline {
+                allocated_ts: initial_ts,
+                finished: BTreeSet::new(),
+            }),
+            published: Condvar::new(),
             commit_lock: Mutex::new(()),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
@@ -45,8 +59,33 @@ impl LsmMvccInner {
         self.ts.lock().0
     }
 
+    /// Allocate the commit timestamp of a write batch. Each allocated timestamp must be passed to
+    /// [`LsmMvccInner::update_commit_ts`] once the batch is written.
+    pub fn allocate_commit_ts(&self) -> u64 {
+        let mut pipeline = self.pipeline.lock();
+        pipeline.allocated_ts += 1;
+        pipeline.allocated_ts
+    }
+
+    /// Mark the batch at `ts` as written. The latest commit timestamp only moves forward over
+    /// batches whose earlier batches have all finished, so that readers never observe gaps. Blocks
+    /// until `ts` is visible.
     pub fn update_commit_ts(&self, ts: u64) {
-        self.ts.lock().0 = ts;
+        let mut pipeline = self.pipeline.lock();
+        pipeline.finished.insert(ts);
+        let mut published = self.ts.lock();
+        let old_ts = published.0;
+        while pipeline.finished.first() == Some(&(published.0 + 1)) {
+            pipeline.finished.pop_first();
+            published.0 += 1;
+        }
+        if published.0 != old_ts {
+            self.published.notify_all();
+        }
+        drop(published);
+        while self.ts.lock().0 < ts {
+            self.published.wait(&mut pipeline);
+        }
     }
 
     /// All ts (strictly) below this ts can be garbage collected.
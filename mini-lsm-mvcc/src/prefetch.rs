@@ -0,0 +1,53 @@
+use std::fmt::Debug;
+
+use crossbeam_channel::{Receiver, Sender};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads reading ahead for iterators, e.g., opening the next SSTs of a sorted
+/// run. The threads exit once the pool is dropped and the queued jobs are done.
+pub struct PrefetchPool {
+    num_threads: usize,
+    sender: Sender<Job>,
+}
+
+impl PrefetchPool {
+    pub fn new(num_threads: usize) -> Self {
+        let num_threads = num_threads.max(1);
+        let (sender, receiver) = crossbeam_channel::unbounded::<Job>();
+        for _ in 0..num_threads {
+            let receiver = receiver.clone();
+            std::thread::spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            });
+        }
+        Self {
+            num_threads,
+            sender,
+        }
+    }
+
+    /// Run `f` on the pool. The result is sent to the returned receiver, and dropped if the
+    /// receiver is dropped first.
+    pub fn spawn<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> Receiver<T> {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        // the threads only exit after the sender is dropped
+        self.sender
+            .send(Box::new(move || {
+                tx.send(f()).ok();
+            }))
+            .unwrap();
+        rx
+    }
+}
+
+impl Debug for PrefetchPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrefetchPool")
+            .field("num_threads", &self.num_threads)
+            .field("queued", &self.sender.len())
+            .finish()
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// The interval of refilling the tokens. The tokens never accumulate beyond one interval worth of
+/// bytes, which bounds the burst after the limiter is idle.
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+/// With auto-tuning, the rate never drops below this fraction of the maximum rate.
+const AUTO_TUNE_MIN_RATE_DIVISOR: u64 = 20;
+
+/// The priority of an I/O request. Waiting high-priority requests are granted before any
+/// low-priority request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Compaction.
+    Low,
+    /// Flush.
+    High,
+}
+
+/// A snapshot of the rate limiter statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimiterStats {
+    /// The current rate in bytes per second.
+    pub bytes_per_sec: u64,
+    /// The bytes granted to high-priority requests.
+    pub high_priority_bytes: u64,
+    /// The bytes granted to low-priority requests.
+    pub low_priority_bytes: u64,
+    /// The total time requests waited for tokens.
+    pub wait_duration: Duration,
+}
+
+struct RateLimiterState {
+    bytes_per_sec: u64,
+    /// The bytes that can be granted without waiting.
+    available: u64,
+    last_refill: Instant,
+    /// The number of high-priority requests waiting for tokens.
+    waiting_high: usize,
+    stats: RateLimiterStats,
+}
+
+impl RateLimiterState {
+    /// The most bytes granted at once, which is also the most tokens kept.
+    fn burst_bytes(&self) -> u64 {
+        (self.bytes_per_sec * REFILL_PERIOD.as_millis() as u64 / 1000).max(1)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill);
+        let refilled = (self.bytes_per_sec as u128 * elapsed.as_micros() / 1_000_000) as u64;
+        if refilled > 0 {
+            self.available = (self.available + refilled).min(self.burst_bytes());
+            self.last_refill = now;
+        }
+    }
+
+    /// The time until `bytes` tokens are available at the current rate.
+    fn time_until_available(&self, bytes: u64) -> Duration {
+        let missing = bytes.saturating_sub(self.available);
+        Duration::from_micros(missing * 1_000_000 / self.bytes_per_sec.max(1)).min(REFILL_PERIOD)
+    }
+}
+
+/// A token bucket limiting the bytes per second written by flush and compaction and read by
+/// compaction, so that background I/O does not saturate the disk. Share it between instances by
+/// passing the same limiter in `LsmStorageOptions::rate_limiter`.
+///
+/// With [`RateLimiter::new_auto_tuned`], the rate follows the pending compaction bytes of all
+/// instances sharing the limiter: it stays low while compaction keeps up and grows towards the
+/// maximum as compaction falls behind.
+pub struct RateLimiter {
+    state: Mutex<RateLimiterState>,
+    /// Notified when a request is granted, so that waiting requests re-check their turn.
+    granted: Condvar,
+    /// The maximum rate and the pending compaction bytes at which it is reached, if auto-tuned.
+    auto_tune: Option<(u64, u64)>,
+    /// The pending compaction bytes of all instances.
+    pending_compaction_bytes: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Create a limiter granting `bytes_per_sec` bytes per second.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        assert!(bytes_per_sec > 0, "rate must be positive");
+        let mut state = RateLimiterState {
+            bytes_per_sec,
+            available: 0,
+            last_refill: Instant::now(),
+            waiting_high: 0,
+            stats: RateLimiterStats::default(),
+        };
+        state.available = state.burst_bytes();
+        Self {
+            state: Mutex::new(state),
+            granted: Condvar::new(),
+            auto_tune: None,
+            pending_compaction_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a limiter whose rate grows linearly from 1/20 of `max_bytes_per_sec` with no
+    /// pending compaction to `max_bytes_per_sec` with `max_rate_pending_bytes` pending compaction
+    /// bytes.
+    pub fn new_auto_tuned(max_bytes_per_sec: u64, max_rate_pending_bytes: u64) -> Self {
+        let limiter = Self {
+            auto_tune: Some((max_bytes_per_sec, max_rate_pending_bytes.max(1))),
+            ..Self::new(max_bytes_per_sec)
+        };
+        limiter.tune(0);
+        limiter
+    }
+
+    /// Change the rate. Ignored if the limiter is auto-tuned.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) {
+        assert!(bytes_per_sec > 0, "rate must be positive");
+        if self.auto_tune.is_none() {
+            self.set_rate(bytes_per_sec);
+        }
+    }
+
+    fn set_rate(&self, bytes_per_sec: u64) {
+        let mut state = self.state.lock();
+        state.refill();
+        state.bytes_per_sec = bytes_per_sec;
+        state.available = state.available.min(state.burst_bytes());
+    }
+
+    pub(crate) fn register(self: &Arc<Self>) -> RateLimiterHandle {
+        RateLimiterHandle {
+            limiter: self.clone(),
+            pending_compaction_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Adapt the rate of an auto-tuned limiter to the bytes compaction needs to rewrite to catch
+    /// up, summed over all instances.
+    fn tune(&self, pending_bytes: u64) {
+        let Some((max_bytes_per_sec, max_rate_pending_bytes)) = self.auto_tune else {
+            return;
+        };
+        let min_bytes_per_sec = (max_bytes_per_sec / AUTO_TUNE_MIN_RATE_DIVISOR).max(1);
+        let ratio = pending_bytes.min(max_rate_pending_bytes) as u128;
+        let extra = (max_bytes_per_sec - min_bytes_per_sec) as u128 * ratio
+            / max_rate_pending_bytes as u128;
+        self.set_rate(min_bytes_per_sec + extra as u64);
+    }
+
+    /// Wait until `bytes` bytes of I/O are allowed. Large requests are granted in chunks of at
+    /// most one refill period worth of bytes.
+    pub fn request(&self, bytes: u64, priority: IoPriority) {
+        let mut remaining = bytes;
+        while remaining > 0 {
+            remaining -= self.request_chunk(remaining, priority);
+        }
+    }
+
+    /// Wait for up to `bytes` tokens and return the number of tokens granted.
+    fn request_chunk(&self, bytes: u64, priority: IoPriority) -> u64 {
+        let start = Instant::now();
+        let mut state = self.state.lock();
+        if priority == IoPriority::High {
+            state.waiting_high += 1;
+        }
+        let granted = loop {
+            state.refill();
+            let chunk = bytes.min(state.burst_bytes());
+            let turn = priority == IoPriority::High || state.waiting_high == 0;
+            if turn && state.available >= chunk {
+                state.available -= chunk;
+                break chunk;
+            }
+            let timeout = if turn {
+                state.time_until_available(chunk)
+            } else {
+                REFILL_PERIOD
+            };
+            self.granted.wait_for(&mut state, timeout);
+        };
+        if priority == IoPriority::High {
+            state.waiting_high -= 1;
+        }
+        match priority {
+            IoPriority::High => state.stats.high_priority_bytes += granted,
+            IoPriority::Low => state.stats.low_priority_bytes += granted,
+        }
+        state.stats.wait_duration += start.elapsed();
+        drop(state);
+        self.granted.notify_all();
+        granted
+    }
+
+    /// The current rate in bytes per second.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.state.lock().bytes_per_sec
+    }
+
+    pub fn stats(&self) -> RateLimiterStats {
+        let state = self.state.lock();
+        RateLimiterStats {
+            bytes_per_sec: state.bytes_per_sec,
+            ..state.stats
+        }
+    }
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("bytes_per_sec", &self.bytes_per_sec())
+            .field("auto_tune", &self.auto_tune.is_some())
+            .finish()
+    }
+}
+
+/// The share of one storage instance in a rate limiter.
+pub(crate) struct RateLimiterHandle {
+    limiter: Arc<RateLimiter>,
+    pending_compaction_bytes: AtomicU64,
+}
+
+impl RateLimiterHandle {
+    /// Report the bytes compaction of the instance needs to rewrite to catch up. Called by the
+    /// storage whenever the LSM state changes.
+    pub(crate) fn update_pending_compaction_bytes(&self, pending_bytes: u64) {
+        let old_bytes = self
+            .pending_compaction_bytes
+            .swap(pending_bytes, Ordering::SeqCst);
+        let total = &self.limiter.pending_compaction_bytes;
+        if pending_bytes >= old_bytes {
+            total.fetch_add(pending_bytes - old_bytes, Ordering::SeqCst);
+        } else {
+            total.fetch_sub(old_bytes - pending_bytes, Ordering::SeqCst);
+        }
+        self.limiter.tune(total.load(Ordering::SeqCst));
+    }
+}
+
+impl Drop for RateLimiterHandle {
+    fn drop(&mut self) {
+        self.update_pending_compaction_bytes(0);
+    }
+}
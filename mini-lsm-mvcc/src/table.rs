@@ -1,22 +1,36 @@
 pub(crate) mod bloom;
 mod builder;
 mod iterator;
+mod table_cache;
 
 use std::fs::File;
-use std::path::Path;
-use std::sync::Arc;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
-pub use iterator::SsTableIterator;
+use bytes::{Buf, BufMut, Bytes};
+pub use iterator::{ReadOptions, SsTableIterator};
+use memmap2::Mmap;
+pub use table_cache::{TableCache, TableCacheStats};
 
 use crate::block::Block;
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::rate_limiter::{IoPriority, RateLimiter};
 
 use self::bloom::Bloom;
 
+/// Starts the block metas written with a format version, as no SST has this many blocks. The
+/// block metas of the course format start with the number of blocks instead.
+const BLOCK_META_VERSION_MARKER: u32 = u32::MAX;
+
+/// The format version of the block metas. The course format is version 0, version 1 adds whether
+/// values are separated, and version 2 adds the number of entries and deletes.
+const BLOCK_META_VERSION: u8 = 2;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
     /// Offset of this data block.
@@ -29,8 +43,17 @@ pub struct BlockMeta {
 
 impl BlockMeta {
     /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
-        let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        max_ts: u64,
+        value_separated: bool,
+        num_entries: u32,
+        num_deletes: u32,
+        buf: &mut Vec<u8>,
+    ) {
+        let mut estimated_size = std::mem::size_of::<u32>(); // version marker
+        estimated_size += std::mem::size_of::<u8>(); // version
+        estimated_size += std::mem::size_of::<u32>(); // number of blocks
         for meta in block_meta {
             // The size of offset
             estimated_size += std::mem::size_of::<u32>();
@@ -44,12 +67,17 @@ impl BlockMeta {
             estimated_size += meta.last_key.raw_len();
         }
         estimated_size += std::mem::size_of::<u64>(); // max timestamp
+        estimated_size += std::mem::size_of::<u8>(); // value separated
+        estimated_size += std::mem::size_of::<u32>(); // number of entries
+        estimated_size += std::mem::size_of::<u32>(); // number of deletes
         estimated_size += std::mem::size_of::<u32>(); // checksum
 
         // Reserve the space to improve performance, especially when the size of incoming data is
         // large
         buf.reserve(estimated_size);
         let original_len = buf.len();
+        buf.put_u32(BLOCK_META_VERSION_MARKER);
+        buf.put_u8(BLOCK_META_VERSION);
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
@@ -61,15 +89,30 @@ impl BlockMeta {
             buf.put_u64(meta.last_key.ts());
         }
         buf.put_u64(max_ts);
+        buf.put_u8(value_separated as u8);
+        buf.put_u32(num_entries);
+        buf.put_u32(num_deletes);
         buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    /// Decode block meta from a buffer. The keys refer to `buf` without copying. Returns the block
+    /// meta, the max timestamp, whether values are separated, and the number of entries and
+    /// deletes. The fields missing in older format versions are false or 0.
+    pub fn decode_block_meta(mut buf: Bytes) -> Result<(Vec<BlockMeta>, u64, bool, u32, u32)> {
         let mut block_meta = Vec::new();
-        let num = buf.get_u32() as usize;
+        let mut num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
+        let version = if num == BLOCK_META_VERSION_MARKER as usize {
+            let version = buf.get_u8();
+            num = buf.get_u32() as usize;
+            version
+        } else {
+            0
+        };
+        if version > BLOCK_META_VERSION {
+            bail!("unsupported block meta version {}", version);
+        }
         for _ in 0..num {
             let offset = buf.get_u32() as usize;
             let first_key_len = buf.get_u16() as usize;
@@ -85,92 +128,431 @@ impl BlockMeta {
             });
         }
         let max_ts = buf.get_u64();
+        let value_separated = version >= 1 && buf.get_u8() != 0;
+        let (num_entries, num_deletes) = if version >= 2 {
+            (buf.get_u32(), buf.get_u32())
+        } else {
+            (0, 0)
+        };
         if buf.get_u32() != checksum {
             bail!("meta checksum mismatched");
         }
 
-        Ok((block_meta, max_ts))
+        Ok((
+            block_meta,
+            max_ts,
+            value_separated,
+            num_entries,
+            num_deletes,
+        ))
     }
 }
 
+/// The size of each write of a new file, which waits for its tokens if the file is rate-limited.
+const FILE_WRITE_SIZE: usize = 64 << 10;
+
 /// A file object.
-pub struct FileObject(Option<File>, u64);
+///
+/// A file object either reads the file with `pread`, or maps the whole file into memory. In the
+/// mmap mode, reads return slices of the mapping without copying. The mapping is reference-counted
+/// by the `Bytes` handed out, so it is unmapped only after the last block, bloom filter or key
+/// referring to it is dropped. This is safe even if the file is removed by a compaction while
+/// iterators still hold the SST, as the kernel keeps the pages of an unlinked file until unmap.
+pub struct FileObject {
+    file: Option<File>,
+    mmap: Option<Bytes>,
+    size: u64,
+    /// The last modification time of the file, which is when it was written.
+    modified: SystemTime,
+}
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if self.mmap.is_some() {
+            return Ok(self.read_bytes(offset, len)?.to_vec());
+        }
         use std::os::unix::fs::FileExt;
         let mut data = vec![0; len as usize];
-        self.0
+        self.file
             .as_ref()
             .unwrap()
             .read_exact_at(&mut data[..], offset)?;
         Ok(data)
     }
 
+    /// Read a range of the file. In mmap mode, the returned buffer points into the mapping.
+    pub fn read_bytes(&self, offset: u64, len: u64) -> Result<Bytes> {
+        if offset + len > self.size {
+            bail!(
+                "read out of range: offset={}, len={}, size={}",
+                offset,
+                len,
+                self.size
+            );
+        }
+        if let Some(ref mmap) = self.mmap {
+            return Ok(mmap.slice(offset as usize..(offset + len) as usize));
+        }
+        Ok(self.read(offset, len)?.into())
+    }
+
     pub fn size(&self) -> u64 {
-        self.1
+        self.size
     }
 
-    /// Create a new file object (day 2) and write the file to the disk (day 4).
-    pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
-        Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
-            data.len() as u64,
-        ))
+    /// The last modification time of the file.
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+
+    pub fn is_mmap(&self) -> bool {
+        self.mmap.is_some()
+    }
+
+    /// Create a new file object (day 2) and write the file to the disk (day 4). With a rate
+    /// limiter, the file is written in chunks and each chunk waits for its tokens first. The file
+    /// is mapped into memory if `enable_mmap` is set.
+    pub fn create(
+        path: &Path,
+        data: Vec<u8>,
+        enable_mmap: bool,
+        rate_limiter: Option<(&RateLimiter, IoPriority)>,
+    ) -> Result<Self> {
+        let mut file = File::create(path)?;
+        for chunk in data.chunks(FILE_WRITE_SIZE) {
+            if let Some((rate_limiter, priority)) = rate_limiter {
+                rate_limiter.request(chunk.len() as u64, priority);
+            }
+            file.write_all(chunk)?;
+        }
+        file.sync_all()?;
+        Self::open_with_mode(path, enable_mmap)
     }
 
     pub fn open(path: &Path) -> Result<Self> {
         let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
-        Ok(FileObject(Some(file), size))
+        let metadata = file.metadata()?;
+        Ok(FileObject {
+            file: Some(file),
+            mmap: None,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    /// Open a file and map it into memory. The file descriptor is closed once the file is mapped.
+    pub fn open_mmap(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).write(false).open(path)?;
+        let metadata = file.metadata()?;
+        // SAFETY: SST files are immutable once written, so the mapping never observes a
+        // concurrent modification or truncation of the file.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(FileObject {
+            file: None,
+            mmap: Some(Bytes::from_owner(mmap)),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    /// Open a file object in either the `pread` mode or the mmap mode.
+    pub fn open_with_mode(path: &Path, enable_mmap: bool) -> Result<Self> {
+        if enable_mmap {
+            Self::open_mmap(path)
+        } else {
+            Self::open(path)
+        }
     }
 }
 
-/// An SSTable.
-pub struct SsTable {
-    /// The actual storage unit of SsTable, the format is as above.
-    pub(crate) file: FileObject,
+/// The index of an SST, including the block metas and the bloom filter.
+pub struct SsTableIndex {
     /// The meta blocks that hold info for data blocks.
     pub(crate) block_meta: Vec<BlockMeta>,
+    pub(crate) bloom: Option<Bloom>,
+    max_ts: u64,
+    value_separated: bool,
+    num_entries: u32,
+    num_deletes: u32,
+}
+
+impl SsTableIndex {
+    /// The approximate memory usage of the index in bytes.
+    pub(crate) fn size(&self) -> usize {
+        let block_meta_size: usize = self
+            .block_meta
+            .iter()
+            .map(|meta| {
+                std::mem::size_of::<BlockMeta>()
+                    + meta.first_key.raw_len()
+                    + meta.last_key.raw_len()
+            })
+            .sum();
+        block_meta_size + self.bloom.as_ref().map_or(0, |bloom| bloom.filter.len())
+    }
+}
+
+/// An opened SST file.
+pub struct SsTableFile {
+    /// The actual storage unit of SsTable, the format is as above.
+    pub(crate) file: FileObject,
     /// The offset that indicates the start point of meta blocks in `file`.
     pub(crate) block_meta_offset: usize,
+    bloom_offset: usize,
+    /// The index kept with the open file, read on first use. It stays empty if the index is cached
+    /// in the block cache.
+    index: OnceLock<Arc<SsTableIndex>>,
+}
+
+impl SsTableFile {
+    /// Open an SST file. Only the footer is read.
+    fn open(file: FileObject) -> Result<Self> {
+        let len = file.size();
+        let raw_bloom_offset = file.read_bytes(len - 4, 4)?;
+        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
+        let raw_meta_offset = file.read_bytes(bloom_offset - 4, 4)?;
+        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
+        Ok(Self {
+            file,
+            block_meta_offset: block_meta_offset as usize,
+            bloom_offset: bloom_offset as usize,
+            index: OnceLock::new(),
+        })
+    }
+
+    /// Read the index of the SST.
+    fn read_index(&self) -> Result<SsTableIndex> {
+        let mut index = self.read_block_meta()?;
+        let bloom_offset = self.bloom_offset as u64;
+        // copied out of the mapping in mmap mode, as the index may stay in the block cache after
+        // the file is deleted
+        let raw_bloom = self
+            .file
+            .read(bloom_offset, self.file.size() - 4 - bloom_offset)?;
+        index.bloom = Some(Bloom::decode(raw_bloom.into())?);
+        Ok(index)
+    }
+
+    /// Read the block metas of the SST without the bloom filter.
+    fn read_block_meta(&self) -> Result<SsTableIndex> {
+        let (block_meta_offset, bloom_offset) =
+            (self.block_meta_offset as u64, self.bloom_offset as u64);
+        let raw_meta = self
+            .file
+            .read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
+        let (block_meta, max_ts, value_separated, num_entries, num_deletes) =
+            BlockMeta::decode_block_meta(raw_meta.into())?;
+        Ok(SsTableIndex {
+            block_meta,
+            bloom: None,
+            max_ts,
+            value_separated,
+            num_entries,
+            num_deletes,
+        })
+    }
+}
+
+/// Where an SST handle gets its file from.
+pub(crate) enum SsTableSource {
+    /// The file is kept open by the handle.
+    Resident(Arc<SsTableFile>),
+    /// The file is opened on demand through the table cache.
+    Lazy {
+        cache_entry: TableCacheEntry,
+        path: PathBuf,
+        enable_mmap: bool,
+        cache_index: bool,
+    },
+    /// A mock SST without a file.
+    MetaOnly,
+}
+
+/// The file of an SST in the table cache, which is closed once the SST is no longer referenced.
+pub(crate) struct TableCacheEntry {
+    table_cache: Arc<TableCache>,
+    id: usize,
+}
+
+impl Drop for TableCacheEntry {
+    fn drop(&mut self) {
+        // the SST is either removed or the storage is closed
+        self.table_cache.remove(self.id);
+    }
+}
+
+/// An SSTable.
+///
+/// The handle only keeps the metadata needed to plan reads and compactions in memory. The file and
+/// the index are either kept open by the handle, or opened on demand through the table cache.
+pub struct SsTable {
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
     first_key: KeyBytes,
     last_key: KeyBytes,
-    pub(crate) bloom: Option<Bloom>,
     max_ts: u64,
+    /// Whether the values are tagged as either inline values or value log pointers.
+    value_separated: bool,
+    /// The number of entries, including deletes.
+    num_entries: u32,
+    num_deletes: u32,
+    size: u64,
+    /// When the SST file was written. SSTs merged by FIFO compaction keep the time of the newest
+    /// input.
+    created_at: SystemTime,
+    num_of_blocks: usize,
+    /// The actual storage unit of SsTable, kept open by the handle or opened on demand.
+    pub(crate) file: SsTableSource,
+    /// The index kept in memory regardless of the table cache and the block cache.
+    pinned_index: Option<Arc<SsTableIndex>>,
+}
+
+/// Lets the tests read the block metas and the bloom filter of an SST as fields, from the index
+/// kept with the handle.
+#[cfg(test)]
+impl std::ops::Deref for SsTable {
+    type Target = SsTableIndex;
+
+    fn deref(&self) -> &SsTableIndex {
+        if let Some(index) = &self.pinned_index {
+            return index;
+        }
+        let SsTableSource::Resident(table_file) = &self.file else {
+            panic!("the index of the SST is not kept with the handle");
+        };
+        table_file
+            .index
+            .get_or_init(|| Arc::new(table_file.read_index().unwrap()))
+    }
 }
+
 impl SsTable {
+    /// Open the file of a resident SST again.
     #[cfg(test)]
-    pub(crate) fn open_for_test(file: FileObject) -> Result<Self> {
-        Self::open(0, None, file)
+    pub(crate) fn open_for_test(file: SsTableSource) -> Result<Self> {
+        let SsTableSource::Resident(table_file) = file else {
+            panic!("the file of the SST is not kept open");
+        };
+        let Ok(table_file) = Arc::try_unwrap(table_file) else {
+            panic!("the file of the SST is shared");
+        };
+        Self::open(0, None, table_file.file)
+    }
+
+    fn new(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        size: u64,
+        created_at: SystemTime,
+        index: &SsTableIndex,
+        source: SsTableSource,
+    ) -> Self {
+        Self {
+            id,
+            block_cache,
+            first_key: index.block_meta.first().unwrap().first_key.clone(),
+            last_key: index.block_meta.last().unwrap().last_key.clone(),
+            max_ts: index.max_ts,
+            value_separated: index.value_separated,
+            num_entries: index.num_entries,
+            num_deletes: index.num_deletes,
+            size,
+            created_at,
+            num_of_blocks: index.block_meta.len(),
+            file: source,
+            pinned_index: None,
+        }
     }
 
-    /// Open SSTable from a file.
+    /// Open SSTable from a file. The file and the index are kept open by the SST.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
-        Ok(Self {
-            file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
-            block_meta,
-            block_meta_offset: block_meta_offset as usize,
+        let table_file = SsTableFile::open(file)?;
+        let index = Arc::new(table_file.read_index()?);
+        let _ = table_file.index.set(index.clone());
+        Ok(Self::new(
             id,
             block_cache,
-            bloom: Some(bloom_filter),
-            max_ts,
-        })
+            table_file.file.size(),
+            table_file.file.modified(),
+            &index,
+            SsTableSource::Resident(Arc::new(table_file)),
+        ))
+    }
+
+    /// Open SSTable from a file, and put the file into the table cache. Afterwards, the file is
+    /// reopened on demand if evicted. If `cache_index` is set and there is a block cache, the index
+    /// is cached in the block cache instead of being kept with the open file.
+    ///
+    /// Only the key range and the stats of the block metas are kept. The index is read on first
+    /// use.
+    pub fn open_lazy(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        table_cache: Arc<TableCache>,
+        path: impl AsRef<Path>,
+        enable_mmap: bool,
+        cache_index: bool,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let table_file = SsTableFile::open(FileObject::open_with_mode(path, enable_mmap)?)?;
+        let block_meta = table_file.read_block_meta()?;
+        Self::new(
+            id,
+            block_cache,
+            table_file.file.size(),
+            table_file.file.modified(),
+            &block_meta,
+            SsTableSource::Resident(Arc::new(table_file)),
+        )
+        .into_lazy(table_cache, path, enable_mmap, cache_index)
+    }
+
+    /// Move the open file of an SST into the table cache.
+    pub fn into_lazy(
+        mut self,
+        table_cache: Arc<TableCache>,
+        path: impl AsRef<Path>,
+        enable_mmap: bool,
+        cache_index: bool,
+    ) -> Result<Self> {
+        let cache_index = cache_index && self.block_cache.is_some();
+        let source = std::mem::replace(
+            &mut self.file,
+            SsTableSource::Lazy {
+                cache_entry: TableCacheEntry {
+                    table_cache: table_cache.clone(),
+                    id: self.id,
+                },
+                path: path.as_ref().to_path_buf(),
+                enable_mmap,
+                cache_index,
+            },
+        );
+        if let SsTableSource::Resident(table_file) = source {
+            let table_file = match Arc::try_unwrap(table_file) {
+                Ok(mut table_file) => {
+                    if cache_index {
+                        if let Some(index) = table_file.index.take() {
+                            self.block_cache
+                                .as_ref()
+                                .unwrap()
+                                .try_get_index_with(self.id, || Ok(index))?;
+                        }
+                    }
+                    Arc::new(table_file)
+                }
+                // an iterator might be reading the SST already
+                Err(table_file) => table_file,
+            };
+            table_cache.insert(self.id, table_file);
+        }
+        Ok(self)
+    }
+
+    /// Keep the index in memory for the lifetime of the SST.
+    pub fn pin_index(mut self) -> Result<Self> {
+        self.pinned_index = Some(self.index()?);
+        Ok(self)
     }
 
     /// Create a mock SST with only first key + last key metadata
@@ -181,59 +563,230 @@ impl SsTable {
         last_key: KeyBytes,
     ) -> Self {
         Self {
-            file: FileObject(None, file_size),
-            block_meta: vec![],
-            block_meta_offset: 0,
             id,
             block_cache: None,
             first_key,
             last_key,
-            bloom: None,
             max_ts: 0,
+            value_separated: false,
+            num_entries: 0,
+            num_deletes: 0,
+            size: file_size,
+            created_at: SystemTime::now(),
+            num_of_blocks: 0,
+            file: SsTableSource::MetaOnly,
+            pinned_index: None,
+        }
+    }
+
+    /// Get the open file of the SST, opening it if it is not in the table cache.
+    pub fn table_file(&self) -> Result<Arc<SsTableFile>> {
+        match &self.file {
+            SsTableSource::Resident(table_file) => Ok(table_file.clone()),
+            SsTableSource::Lazy {
+                cache_entry,
+                path,
+                enable_mmap,
+                ..
+            } => cache_entry.table_cache.get_or_open(self.id, || {
+                SsTableFile::open(FileObject::open_with_mode(path, *enable_mmap)?)
+            }),
+            SsTableSource::MetaOnly => bail!("SST {} has no file", self.id),
+        }
+    }
+
+    /// Get the index of the SST.
+    pub fn index(&self) -> Result<Arc<SsTableIndex>> {
+        if let Some(index) = &self.pinned_index {
+            return Ok(index.clone());
+        }
+        self.index_of(&*self.table_file()?)
+    }
+
+    /// Get the index of the SST whose open file is `table_file`.
+    fn index_of(&self, table_file: &SsTableFile) -> Result<Arc<SsTableIndex>> {
+        if let Some(index) = &self.pinned_index {
+            return Ok(index.clone());
+        }
+        if let Some(index) = table_file.index.get() {
+            return Ok(index.clone());
+        }
+        match (&self.block_cache, &self.file) {
+            (
+                Some(block_cache),
+                SsTableSource::Lazy {
+                    cache_index: true, ..
+                },
+            ) => block_cache.try_get_index_with(self.id, || Ok(Arc::new(table_file.read_index()?))),
+            _ => {
+                let index = Arc::new(table_file.read_index()?);
+                Ok(table_file.index.get_or_init(|| index).clone())
+            }
         }
     }
 
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        let offset = self.block_meta[block_idx].offset;
-        let offset_end = self
-            .block_meta
-            .get(block_idx + 1)
-            .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
-        let block_data_with_chksum: Vec<u8> = self
+        Ok(self.read_blocks(block_idx, 1)?.pop().unwrap())
+    }
+
+    /// Read `count` consecutive blocks starting from `block_idx` from the disk in one I/O.
+    pub fn read_blocks(&self, block_idx: usize, count: usize) -> Result<Vec<Arc<Block>>> {
+        let table_file = self.table_file()?;
+        let index = self.index_of(&table_file)?;
+        self.read_blocks_inner(&table_file, &index, block_idx, count, false)
+    }
+
+    /// Read `count` consecutive blocks like [`SsTable::read_blocks`]. The blocks going into the
+    /// block cache are copied out of the mapping in mmap mode, or the cache would keep the mapping
+    /// of a deleted SST alive.
+    fn read_blocks_inner(
+        &self,
+        table_file: &SsTableFile,
+        index: &SsTableIndex,
+        block_idx: usize,
+        count: usize,
+        for_cache: bool,
+    ) -> Result<Vec<Arc<Block>>> {
+        let copy = for_cache && table_file.file.is_mmap();
+        let block_offset = |idx: usize| {
+            index
+                .block_meta
+                .get(idx)
+                .map_or(table_file.block_meta_offset, |x| x.offset)
+        };
+        let begin = block_offset(block_idx);
+        let end = block_offset(block_idx + count);
+        let data = table_file
             .file
-            .read(offset as u64, (offset_end - offset) as u64)?;
-        let block_data = &block_data_with_chksum[..block_len];
-        let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
-            bail!("block checksum mismatched");
+            .read_bytes(begin as u64, (end - begin) as u64)?;
+        let mut blocks = Vec::with_capacity(count);
+        for idx in block_idx..block_idx + count {
+            let block_data_with_chksum =
+                data.slice(block_offset(idx) - begin..block_offset(idx + 1) - begin);
+            let block_len = block_data_with_chksum.len() - 4;
+            let block_data = block_data_with_chksum.slice(..block_len);
+            let checksum = (&block_data_with_chksum[block_len..]).get_u32();
+            if checksum != crc32fast::hash(&block_data) {
+                bail!("block checksum mismatched");
+            }
+            blocks.push(Arc::new(if copy {
+                Block::decode(&block_data)
+            } else {
+                Block::decode_bytes(block_data)
+            }));
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        Ok(blocks)
+    }
+
+    /// Read `count` consecutive blocks from the disk like [`SsTable::read_blocks`], waiting for the
+    /// rate limiter of `read_options` first.
+    fn read_blocks_rate_limited(
+        &self,
+        block_idx: usize,
+        count: usize,
+        read_options: &ReadOptions,
+    ) -> Result<Vec<Arc<Block>>> {
+        let table_file = self.table_file()?;
+        let index = self.index_of(&table_file)?;
+        if let Some(rate_limiter) = &read_options.rate_limiter {
+            let end = index
+                .block_meta
+                .get(block_idx + count)
+                .map_or(table_file.block_meta_offset, |x| x.offset);
+            let len = end - index.block_meta[block_idx].offset;
+            rate_limiter.request(len as u64, IoPriority::Low);
+        }
+        let for_cache = read_options.fill_cache && self.block_cache.is_some();
+        self.read_blocks_inner(&table_file, &index, block_idx, count, for_cache)
     }
 
     /// Read a block from disk, with block cache.
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
-        if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
-            Ok(blk)
-        } else {
-            self.read_block(block_idx)
+        self.read_block_with_options(block_idx, &ReadOptions::default())
+    }
+
+    /// Read a block from disk, with block cache. The block is not put into the cache on a miss if
+    /// `fill_cache` is disabled.
+    pub fn read_block_with_options(
+        &self,
+        block_idx: usize,
+        read_options: &ReadOptions,
+    ) -> Result<Arc<Block>> {
+        let read_block = || {
+            Ok(self
+                .read_blocks_rate_limited(block_idx, 1, read_options)?
+                .pop()
+                .unwrap())
+        };
+        match &self.block_cache {
+            Some(block_cache) if read_options.fill_cache => {
+                block_cache.try_get_with(self.id, block_idx, read_block)
+            }
+            Some(block_cache) => match block_cache.get(self.id, block_idx) {
+                Some(block) => Ok(block),
+                None => match block_cache.get_secondary(self.id, block_idx)? {
+                    Some(block) => Ok(block),
+                    None => read_block(),
+                },
+            },
+            None => read_block(),
         }
     }
 
+    /// Read the block at `block_idx` and up to `readahead_blocks - 1` blocks after it in one I/O,
+    /// unless the block is in the block cache or the secondary cache.
+    pub fn read_blocks_ahead(
+        &self,
+        block_idx: usize,
+        read_options: &ReadOptions,
+    ) -> Result<Vec<Arc<Block>>> {
+        if read_options.readahead_blocks <= 1 {
+            return Ok(vec![self.read_block_with_options(block_idx, read_options)?]);
+        }
+        if let Some(block_cache) = &self.block_cache {
+            if let Some(block) = block_cache.get(self.id, block_idx) {
+                return Ok(vec![block]);
+            }
+            if let Some(block) = block_cache.get_secondary(self.id, block_idx)? {
+                if read_options.fill_cache {
+                    block_cache.insert(self.id, block_idx, block.clone());
+                }
+                return Ok(vec![block]);
+            }
+        }
+        let count = read_options
+            .readahead_blocks
+            .min(self.num_of_blocks - block_idx);
+        let blocks = self.read_blocks_rate_limited(block_idx, count, read_options)?;
+        if let (Some(block_cache), true) = (&self.block_cache, read_options.fill_cache) {
+            for (idx, block) in blocks.iter().enumerate() {
+                block_cache.insert(self.id, block_idx + idx, block.clone());
+            }
+        }
+        Ok(blocks)
+    }
+
     /// Find the block that may contain `key`.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
-        self.block_meta
+    pub fn find_block_idx(&self, key: KeySlice) -> Result<usize> {
+        Ok(self
+            .index()?
+            .block_meta
             .partition_point(|meta| meta.first_key.as_key_slice() <= key)
-            .saturating_sub(1)
+            .saturating_sub(1))
+    }
+
+    /// Check the bloom filter of the SST. Returns `true` if the SST has no bloom filter.
+    pub fn may_contain(&self, key: &[u8]) -> Result<bool> {
+        Ok(match &self.index()?.bloom {
+            Some(bloom) => bloom.may_contain(farmhash::fingerprint32(key)),
+            None => true,
+        })
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_meta.len()
+        self.num_of_blocks
     }
 
     pub fn first_key(&self) -> &KeyBytes {
@@ -245,7 +798,28 @@ impl SsTable {
     }
 
     pub fn table_size(&self) -> u64 {
-        self.file.1
+        self.size
+    }
+
+    /// The number of entries in the SST, including deletes.
+    pub fn num_entries(&self) -> u32 {
+        self.num_entries
+    }
+
+    /// The number of deletes in the SST.
+    pub fn num_deletes(&self) -> u32 {
+        self.num_deletes
+    }
+
+    /// When the SST file was written.
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// Set the creation time of the SST, which does not change the file.
+    pub fn with_created_at(mut self, created_at: SystemTime) -> Self {
+        self.created_at = created_at;
+        self
     }
 
     pub fn sst_id(&self) -> usize {
@@ -255,4 +829,8 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    pub fn value_separated(&self) -> bool {
+        self.value_separated
+    }
 }
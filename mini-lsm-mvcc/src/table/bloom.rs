@@ -45,18 +45,15 @@ impl<T: AsMut<[u8]>> BitSliceMut for T {
 }
 
 impl Bloom {
-    /// Decode a bloom filter
-    pub fn decode(buf: &[u8]) -> Result<Self> {
+    /// Decode a bloom filter. The filter refers to `buf` without copying.
+    pub fn decode(buf: Bytes) -> Result<Self> {
         let checksum = (&buf[buf.len() - 4..buf.len()]).get_u32();
         if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
             bail!("checksum mismatched for bloom filters");
         }
-        let filter = &buf[..buf.len() - 5];
+        let filter = buf.slice(..buf.len() - 5);
         let k = buf[buf.len() - 5];
-        Ok(Self {
-            filter: filter.to_vec().into(),
-            k,
-        })
+        Ok(Self { filter, k })
     }
 
     /// Encode a bloom filter
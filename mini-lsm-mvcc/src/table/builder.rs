@@ -5,10 +5,12 @@ use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
+use super::{BlockMeta, FileObject, SsTable, SsTableFile, SsTableIndex, SsTableSource};
 use crate::block::BlockBuilder;
 use crate::key::{KeySlice, KeyVec};
 use crate::lsm_storage::BlockCache;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::value_log::{ValueLogBuilder, VALUE_TAG_INLINE, VALUE_TAG_POINTER};
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -20,6 +22,15 @@ pub struct SsTableBuilder {
     block_size: usize,
     key_hashes: Vec<u32>,
     max_ts: u64,
+    num_entries: u32,
+    num_deletes: u32,
+    enable_mmap: bool,
+    /// The value log for large values, together with the value size threshold.
+    value_log: Option<(ValueLogBuilder, usize)>,
+    /// Scratch buffer for encoding tagged values.
+    tagged_value: Vec<u8>,
+    /// Write the SST at the pace of the rate limiter with the priority.
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
 }
 
 impl SsTableBuilder {
@@ -34,11 +45,88 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            num_entries: 0,
+            num_deletes: 0,
+            enable_mmap: false,
+            value_log: None,
+            tagged_value: Vec::new(),
+            rate_limiter: None,
         }
     }
 
+    /// Map the SST file into memory after it is written.
+    pub fn with_mmap(mut self, enable_mmap: bool) -> Self {
+        self.enable_mmap = enable_mmap;
+        self
+    }
+
+    /// Store values of at least `value_threshold` bytes in the value log. All values of the SST
+    /// are then tagged as either inline values or value log pointers.
+    pub fn with_value_log(mut self, value_log: ValueLogBuilder, value_threshold: usize) -> Self {
+        self.value_log = Some((value_log, value_threshold));
+        self
+    }
+
+    /// Write the SST file at the pace allowed by `rate_limiter`, at `priority`.
+    pub fn with_rate_limiter(
+        mut self,
+        rate_limiter: Arc<RateLimiter>,
+        priority: IoPriority,
+    ) -> Self {
+        self.rate_limiter = Some((rate_limiter, priority));
+        self
+    }
+
+    /// Whether no entry was added yet.
+    pub fn is_empty(&self) -> bool {
+        self.num_entries == 0
+    }
+
+    /// The number of finished blocks.
+    pub fn num_blocks(&self) -> usize {
+        self.meta.len()
+    }
+
+    /// Whether the SST stores large values in the value log.
+    pub fn separates_values(&self) -> bool {
+        self.value_log.is_some()
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        // tombstones are never tagged
+        if value.is_empty() {
+            self.add_inner(key, value);
+            return;
+        }
+        let Some((value_log, value_threshold)) = self.value_log.as_mut() else {
+            self.add_inner(key, value);
+            return;
+        };
+        if value.len() >= *value_threshold {
+            let pointer = value_log.add(key, value);
+            self.add_tagged(key, VALUE_TAG_POINTER, &pointer.encode());
+        } else {
+            self.add_tagged(key, VALUE_TAG_INLINE, value);
+        }
+    }
+
+    /// Adds a key with an encoded value pointer. Only valid when the SST has a value log.
+    pub fn add_value_pointer(&mut self, key: KeySlice, pointer: &[u8]) {
+        assert!(self.separates_values(), "SST does not separate values");
+        self.add_tagged(key, VALUE_TAG_POINTER, pointer);
+    }
+
+    fn add_tagged(&mut self, key: KeySlice, tag: u8, value: &[u8]) {
+        let mut tagged_value = std::mem::take(&mut self.tagged_value);
+        tagged_value.clear();
+        tagged_value.push(tag);
+        tagged_value.extend_from_slice(value);
+        self.add_inner(key, &tagged_value);
+        self.tagged_value = tagged_value;
+    }
+
+    fn add_inner(&mut self, key: KeySlice, value: &[u8]) {
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -46,6 +134,10 @@ impl SsTableBuilder {
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
+        self.num_entries += 1;
+        if value.is_empty() {
+            self.num_deletes += 1;
+        }
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
 
         if self.builder.add(key, value) {
@@ -88,9 +180,21 @@ impl SsTableBuilder {
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
         self.finish_block();
+        let value_separated = self.separates_values();
+        if let Some((value_log, _)) = self.value_log {
+            // the values must be durable before the SST refers to them
+            value_log.finish()?;
+        }
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+        BlockMeta::encode_block_meta(
+            &self.meta,
+            self.max_ts,
+            value_separated,
+            self.num_entries,
+            self.num_deletes,
+            &mut buf,
+        );
         buf.put_u32(meta_offset as u32);
         let bloom = Bloom::build_from_key_hashes(
             &self.key_hashes,
@@ -99,18 +203,35 @@ impl SsTableBuilder {
         let bloom_offset = buf.len();
         bloom.encode(&mut buf);
         buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
-        Ok(SsTable {
-            id,
-            file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
+        let file = FileObject::create(
+            path.as_ref(),
+            buf,
+            self.enable_mmap,
+            self.rate_limiter
+                .as_ref()
+                .map(|(rate_limiter, priority)| (rate_limiter.as_ref(), *priority)),
+        )?;
+        let index = Arc::new(SsTableIndex {
             block_meta: self.meta,
-            block_meta_offset: meta_offset,
-            block_cache,
             bloom: Some(bloom),
             max_ts: self.max_ts,
-        })
+            value_separated,
+            num_entries: self.num_entries,
+            num_deletes: self.num_deletes,
+        });
+        Ok(SsTable::new(
+            id,
+            block_cache,
+            file.size(),
+            file.modified(),
+            &index,
+            SsTableSource::Resident(Arc::new(SsTableFile {
+                file,
+                block_meta_offset: meta_offset,
+                bloom_offset,
+                index: index.clone().into(),
+            })),
+        ))
     }
 
     #[cfg(test)]
@@ -1,55 +1,116 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
 
 use anyhow::Result;
 
 use super::SsTable;
-use crate::block::BlockIterator;
+use crate::block::{Block, BlockIterator};
 use crate::iterators::StorageIterator;
 use crate::key::KeySlice;
+use crate::prefetch::PrefetchPool;
+use crate::rate_limiter::RateLimiter;
+use crate::value_log::VALUE_TAG_POINTER;
+
+/// Options of reading SSTs with iterators.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Put the blocks read from the disk into the block cache. Compaction disables it so that the
+    /// blocks of the compaction inputs do not evict the blocks being read.
+    pub fill_cache: bool,
+    /// The number of consecutive blocks read in one I/O when the iterator moves to the next
+    /// block. Readahead is disabled if it is not greater than 1.
+    pub readahead_blocks: usize,
+    /// The number of SSTs after the current one of a sorted run opened in the background on
+    /// `prefetch_pool` while reading the current one.
+    pub prefetch_tables: usize,
+    /// The threads prefetching SSTs, prefetch is disabled if `None`.
+    pub prefetch_pool: Option<Arc<PrefetchPool>>,
+    /// Wait for this rate limiter at low priority before reading blocks from the disk. Set for
+    /// compaction.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            fill_cache: true,
+            readahead_blocks: 1,
+            prefetch_tables: 0,
+            prefetch_pool: None,
+            rate_limiter: None,
+        }
+    }
+}
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    read_options: ReadOptions,
+    /// The blocks after `blk_idx` read by readahead.
+    readahead: VecDeque<Arc<Block>>,
 }
 
 impl SsTableIterator {
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+    fn seek_to_first_inner(
+        table: &Arc<SsTable>,
+        read_options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
         Ok((
             0,
-            BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
+            BlockIterator::create_and_seek_to_first(
+                table.read_block_with_options(0, read_options)?,
+            ),
         ))
     }
 
     /// Create a new iterator and seek to the first key-value pair.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
+        Self::create_and_seek_to_first_with_options(table, ReadOptions::default())
+    }
+
+    /// Create a new iterator with `read_options` and seek to the first key-value pair.
+    pub fn create_and_seek_to_first_with_options(
+        table: Arc<SsTable>,
+        read_options: ReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table, &read_options)?;
         let iter = Self {
             blk_iter,
             table,
             blk_idx,
+            read_options,
+            readahead: VecDeque::new(),
         };
         Ok(iter)
     }
 
     /// Seek to the first key-value pair.
     pub fn seek_to_first(&mut self) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table, &self.read_options)?;
         self.blk_idx = blk_idx;
         self.blk_iter = blk_iter;
+        self.readahead.clear();
         Ok(())
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
-        let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
+    fn seek_to_key_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        read_options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        let mut blk_idx = table.find_block_idx(key)?;
+        let mut blk_iter = BlockIterator::create_and_seek_to_key(
+            table.read_block_with_options(blk_idx, read_options)?,
+            key,
+        );
         if !blk_iter.is_valid() {
             blk_idx += 1;
             if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
+                blk_iter = BlockIterator::create_and_seek_to_first(
+                    table.read_block_with_options(blk_idx, read_options)?,
+                );
             }
         }
         Ok((blk_idx, blk_iter))
@@ -57,29 +118,71 @@ impl SsTableIterator {
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
+        Self::create_and_seek_to_key_with_options(table, key, ReadOptions::default())
+    }
+
+    /// Create a new iterator with `read_options` and seek to the first key-value pair which >=
+    /// `key`.
+    pub fn create_and_seek_to_key_with_options(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        read_options: ReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key, &read_options)?;
         let iter = Self {
             blk_iter,
             table,
             blk_idx,
+            read_options,
+            readahead: VecDeque::new(),
         };
         Ok(iter)
     }
 
     /// Seek to the first key-value pair which >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key, &self.read_options)?;
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
+        self.readahead.clear();
         Ok(())
     }
+
+    /// Read the block at `blk_idx` when the iterator moves on to it sequentially, which starts a
+    /// readahead if the block was not read ahead already.
+    fn read_next_block(&mut self) -> Result<Arc<Block>> {
+        if let Some(block) = self.readahead.pop_front() {
+            return Ok(block);
+        }
+        let mut blocks = self
+            .table
+            .read_blocks_ahead(self.blk_idx, &self.read_options)?
+            .into_iter();
+        let block = blocks.next().unwrap();
+        self.readahead.extend(blocks);
+        Ok(block)
+    }
 }
 
 impl StorageIterator for SsTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        self.blk_iter.value()
+        let value = self.blk_iter.value();
+        if self.table.value_separated() && !value.is_empty() {
+            // strip the value tag, tombstones are not tagged
+            &value[1..]
+        } else {
+            value
+        }
+    }
+
+    fn is_value_pointer(&self) -> bool {
+        if !self.table.value_separated() {
+            return false;
+        }
+        let value = self.blk_iter.value();
+        !value.is_empty() && value[0] == VALUE_TAG_POINTER
     }
 
     fn key(&self) -> KeySlice {
@@ -95,9 +198,7 @@ impl StorageIterator for SsTableIterator {
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
             if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
+                self.blk_iter = BlockIterator::create_and_seek_to_first(self.read_next_block()?);
             }
         }
         Ok(())
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use super::SsTableFile;
+
+/// A snapshot of the table cache statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// The number of files in the cache.
+    pub open_files: usize,
+}
+
+#[derive(Default)]
+struct TableCacheState {
+    /// The open files and their last access ticks.
+    files: HashMap<usize, (Arc<SsTableFile>, u64)>,
+    /// Maps the last access ticks to SST ids, the first entry is the least recently used file.
+    lru: BTreeMap<u64, usize>,
+    tick: u64,
+    stats: TableCacheStats,
+}
+
+impl TableCacheState {
+    fn touch(&mut self, id: usize) -> Option<Arc<SsTableFile>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (table_file, last_tick) = self.files.get_mut(&id)?;
+        self.lru.remove(last_tick);
+        self.lru.insert(tick, id);
+        *last_tick = tick;
+        Some(table_file.clone())
+    }
+}
+
+/// Keeps up to `max_open_files` SST files open, and closes the least recently used ones beyond
+/// that. A closed file is only released after the iterators reading it are dropped.
+pub struct TableCache {
+    max_open_files: usize,
+    state: Mutex<TableCacheState>,
+}
+
+impl TableCache {
+    pub fn new(max_open_files: usize) -> Self {
+        Self {
+            max_open_files: max_open_files.max(1),
+            state: Mutex::new(TableCacheState::default()),
+        }
+    }
+
+    /// Get an open file, or open it with `open` on a miss.
+    pub(crate) fn get_or_open(
+        &self,
+        id: usize,
+        open: impl FnOnce() -> Result<SsTableFile>,
+    ) -> Result<Arc<SsTableFile>> {
+        {
+            let mut state = self.state.lock();
+            if let Some(table_file) = state.touch(id) {
+                state.stats.hits += 1;
+                return Ok(table_file);
+            }
+            state.stats.misses += 1;
+        }
+        // do not block other readers on I/O; if the file is opened concurrently, one of the
+        // duplicates is closed right away
+        let table_file = Arc::new(open()?);
+        Ok(self.insert(id, table_file))
+    }
+
+    /// Put an open file into the cache, returns the cached file if there is already one.
+    pub(crate) fn insert(&self, id: usize, table_file: Arc<SsTableFile>) -> Arc<SsTableFile> {
+        let mut state = self.state.lock();
+        if let Some(table_file) = state.touch(id) {
+            return table_file;
+        }
+        let tick = state.tick;
+        state.files.insert(id, (table_file.clone(), tick));
+        state.lru.insert(tick, id);
+        while state.files.len() > self.max_open_files {
+            let (_, id) = state.lru.pop_first().unwrap();
+            state.files.remove(&id);
+            state.stats.evictions += 1;
+        }
+        table_file
+    }
+
+    /// Close the file of a removed SST.
+    pub(crate) fn remove(&self, id: usize) {
+        let mut state = self.state.lock();
+        if let Some((_, tick)) = state.files.remove(&id) {
+            state.lru.remove(&tick);
+        }
+    }
+
+    pub fn stats(&self) -> TableCacheStats {
+        let state = self.state.lock();
+        TableCacheStats {
+            open_files: state.files.len(),
+            ..state.stats
+        }
+    }
+}
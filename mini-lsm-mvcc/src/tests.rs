@@ -1,4 +1,26 @@
+mod background_work;
+mod block_cache;
+mod commit_pipeline;
+mod compact_range;
+mod compaction_filter;
+mod compaction_triggers;
+mod concurrent_compaction;
+mod fifo_compaction;
 mod harness;
+mod mem_table_rep;
+mod mmap;
+mod point_lookup;
+mod rate_limiter;
+mod readahead;
+mod secondary_cache;
+mod set_options;
+mod sst_partitioner;
+mod subcompaction;
+mod table_cache;
+mod tiered_compaction;
+mod time_window_compaction;
+mod trivial_move;
+mod value_log;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -19,3 +41,5 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_buffer_manager;
+mod write_stall;
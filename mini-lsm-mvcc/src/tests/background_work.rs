@@ -0,0 +1,190 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    rate_limiter::RateLimiter,
+    value_log::ValueLogOptions,
+};
+
+fn simple_options(level0_file_num_compaction_trigger: usize) -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger,
+            max_levels: 3,
+        },
+    ))
+}
+
+fn put_and_flush(storage: &MiniLsm, round: usize) {
+    for i in 0..100 {
+        let key = format!("key_{:05}", i);
+        let value = format!("value_{}_{:02000}", round, i);
+        storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+    storage.force_flush().unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+fn num_files(path: &Path, extension: &str) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_str()
+                .unwrap()
+                .ends_with(extension)
+        })
+        .count()
+}
+
+fn num_sst_files(path: &Path) -> usize {
+    num_files(path, ".sst")
+}
+
+#[test]
+fn test_pause_and_resume_background_work() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, simple_options(2)).unwrap();
+    storage.pause_background_work();
+    for round in 0..3 {
+        put_and_flush(&storage, round);
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 3);
+
+    storage.resume_background_work();
+    let start = Instant::now();
+    while !storage.inner.state.read().l0_sstables.is_empty() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "L0 not compacted after resume"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_cancel_running_compaction() {
+    let dir = tempdir().unwrap();
+    let rate_limiter = Arc::new(RateLimiter::new(1 << 30));
+    let mut options = simple_options(100);
+    options.target_sst_size = 32 << 10;
+    options.rate_limiter = Some(rate_limiter.clone());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        put_and_flush(&storage, round);
+    }
+    let state = storage.inner.state.read().clone();
+    let num_files = num_sst_files(dir.path());
+
+    // the compaction takes seconds at this rate
+    rate_limiter.set_bytes_per_sec(256 << 10);
+    let compaction = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.compact_range(Bound::Unbounded, Bound::Unbounded, None))
+    };
+    std::thread::sleep(Duration::from_millis(500));
+    storage.cancel_running_compaction();
+    let result = compaction.join().unwrap();
+    assert!(result.unwrap_err().to_string().contains("cancelled"));
+
+    // the state and the files are left as before the compaction
+    let new_state = storage.inner.state.read().clone();
+    assert_eq!(new_state.l0_sstables, state.l0_sstables);
+    assert_eq!(new_state.levels, state.levels);
+    assert!(storage.inner.compacting_sstables.lock().is_empty());
+    assert_eq!(num_sst_files(dir.path()), num_files);
+
+    // compactions run again once cancelled
+    rate_limiter.set_bytes_per_sec(1 << 30);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, None)
+        .unwrap();
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    assert_eq!(
+        storage.get(b"key_00042").unwrap().unwrap()[..8],
+        b"value_1_"[..]
+    );
+}
+
+#[test]
+fn test_cancel_compaction_with_value_log() {
+    let dir = tempdir().unwrap();
+    let rate_limiter = Arc::new(RateLimiter::new(1 << 30));
+    let options_with_threshold = |value_threshold| {
+        let mut options = simple_options(100);
+        // the output SSTs only hold value pointers, small SSTs let the compaction be cancelled
+        // between two of them
+        options.block_size = 256;
+        options.target_sst_size = 1 << 10;
+        options.rate_limiter = Some(rate_limiter.clone());
+        options.value_log = Some(ValueLogOptions {
+            value_threshold,
+            gc_discard_ratio: 2.0,
+        });
+        options
+    };
+    // the first values go to the value log, the second ones are separated by the compaction
+    let storage = MiniLsm::open(&dir, options_with_threshold(64)).unwrap();
+    put_and_flush(&storage, 0);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options_with_threshold(1 << 20)).unwrap();
+    put_and_flush(&storage, 1);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options_with_threshold(64)).unwrap();
+    let num_vlog_files = num_files(dir.path(), ".vlog");
+    assert!(num_vlog_files > 0);
+    // the values overwritten by the compactions of the second round may already be discarded
+    let value_log = storage.inner.value_log.clone();
+    let gc_candidate = value_log.pick_gc_candidate(f64::MIN_POSITIVE).unwrap();
+
+    rate_limiter.set_bytes_per_sec(64 << 10);
+    let compaction = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.compact_range(Bound::Unbounded, Bound::Unbounded, None))
+    };
+    // cancel once the compaction has written a value log file
+    let start = Instant::now();
+    while num_files(dir.path(), ".vlog") == num_vlog_files {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    storage.cancel_running_compaction();
+    let result = compaction.join().unwrap();
+    assert!(result.unwrap_err().to_string().contains("cancelled"));
+
+    // the value logs of the output are removed, and the dropped values are still referenced
+    assert_eq!(num_files(dir.path(), ".vlog"), num_vlog_files);
+    assert_eq!(
+        value_log.pick_gc_candidate(f64::MIN_POSITIVE).unwrap(),
+        gc_candidate
+    );
+
+    rate_limiter.set_bytes_per_sec(1 << 30);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, None)
+        .unwrap();
+    assert!(num_files(dir.path(), ".vlog") > num_vlog_files);
+    assert!(value_log
+        .pick_gc_candidate(f64::MIN_POSITIVE)
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        storage.get(b"key_00042").unwrap().unwrap()[..8],
+        b"value_1_"[..]
+    );
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    key::KeySlice,
+    lsm_storage::{BlockCache, LsmStorageOptions, MiniLsm},
+    table::SsTableBuilder,
+};
+
+#[test]
+fn test_block_cache_byte_capacity() {
+    let mut builder = SsTableBuilder::new(256);
+    for idx in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:05}", idx).as_bytes()),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(4096));
+    let sst = builder
+        .build(1, Some(block_cache.clone()), dir.path().join("1.sst"))
+        .unwrap();
+    assert!(sst.num_of_blocks() > 16);
+
+    for _ in 0..2 {
+        for idx in 0..4 {
+            sst.read_block_cached(idx).unwrap();
+        }
+    }
+    let stats = block_cache.stats();
+    assert_eq!(stats.misses, 4);
+    assert_eq!(stats.inserts, 4);
+    assert_eq!(stats.hits, 4);
+    assert_eq!(stats.evictions, 0);
+
+    // reading all blocks exceeds the capacity
+    for idx in 0..sst.num_of_blocks() {
+        sst.read_block_cached(idx).unwrap();
+    }
+    let stats = block_cache.stats();
+    assert!(stats.evictions > 0);
+    assert!(stats.size <= stats.capacity);
+    assert!(stats.entries < sst.num_of_blocks() as u64);
+}
+
+#[test]
+fn test_block_cache_shared_across_instances() {
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let mut storages = Vec::new();
+    let mut dirs = Vec::new();
+    for instance in 0..2 {
+        let dir = tempdir().unwrap();
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.block_cache = Some(block_cache.clone());
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        // both instances use the same SST ids
+        for idx in 0..100 {
+            storage
+                .put(
+                    format!("key_{:05}", idx).as_bytes(),
+                    format!("value_{:05}@{}", idx, instance).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        storages.push(storage);
+        dirs.push(dir);
+    }
+    for _ in 0..2 {
+        for (instance, storage) in storages.iter().enumerate() {
+            assert_eq!(
+                storage.get(b"key_00042").unwrap(),
+                Some(Bytes::from(format!("value_00042@{}", instance)))
+            );
+        }
+    }
+    let stats = storages[0].block_cache_stats();
+    assert_eq!(stats, storages[1].block_cache_stats());
+    assert_eq!(stats.misses, 2);
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.entries, 2);
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    mvcc::LsmMvccInner,
+};
+
+#[test]
+fn test_commit_ts_published_in_order() {
+    let mvcc = Arc::new(LsmMvccInner::new(0));
+    assert_eq!(mvcc.allocate_commit_ts(), 1);
+    assert_eq!(mvcc.allocate_commit_ts(), 2);
+    assert_eq!(mvcc.allocate_commit_ts(), 3);
+    let finish = |ts| {
+        let mvcc = mvcc.clone();
+        std::thread::spawn(move || mvcc.update_commit_ts(ts))
+    };
+    let batch3 = finish(3);
+    let batch2 = finish(2);
+    std::thread::sleep(Duration::from_millis(100));
+    // batch 1 has not finished, so neither 2 nor 3 can be visible
+    assert_eq!(mvcc.latest_commit_ts(), 0);
+    assert!(!batch2.is_finished());
+    assert!(!batch3.is_finished());
+    mvcc.update_commit_ts(1);
+    batch2.join().unwrap();
+    batch3.join().unwrap();
+    assert_eq!(mvcc.latest_commit_ts(), 3);
+    assert_eq!(mvcc.allocate_commit_ts(), 4);
+}
+
+#[test]
+fn test_concurrent_write_batches() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.target_sst_size = 4096;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let threads = (0..8)
+        .map(|thread| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for idx in 0..200 {
+                    let key1 = format!("key1_{:02}_{:03}", thread, idx);
+                    let key2 = format!("key2_{:02}_{:03}", thread, idx);
+                    storage
+                        .write_batch(&[
+                            WriteBatchRecord::Put(key1.as_bytes(), b"value"),
+                            WriteBatchRecord::Put(key2.as_bytes(), b"value"),
+                        ])
+                        .unwrap();
+                    // a finished write is immediately visible
+                    assert_eq!(
+                        storage.get(key2.as_bytes()).unwrap(),
+                        Some(Bytes::from_static(b"value"))
+                    );
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    // both keys of a batch become visible at once
+    let reader = {
+        let storage = storage.clone();
+        std::thread::spawn(move || {
+            for _ in 0..200 {
+                let txn = storage.new_txn().unwrap();
+                let mut key1s = txn
+                    .scan(
+                        std::ops::Bound::Included(b"key1"),
+                        std::ops::Bound::Excluded(b"key2"),
+                    )
+                    .unwrap();
+                let mut key2s = txn
+                    .scan(
+                        std::ops::Bound::Included(b"key2"),
+                        std::ops::Bound::Unbounded,
+                    )
+                    .unwrap();
+                while key1s.is_valid() {
+                    assert!(key2s.is_valid());
+                    assert_eq!(&key1s.key()[4..], &key2s.key()[4..]);
+                    key1s.next().unwrap();
+                    key2s.next().unwrap();
+                }
+                assert!(!key2s.is_valid());
+            }
+        })
+    };
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    reader.join().unwrap();
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), 1600);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for thread in 0..8 {
+        for idx in 0..200 {
+            let key = format!("key1_{:02}_{:03}", thread, idx);
+            assert_eq!(
+                storage.get(key.as_bytes()).unwrap(),
+                Some(Bytes::from_static(b"value"))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_invalid_write_batch() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    let ts = storage.inner.mvcc().latest_commit_ts();
+    // nothing of a batch with an empty value is written, and no timestamp is taken
+    assert!(storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"key"[..], &b"value2"[..]),
+            WriteBatchRecord::Put(&b"key2"[..], &b""[..]),
+        ])
+        .is_err());
+    assert!(storage.write_batch(&[WriteBatchRecord::Del(b"")]).is_err());
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), ts);
+    assert_eq!(
+        storage.get(b"key").unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    storage.put(b"key2", b"value2").unwrap();
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), ts + 1);
+}
@@ -0,0 +1,208 @@
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, RangeCompactionTask, SimpleLeveledCompactionOptions,
+        TieredCompactionOptions,
+    },
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    table::SsTableIterator,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn flush_all(storage: &MiniLsm) {
+    storage.force_flush().unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+/// Fill `0..count` in batches flushed to separate SSTs, and delete `deleted`.
+fn fill_and_delete(storage: &MiniLsm, count: usize, deleted: std::ops::Range<usize>) {
+    let value = vec![b'v'; 64];
+    for batch in 0..4 {
+        for idx in (batch..count).step_by(4) {
+            storage.put(&key_of(idx), &value).unwrap();
+        }
+        flush_all(storage);
+    }
+    for idx in deleted {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    flush_all(storage);
+}
+
+/// Count the entries, including deletes, stored in the SSTs for the keys in `range`.
+fn count_sst_entries(storage: &MiniLsm, range: std::ops::Range<usize>) -> usize {
+    let (lower, upper) = (key_of(range.start), key_of(range.end));
+    let state = storage.inner.state.read().clone();
+    let mut count = 0;
+    for table in state.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+        while iter.is_valid() {
+            let key = iter.key().key_ref();
+            if key >= lower.as_slice() && key < upper.as_slice() {
+                count += 1;
+            }
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+fn check_keys(storage: &MiniLsm, count: usize, deleted: std::ops::Range<usize>) {
+    for idx in 0..count {
+        let value = storage.get(&key_of(idx)).unwrap();
+        assert_eq!(value.is_none(), deleted.contains(&idx), "key {}", idx);
+    }
+}
+
+#[test]
+fn test_compact_range_simple_leveled() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 4 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    fill_and_delete(&storage, 2000, 500..1500);
+    assert!(count_sst_entries(&storage, 500..1500) > 0);
+
+    // the background compaction thread runs at the same time
+    storage
+        .compact_range(
+            Bound::Included(&key_of(500)),
+            Bound::Excluded(&key_of(1500)),
+            None,
+        )
+        .unwrap();
+    assert_eq!(count_sst_entries(&storage, 500..1500), 0);
+    check_keys(&storage, 2000, 500..1500);
+    // the background compactions scheduled afterwards release their SSTs once they are done
+    storage.pause_background_work();
+    let start = Instant::now();
+    while !storage.inner.compacting_sstables.lock().is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let state = storage.inner.state.read().clone();
+    for (_, level) in &state.levels {
+        for pair in level.windows(2) {
+            assert!(state.sstables[&pair[0]].last_key() < state.sstables[&pair[1]].first_key());
+        }
+    }
+}
+
+#[test]
+fn test_compact_range_holds_target_level() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 4 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    fill_and_delete(&storage, 2000, 0..0);
+    let (lower, upper) = (key_of(0), key_of(10));
+    let generate = |state: &LsmStorageState| {
+        RangeCompactionTask::generate_leveled(
+            state,
+            Bound::Included(&lower),
+            Bound::Included(&upper),
+            Some(1),
+        )
+        .unwrap()
+        .unwrap()
+    };
+
+    // the empty target level is held through L0
+    let state = storage.inner.state.read().clone();
+    assert!(state.levels[0].1.is_empty());
+    let task = generate(&state);
+    assert_eq!(task.held_sst_ids, state.l0_sstables);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(1))
+        .unwrap();
+
+    // every SST of the target level is held, not only those overlapping the range
+    fill_and_delete(&storage, 2000, 0..0);
+    let state = storage.inner.state.read().clone();
+    let task = generate(&state);
+    assert_eq!(task.held_sst_ids, state.levels[0].1);
+    let compacting = task.compacting_sst_ids();
+    assert!(compacting.len() > task.input_sst_ids().len());
+    for id in &state.levels[0].1 {
+        assert!(compacting.contains(id));
+    }
+}
+
+#[test]
+fn test_compact_range_tiered() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 100,
+            max_size_amplification_percent: 10000,
+            size_ratio: 10000,
+            min_merge_width: 100,
+        },
+    ));
+    options.target_sst_size = 4 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    fill_and_delete(&storage, 2000, 0..800);
+    let num_tiers = storage.inner.state.read().levels.len();
+    assert!(num_tiers > 5);
+    assert!(storage
+        .compact_range(Bound::Unbounded, Bound::Excluded(&key_of(800)), Some(1))
+        .is_err());
+
+    storage
+        .compact_range(Bound::Unbounded, Bound::Excluded(&key_of(800)), None)
+        .unwrap();
+    assert_eq!(count_sst_entries(&storage, 0..800), 0);
+    check_keys(&storage, 2000, 0..800);
+    // the tiers overlapping the range are merged into one, and the other tiers are kept
+    let num_tiers_after = storage.inner.state.read().levels.len();
+    assert!(num_tiers_after > 1 && num_tiers_after < num_tiers);
+}
+
+#[test]
+fn test_compact_range_recover() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 4 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    fill_and_delete(&storage, 2000, 1000..1200);
+    storage
+        .compact_range(
+            Bound::Included(&key_of(1000)),
+            Bound::Included(&key_of(1199)),
+            Some(1),
+        )
+        .unwrap();
+    storage
+        .compact_range(
+            Bound::Included(&key_of(0)),
+            Bound::Included(&key_of(10)),
+            Some(1),
+        )
+        .unwrap();
+    let state = storage.inner.state.read().clone();
+    assert!(!state.levels[0].1.is_empty());
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let recovered = storage.inner.state.read().clone();
+    assert_eq!(recovered.l0_sstables, state.l0_sstables);
+    assert_eq!(recovered.levels, state.levels);
+    check_keys(&storage, 2000, 1000..1200);
+}
@@ -0,0 +1,154 @@
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{
+        CompactionFilter, CompactionFilterContext, CompactionFilterDecision,
+        CompactionFilterFactory, CompactionKeyFilter, LsmStorageOptions, MiniLsm,
+    },
+};
+
+use super::harness::{check_iter_result_by_key, construct_merge_iterator_over_storage};
+
+/// Removes the keys ending with `_del`, upper-cases the other values, and counts the versions it
+/// sees in each compaction.
+struct UpperCaseFilter {
+    seen: usize,
+    level: usize,
+    log: Arc<Mutex<Vec<(usize, bool, usize)>>>,
+    context: CompactionFilterContext,
+}
+
+impl CompactionKeyFilter for UpperCaseFilter {
+    fn filter(
+        &mut self,
+        key: &[u8],
+        _ts: u64,
+        value: &[u8],
+        level: usize,
+    ) -> CompactionFilterDecision {
+        self.seen += 1;
+        self.level = level;
+        if key.ends_with(b"_del") {
+            CompactionFilterDecision::Remove
+        } else {
+            CompactionFilterDecision::ChangeValue(value.to_ascii_uppercase().into())
+        }
+    }
+}
+
+impl Drop for UpperCaseFilter {
+    fn drop(&mut self) {
+        self.log.lock().unwrap().push((
+            self.seen,
+            self.context.is_bottom_level,
+            self.context.level,
+        ));
+        assert!(self.seen == 0 || self.level == self.context.level);
+    }
+}
+
+struct UpperCaseFilterFactory {
+    log: Arc<Mutex<Vec<(usize, bool, usize)>>>,
+}
+
+impl CompactionFilterFactory for UpperCaseFilterFactory {
+    fn create_compaction_filter(
+        &self,
+        context: &CompactionFilterContext,
+    ) -> Box<dyn CompactionKeyFilter> {
+        Box::new(UpperCaseFilter {
+            seen: 0,
+            level: 0,
+            log: self.log.clone(),
+            context: context.clone(),
+        })
+    }
+}
+
+#[test]
+fn test_compaction_filter_change_and_remove() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"value_a").unwrap();
+    storage.put(b"b_del", b"value_b").unwrap();
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.put(b"a", b"value_a2").unwrap();
+    storage.put(b"c", b"value_c").unwrap();
+    storage.force_flush().unwrap();
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    storage.add_compaction_filter(UpperCaseFilterFactory { log: log.clone() });
+    storage.force_full_compaction().unwrap();
+    // the versions above the watermark are not filtered
+    let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("value_a2")),
+            (Bytes::from("a"), Bytes::from("VALUE_A")),
+            (Bytes::from("c"), Bytes::from("value_c")),
+        ],
+    );
+    assert_eq!(*log.lock().unwrap(), vec![(2, true, 1)]);
+
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("VALUE_A2")),
+            (Bytes::from("c"), Bytes::from("VALUE_C")),
+        ],
+    );
+    // every compaction gets a new filter
+    assert_eq!(log.lock().unwrap().len(), 2);
+    assert_eq!(storage.get(b"b_del").unwrap(), None);
+}
+
+#[test]
+fn test_compaction_filter_remove_above_bottom_level() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 3,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"table1_a", b"1").unwrap();
+    storage.put(b"table2_a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(3))
+        .unwrap();
+    storage.put(b"table2_a", b"2").unwrap();
+    storage.force_flush().unwrap();
+
+    // the removed version becomes a delete, which hides the version in the bottom level
+    storage.add_compaction_filter(CompactionFilter::Prefix(Bytes::from("table2_")));
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(1))
+        .unwrap();
+    let state = storage.inner.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert_eq!(state.levels[0].1.len(), 1);
+    assert_eq!(state.levels[2].1.len(), 1);
+    assert_eq!(storage.get(b"table2_a").unwrap(), None);
+    assert_eq!(storage.get(b"table1_a").unwrap(), Some(Bytes::from("1")));
+
+    // the delete is removed with the bottom level
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, None)
+        .unwrap();
+    let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+    check_iter_result_by_key(&mut iter, vec![(Bytes::from("table1_a"), Bytes::from("1"))]);
+}
@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionTriggerOptions, LeveledCompactionController,
+        LeveledCompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionController,
+        TieredCompactionOptions,
+    },
+    iterators::StorageIterator,
+    key::{KeyBytes, KeySlice},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState},
+    table::{BlockMeta, FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Create a meta-only SST covering `[begin, end]`, written `age` ago.
+fn meta_only_table(id: usize, begin: usize, end: usize, age: Duration) -> Arc<SsTable> {
+    let first_key = KeyBytes::for_testing_from_bytes_no_ts(key_of(begin).into());
+    let last_key = KeyBytes::for_testing_from_bytes_no_ts(key_of(end).into());
+    Arc::new(
+        SsTable::create_meta_only(id, 100, first_key, last_key)
+            .with_created_at(SystemTime::now() - age),
+    )
+}
+
+fn age_triggers() -> CompactionTriggerOptions {
+    CompactionTriggerOptions {
+        tombstone_ratio: None,
+        max_sst_age: Some(Duration::from_secs(3600)),
+    }
+}
+
+#[test]
+fn test_leveled_compaction_age_trigger() {
+    let options = LeveledCompactionOptions {
+        level_size_multiplier: 10,
+        level0_file_num_compaction_trigger: 4,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    };
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Leveled(options.clone()),
+    ));
+    let old = Duration::from_secs(7200);
+    state.l0_sstables.push(1);
+    state.sstables.insert(1, meta_only_table(1, 0, 10, old));
+    state.levels[2].1 = vec![2, 3];
+    state.sstables.insert(2, meta_only_table(2, 20, 30, old));
+    state
+        .sstables
+        .insert(3, meta_only_table(3, 40, 50, Duration::ZERO));
+
+    // no compaction below the size-based triggers
+    let controller = LeveledCompactionController::new(options.clone());
+    assert!(controller.generate_compaction_task(&state).is_none());
+
+    // an old L0 SST moves L0 to the base level
+    let controller =
+        LeveledCompactionController::new(options).with_compaction_triggers(age_triggers());
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    assert_eq!(task.lower_level, 3);
+    assert!(task.lower_level_sst_ids.is_empty());
+
+    // an old SST in the bottom level is rewritten in place
+    let task = controller
+        .generate_compaction_task_skipping(&state, &HashSet::from([1]))
+        .unwrap();
+    assert_eq!(task.upper_level, Some(3));
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert_eq!(task.lower_level, 3);
+    assert!(task.lower_level_sst_ids.is_empty());
+    assert!(task.is_lower_level_bottom_level);
+
+    state
+        .sstables
+        .insert(4, meta_only_table(4, 20, 30, Duration::ZERO));
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[4]);
+    assert_eq!(removed, vec![2]);
+    assert_eq!(state.levels[2].1, vec![4, 3]);
+}
+
+#[test]
+fn test_tiered_compaction_age_trigger() {
+    let options = TieredCompactionOptions {
+        num_tiers: 5,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    };
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Tiered(options.clone()),
+    ));
+    state.levels = vec![(3, vec![3]), (2, vec![2]), (1, vec![1])];
+    state
+        .sstables
+        .insert(3, meta_only_table(3, 0, 10, Duration::ZERO));
+    state
+        .sstables
+        .insert(2, meta_only_table(2, 0, 10, Duration::from_secs(7200)));
+    state
+        .sstables
+        .insert(1, meta_only_table(1, 0, 10, Duration::ZERO));
+
+    let controller = TieredCompactionController::new(options.clone());
+    assert!(controller.generate_compaction_task(&state).is_none());
+
+    // the tier with an old SST is merged with all older tiers
+    let controller =
+        TieredCompactionController::new(options).with_compaction_triggers(age_triggers());
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(2, vec![2]), (1, vec![1])]);
+    assert!(task.bottom_tier_included);
+
+    // the tiers are removed if everything in them was deleted
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[]);
+    assert_eq!(removed, vec![2, 1]);
+    assert_eq!(state.levels, vec![(3, vec![3])]);
+}
+
+#[test]
+fn test_tiered_compaction_age_trigger_above_num_tiers() {
+    let options = TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 10000,
+        size_ratio: 10000,
+        min_merge_width: 2,
+    };
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Tiered(options.clone()),
+    ));
+    state.levels = vec![(3, vec![3]), (2, vec![2]), (1, vec![1])];
+    state
+        .sstables
+        .insert(3, meta_only_table(3, 0, 10, Duration::ZERO));
+    state
+        .sstables
+        .insert(2, meta_only_table(2, 0, 10, Duration::ZERO));
+    state
+        .sstables
+        .insert(1, meta_only_table(1, 0, 10, Duration::from_secs(7200)));
+
+    // the sorted runs are reduced by merging the newest tiers
+    let controller = TieredCompactionController::new(options.clone());
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(3, vec![3]), (2, vec![2])]);
+
+    // the old SST in the bottom tier is rewritten first
+    let controller =
+        TieredCompactionController::new(options).with_compaction_triggers(age_triggers());
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(1, vec![1])]);
+    assert!(task.bottom_tier_included);
+}
+
+/// Encode the block metas as the older format versions did, version 0 being the course format
+/// without a version.
+fn encode_old_block_meta(block_meta: &[BlockMeta], max_ts: u64, version: u8) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if version > 0 {
+        buf.put_u32(u32::MAX);
+        buf.put_u8(version);
+    }
+    buf.put_u32(block_meta.len() as u32);
+    for meta in block_meta {
+        buf.put_u32(meta.offset as u32);
+        for key in [&meta.first_key, &meta.last_key] {
+            buf.put_u16(key.key_len() as u16);
+            buf.put_slice(key.key_ref());
+            buf.put_u64(key.ts());
+        }
+    }
+    buf.put_u64(max_ts);
+    if version >= 1 {
+        buf.put_u8(1);
+    }
+    buf.put_u32(crc32fast::hash(&buf[4..]));
+    buf
+}
+
+#[test]
+fn test_decode_block_meta_of_older_versions() {
+    let block_meta = vec![BlockMeta {
+        offset: 0,
+        first_key: KeyBytes::for_testing_from_bytes_no_ts(key_of(0).into()),
+        last_key: KeyBytes::for_testing_from_bytes_no_ts(key_of(10).into()),
+    }];
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&block_meta, 42, true, 11, 3, &mut buf);
+    let (decoded, max_ts, value_separated, num_entries, num_deletes) =
+        BlockMeta::decode_block_meta(buf.into()).unwrap();
+    assert_eq!(decoded, block_meta);
+    assert_eq!(
+        (max_ts, value_separated, num_entries, num_deletes),
+        (42, true, 11, 3)
+    );
+
+    // the metas without whether values are separated and without the number of entries and
+    // deletes
+    for version in [0, 1] {
+        let buf = encode_old_block_meta(&block_meta, 42, version);
+        let (decoded, max_ts, value_separated, num_entries, num_deletes) =
+            BlockMeta::decode_block_meta(buf.into()).unwrap();
+        assert_eq!(decoded, block_meta);
+        assert_eq!(max_ts, 42);
+        assert_eq!(value_separated, version == 1);
+        assert_eq!((num_entries, num_deletes), (0, 0));
+    }
+    let buf = encode_old_block_meta(&block_meta, 42, 3);
+    assert!(BlockMeta::decode_block_meta(buf.into()).is_err());
+}
+
+#[test]
+fn test_open_sst_of_course_format() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key_of(i), 5),
+            b"value",
+        );
+    }
+    let sst = builder.build_for_test(&path).unwrap();
+    let block_meta = sst.block_meta.clone();
+    drop(sst);
+
+    // write the block metas of the SST in the course format, and keep the blocks and the bloom
+    // filter
+    let data = std::fs::read(&path).unwrap();
+    let bloom_offset = (&data[data.len() - 4..]).get_u32() as usize;
+    let meta_offset = (&data[bloom_offset - 4..bloom_offset]).get_u32() as usize;
+    let mut old = data[..meta_offset].to_vec();
+    old.extend(encode_old_block_meta(&block_meta, 5, 0));
+    old.put_u32(meta_offset as u32);
+    let old_bloom_offset = old.len();
+    old.extend(&data[bloom_offset..data.len() - 4]);
+    old.put_u32(old_bloom_offset as u32);
+    std::fs::write(&path, old).unwrap();
+
+    let sst = Arc::new(SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.max_ts(), 5);
+    assert!(!sst.value_separated());
+    assert_eq!(sst.num_entries(), 0);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for i in 0..100 {
+        assert_eq!(iter.key().key_ref(), key_of(i));
+        assert_eq!(iter.value(), b"value");
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_tombstone_trigger_pushes_deletes_down() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 0,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 3,
+        },
+    ));
+    options.compaction_triggers.tombstone_ratio = Some(0.5);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..100 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    flush(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(3))
+        .unwrap();
+    for i in 0..80 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    flush(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(2))
+        .unwrap();
+    let state = storage.state.read().clone();
+    assert_eq!(state.levels[1].1.len(), 1);
+    let table = &state.sstables[&state.levels[1].1[0]];
+    assert_eq!(table.num_entries(), 80);
+    assert_eq!(table.num_deletes(), 80);
+
+    // the deletes are pushed down to the bottom level, where they are removed
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.levels[1].1.is_empty());
+    assert_eq!(state.levels[2].1.len(), 1);
+    let table = &state.sstables[&state.levels[2].1[0]];
+    assert_eq!(table.num_entries(), 20);
+    assert_eq!(table.num_deletes(), 0);
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key_of(90)).unwrap(),
+        Some(Bytes::from("value"))
+    );
+
+    // nothing left to compact
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+}
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionController,
+        SimpleLeveledCompactionOptions, TieredCompactionController, TieredCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    table::SsTable,
+};
+
+#[test]
+fn test_simple_leveled_tasks_on_disjoint_levels() {
+    let compaction_options = SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    };
+    let controller = SimpleLeveledCompactionController::new(compaction_options.clone());
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Simple(compaction_options),
+    ));
+    state.l0_sstables = vec![11, 10];
+    state.levels[0].1 = vec![1, 2, 3];
+    state.levels[1].1 = vec![4];
+    state.levels[2].1 = vec![5];
+
+    let mut compacting_sstables = HashSet::new();
+    let task1 = controller
+        .generate_compaction_task_skipping(&state, &compacting_sstables)
+        .unwrap();
+    assert_eq!(task1.upper_level, None);
+    compacting_sstables.extend([11, 10, 1, 2, 3]);
+    // L1 is taken by the L0 compaction, so L2 is compacted next
+    let task2 = controller
+        .generate_compaction_task_skipping(&state, &compacting_sstables)
+        .unwrap();
+    assert_eq!(task2.upper_level, Some(2));
+    assert_eq!(task2.lower_level_sst_ids, vec![5]);
+    compacting_sstables.extend([4, 5]);
+    assert!(controller
+        .generate_compaction_task_skipping(&state, &compacting_sstables)
+        .is_none());
+
+    // a flush while the tasks run does not start another L0 compaction
+    state.l0_sstables.insert(0, 12);
+    state.l0_sstables.insert(0, 13);
+    assert!(controller
+        .generate_compaction_task_skipping(&state, &compacting_sstables)
+        .is_none());
+
+    // the tasks finish in any order
+    let (state, removed) = controller.apply_compaction_result(&state, &task2, &[20]);
+    assert_eq!(removed, vec![4, 5]);
+    let (state, removed) = controller.apply_compaction_result(&state, &task1, &[21, 22]);
+    assert_eq!(removed, vec![11, 10, 1, 2, 3]);
+    assert_eq!(state.l0_sstables, vec![13, 12]);
+    assert_eq!(state.levels[0].1, vec![21, 22]);
+    assert!(state.levels[1].1.is_empty());
+    assert_eq!(state.levels[2].1, vec![20]);
+}
+
+#[test]
+fn test_tiered_task_above_running_task() {
+    let compaction_options = TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    };
+    let controller = TieredCompactionController::new(compaction_options.clone());
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Tiered(compaction_options),
+    ));
+    state.levels = vec![(3, vec![3]), (2, vec![2]), (1, vec![1])];
+    for id in 1..=5 {
+        let first_key = KeyBytes::for_testing_from_bytes_no_ts(Bytes::from("a"));
+        let last_key = KeyBytes::for_testing_from_bytes_no_ts(Bytes::from("z"));
+        state.sstables.insert(
+            id,
+            Arc::new(SsTable::create_meta_only(id, 100, first_key, last_key)),
+        );
+    }
+    let task1 = controller.generate_compaction_task(&state).unwrap();
+    assert!(task1.bottom_tier_included);
+    let compacting_sstables = HashSet::from([1, 2, 3]);
+    assert!(controller
+        .generate_compaction_task_skipping(&state, &compacting_sstables)
+        .is_none());
+
+    // only the tiers flushed after the running task started can be compacted
+    state.levels.insert(0, (4, vec![4]));
+    state.levels.insert(0, (5, vec![5]));
+    let task2 = controller
+        .generate_compaction_task_skipping(&state, &compacting_sstables)
+        .unwrap();
+    assert_eq!(task2.tiers, vec![(5, vec![5]), (4, vec![4])]);
+    assert!(!task2.bottom_tier_included);
+
+    let (state, _) = controller.apply_compaction_result(&state, &task1, &[6]);
+    let (state, _) = controller.apply_compaction_result(&state, &task2, &[7]);
+    assert_eq!(state.levels, vec![(7, vec![7]), (6, vec![6])]);
+}
+
+#[test]
+fn test_concurrent_leveled_compactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.target_sst_size = 16 << 10;
+    options.max_background_compactions = 3;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let value = vec![b'v'; 200];
+    for round in 0..4 {
+        for idx in 0..5000 {
+            let mut value = value.clone();
+            value[..8].copy_from_slice(format!("{:08}", round).as_bytes());
+            storage
+                .put(format!("key_{:05}", idx * 7 % 5000).as_bytes(), &value)
+                .unwrap();
+        }
+    }
+    storage.force_flush().unwrap();
+
+    let start = Instant::now();
+    loop {
+        let state = storage.inner.state.read().clone();
+        if state.l0_sstables.len() < 2 && storage.inner.compacting_sstables.lock().is_empty() {
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(30), "timed out");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let state = storage.inner.state.read().clone();
+    for (_, level) in &state.levels {
+        for pair in level.windows(2) {
+            assert!(state.sstables[&pair[0]].last_key() < state.sstables[&pair[1]].first_key());
+        }
+    }
+    for idx in 0..5000 {
+        let mut expected = value.clone();
+        expected[..8].copy_from_slice(format!("{:08}", 3).as_bytes());
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from(expected))
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, FifoCompactionController, FifoCompactionOptions},
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    table::SsTable,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn storage_options(options: FifoCompactionOptions) -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(options))
+}
+
+/// Create a state with one meta-only SST per `(size, age)`, from the newest to the oldest.
+fn state_with_l0(options: &FifoCompactionOptions, tables: &[(u64, u64)]) -> LsmStorageState {
+    let mut state = LsmStorageState::create(&storage_options(options.clone()));
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+    for (idx, (size, age)) in tables.iter().enumerate() {
+        let id = tables.len() - idx;
+        let key = KeyBytes::for_testing_from_bytes_no_ts(key_of(id).into());
+        let table = SsTable::create_meta_only(id, *size, key.clone(), key)
+            .with_created_at(now - Duration::from_secs(*age));
+        state.l0_sstables.push(id);
+        state.sstables.insert(id, Arc::new(table));
+    }
+    state
+}
+
+#[test]
+fn test_fifo_drop_by_size_and_ttl() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+    let options = FifoCompactionOptions {
+        max_table_files_size: 250,
+        ttl: None,
+        intra_l0_compaction_trigger: None,
+        intra_l0_max_file_size: 0,
+    };
+    let controller = FifoCompactionController::new(options.clone());
+    let state = state_with_l0(&options, &[(100, 1), (100, 2), (100, 3), (100, 4)]);
+    assert_eq!(state.l0_sstables, vec![4, 3, 2, 1]);
+    let task = controller
+        .generate_compaction_task_at(&state, &HashSet::new(), now)
+        .unwrap();
+    assert_eq!(task.dropped_sst_ids, vec![1, 2]);
+    assert!(task.merged_sst_ids.is_empty());
+
+    // a flush while the task runs is kept
+    let mut state = state;
+    state.l0_sstables.insert(0, 5);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[]);
+    assert_eq!(removed, vec![1, 2]);
+    assert_eq!(state.l0_sstables, vec![5, 4, 3]);
+
+    let options = FifoCompactionOptions {
+        max_table_files_size: u64::MAX,
+        ttl: Some(Duration::from_secs(10)),
+        ..options
+    };
+    let controller = FifoCompactionController::new(options.clone());
+    let state = state_with_l0(&options, &[(100, 5), (100, 10), (100, 20), (100, 30)]);
+    let task = controller
+        .generate_compaction_task_at(&state, &HashSet::new(), now)
+        .unwrap();
+    assert_eq!(task.dropped_sst_ids, vec![1, 2]);
+    // SSTs being compacted are not picked again
+    assert!(controller
+        .generate_compaction_task_at(&state, &HashSet::from([1]), now)
+        .is_none());
+}
+
+#[test]
+fn test_fifo_intra_l0_compaction() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+    let options = FifoCompactionOptions {
+        max_table_files_size: u64::MAX,
+        ttl: None,
+        intra_l0_compaction_trigger: Some(3),
+        intra_l0_max_file_size: 200,
+    };
+    let controller = FifoCompactionController::new(options.clone());
+    let state = state_with_l0(&options, &[(100, 1), (100, 2), (300, 3), (100, 4)]);
+    // the newest SSTs up to the first large SST are not enough to merge
+    assert!(controller
+        .generate_compaction_task_at(&state, &HashSet::new(), now)
+        .is_none());
+
+    let mut state = state_with_l0(&options, &[(100, 1), (100, 2), (100, 3), (300, 4)]);
+    let task = controller
+        .generate_compaction_task_at(&state, &HashSet::new(), now)
+        .unwrap();
+    assert!(task.dropped_sst_ids.is_empty());
+    assert_eq!(task.merged_sst_ids, vec![4, 3, 2]);
+    state.l0_sstables.insert(0, 5);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[6]);
+    assert_eq!(removed, vec![4, 3, 2]);
+    assert_eq!(state.l0_sstables, vec![5, 6, 1]);
+}
+
+#[test]
+fn test_integration_fifo() {
+    let dir = tempdir().unwrap();
+    let compaction_options = FifoCompactionOptions {
+        max_table_files_size: 64 << 10,
+        ttl: None,
+        intra_l0_compaction_trigger: Some(4),
+        intra_l0_max_file_size: 8 << 10,
+    };
+    let mut options = storage_options(compaction_options);
+    options.target_sst_size = 4 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let value = vec![b'v'; 128];
+    for idx in 0..4000 {
+        storage.put(&key_of(idx), &value).unwrap();
+    }
+    storage.force_flush().unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+    // the background compaction thread may run some of the tasks, and the SSTs it is compacting
+    // are skipped by `trigger_compaction`
+    loop {
+        let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+        storage.inner.trigger_compaction().unwrap();
+        if storage.inner.state.read().l0_sstables == l0_sstables
+            && storage.inner.compacting_sstables.lock().is_empty()
+        {
+            break;
+        }
+    }
+
+    let state = storage.inner.state.read().clone();
+    assert!(state.levels.is_empty());
+    let total_size = state
+        .l0_sstables
+        .iter()
+        .map(|id| state.sstables[id].table_size())
+        .sum::<u64>();
+    assert!(total_size <= 64 << 10, "total size {}", total_size);
+    // the newest keys survive, and the oldest keys are dropped
+    assert_eq!(storage.get(&key_of(3999)).unwrap().unwrap(), value);
+    assert!(storage.get(&key_of(0)).unwrap().is_none());
+    let l0_sstables = state.l0_sstables.clone();
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().l0_sstables, l0_sstables);
+    assert_eq!(storage.get(&key_of(3999)).unwrap().unwrap(), value);
+}
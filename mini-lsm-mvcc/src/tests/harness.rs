@@ -1 +0,0 @@
-../../../mini-lsm/src/tests/harness.rs
\ No newline at end of file
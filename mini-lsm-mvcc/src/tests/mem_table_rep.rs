@@ -0,0 +1,259 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mem_table::{MemTable, MemTableRepType},
+    table::{SsTableBuilder, SsTableIterator},
+};
+
+const REP_TYPES: [MemTableRepType; 4] = [
+    MemTableRepType::SkipList,
+    MemTableRepType::Vector,
+    MemTableRepType::Hash,
+    MemTableRepType::Arena,
+];
+
+fn check_scan(memtable: &MemTable, lower: Bound<KeySlice>, expected: &[(&[u8], u64, &[u8])]) {
+    let mut iter = memtable.scan(lower, Bound::Unbounded);
+    for (key, ts, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), KeySlice::from_slice(key, *ts));
+        assert_eq!(iter.value(), *value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_mem_table_reps() {
+    for rep in REP_TYPES {
+        let memtable = MemTable::create_with_rep(0, rep);
+        assert!(memtable.is_empty());
+        memtable.put(KeySlice::from_slice(b"b", 1), b"b1").unwrap();
+        memtable.put(KeySlice::from_slice(b"a", 2), b"a2").unwrap();
+        memtable.put(KeySlice::from_slice(b"b", 3), b"b3").unwrap();
+        memtable.put(KeySlice::from_slice(b"a", 1), b"a1").unwrap();
+        // overwrite the same key and timestamp
+        memtable.put(KeySlice::from_slice(b"c", 1), b"c0").unwrap();
+        memtable.put(KeySlice::from_slice(b"c", 1), b"c1").unwrap();
+        if rep == MemTableRepType::Arena {
+            // the arena also counts the nodes
+            assert!(memtable.approximate_size() > 6 * (1 + 8 + 2));
+        } else {
+            assert_eq!(memtable.approximate_size(), 6 * (1 + 8 + 2));
+        }
+        let expected: &[(&[u8], u64, &[u8])] = &[
+            (b"a", 2, b"a2"),
+            (b"a", 1, b"a1"),
+            (b"b", 3, b"b3"),
+            (b"b", 1, b"b1"),
+            (b"c", 1, b"c1"),
+        ];
+
+        for frozen in [false, true] {
+            if frozen {
+                memtable.freeze();
+            }
+            assert_eq!(
+                memtable.get(KeySlice::from_slice(b"b", 3)),
+                Some(Bytes::from_static(b"b3"))
+            );
+            assert_eq!(
+                memtable.get(KeySlice::from_slice(b"c", 1)),
+                Some(Bytes::from_static(b"c1"))
+            );
+            assert_eq!(memtable.get(KeySlice::from_slice(b"b", 2)), None);
+            check_scan(&memtable, Bound::Unbounded, expected);
+            check_scan(
+                &memtable,
+                Bound::Excluded(KeySlice::from_slice(b"a", 1)),
+                &expected[2..],
+            );
+            // the range of one key, as used by point lookups
+            let mut iter = memtable.scan(
+                Bound::Included(KeySlice::from_slice(b"b", 2)),
+                Bound::Included(KeySlice::from_slice(b"b", 0)),
+            );
+            assert_eq!(iter.key(), KeySlice::from_slice(b"b", 1));
+            iter.next().unwrap();
+            assert!(!iter.is_valid());
+            assert_eq!(memtable.max_ts().unwrap(), 3);
+        }
+        if matches!(rep, MemTableRepType::Vector | MemTableRepType::Hash) {
+            // the entries sorted on freeze would miss the put
+            assert!(memtable.put(KeySlice::from_slice(b"d", 1), b"d1").is_err());
+        }
+
+        let dir = tempdir().unwrap();
+        let mut builder = SsTableBuilder::new(128);
+        memtable.flush(&mut builder).unwrap();
+        let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.into()).unwrap();
+        for (key, ts, value) in expected {
+            assert_eq!(iter.key(), KeySlice::from_slice(key, *ts));
+            assert_eq!(iter.value(), *value);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_mem_table_reps_read_between_writes() {
+    for rep in REP_TYPES {
+        let memtable = MemTable::create_with_rep(0, rep);
+        memtable.put(KeySlice::from_slice(b"b", 1), b"b0").unwrap();
+        check_scan(&memtable, Bound::Unbounded, &[(b"b", 1, b"b0")]);
+        // the reads see the writes after the previous reads
+        memtable.put(KeySlice::from_slice(b"b", 1), b"b1").unwrap();
+        memtable.put(KeySlice::from_slice(b"a", 1), b"a1").unwrap();
+        assert_eq!(
+            memtable.get(KeySlice::from_slice(b"b", 1)),
+            Some(Bytes::from_static(b"b1"))
+        );
+        memtable.put(KeySlice::from_slice(b"c", 1), b"c1").unwrap();
+        check_scan(
+            &memtable,
+            Bound::Unbounded,
+            &[(b"a", 1, b"a1"), (b"b", 1, b"b1"), (b"c", 1, b"c1")],
+        );
+        memtable.freeze();
+        check_scan(
+            &memtable,
+            Bound::Unbounded,
+            &[(b"a", 1, b"a1"), (b"b", 1, b"b1"), (b"c", 1, b"c1")],
+        );
+    }
+}
+
+#[test]
+fn test_storage_with_mem_table_reps() {
+    for rep in REP_TYPES {
+        let dir = tempdir().unwrap();
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.enable_wal = true;
+        options.memtable_rep = rep;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for idx in 0..100 {
+            storage
+                .put(format!("key_{:03}", idx).as_bytes(), b"value_1")
+                .unwrap();
+        }
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+        for idx in 0..100 {
+            if idx % 2 == 0 {
+                storage
+                    .put(format!("key_{:03}", idx).as_bytes(), b"value_2")
+                    .unwrap();
+            } else {
+                storage
+                    .delete(format!("key_{:03}", idx).as_bytes())
+                    .unwrap();
+            }
+        }
+        let check = |storage: &MiniLsm| {
+            let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+            for idx in (0..100).step_by(2) {
+                assert_eq!(iter.key(), format!("key_{:03}", idx).as_bytes());
+                assert_eq!(iter.value(), b"value_2");
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+            assert_eq!(
+                storage.get(b"key_042").unwrap(),
+                Some(Bytes::from_static(b"value_2"))
+            );
+            assert_eq!(storage.get(b"key_043").unwrap(), None);
+        };
+        check(&storage);
+
+        // recover the memtables from the WAL
+        storage.close().unwrap();
+        drop(storage);
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        check(&storage);
+    }
+}
+
+#[test]
+fn test_arena_mem_table_memory_usage() {
+    let memtable = MemTable::create_with_rep(0, MemTableRepType::Arena);
+    let empty_size = memtable.approximate_size();
+    let mut data_size = 0;
+    for idx in 0..10000 {
+        let key = format!("key_{:05}", idx);
+        let value = format!("value_{:05}", idx);
+        data_size += key.len() + value.len();
+        memtable
+            .put(KeySlice::from_slice(key.as_bytes(), 1), value.as_bytes())
+            .unwrap();
+    }
+    let size = memtable.approximate_size() - empty_size;
+    // each entry takes at least a node header and one next pointer besides the data
+    assert!(size >= data_size + 10000 * 32);
+    assert!(size < data_size + 10000 * 128);
+}
+
+#[test]
+fn test_arena_mem_table_reports_chunks() {
+    let memtable = MemTable::create_with_rep(0, MemTableRepType::Arena);
+    // the head node takes the first chunk of 256KB
+    let chunk_size = memtable.approximate_size();
+    assert!(chunk_size >= 256 << 10);
+    // a value larger than a chunk gets a chunk of its own, and the next entry starts a new chunk
+    // as the tail of the first chunk is too small for the value
+    let value = vec![0; 2 * chunk_size];
+    memtable
+        .put(KeySlice::from_slice(b"key_1", 1), &value)
+        .unwrap();
+    memtable
+        .put(KeySlice::from_slice(b"key_2", 1), b"value")
+        .unwrap();
+    assert!(memtable.approximate_size() >= 4 * chunk_size);
+}
+
+#[test]
+fn test_arena_mem_table_concurrent_access() {
+    let memtable = std::sync::Arc::new(MemTable::create_with_rep(0, MemTableRepType::Arena));
+    let writers = (0..4)
+        .map(|thread| {
+            let memtable = memtable.clone();
+            std::thread::spawn(move || {
+                for idx in (thread..4000).step_by(4) {
+                    memtable
+                        .for_testing_put_slice(format!("key_{:05}", idx).as_bytes(), b"value")
+                        .unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    // scans see the keys in order while the writers run
+    for _ in 0..10 {
+        let mut iter = memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded);
+        let mut last_key = Vec::new();
+        while iter.is_valid() {
+            assert!(iter.key().key_ref() > &last_key[..]);
+            last_key = iter.key().key_ref().to_vec();
+            iter.next().unwrap();
+        }
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let mut iter = memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded);
+    for idx in 0..4000 {
+        assert_eq!(iter.key().key_ref(), format!("key_{:05}", idx).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
@@ -0,0 +1,82 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{FileObject, SsTable, SsTableBuilder},
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+#[test]
+fn test_mmap_sst_decode() {
+    let mut builder = SsTableBuilder::new(128).with_mmap(true);
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:05}", idx).as_bytes()),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert!(sst.table_file().unwrap().file.is_mmap());
+    let sst2 = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(!sst2.table_file().unwrap().file.is_mmap());
+    assert_eq!(
+        sst.index().unwrap().block_meta,
+        sst2.index().unwrap().block_meta
+    );
+    for idx in 0..sst.num_of_blocks() {
+        let block1 = sst.read_block(idx).unwrap();
+        let block2 = sst2.read_block(idx).unwrap();
+        assert_eq!(block1.data, block2.data);
+        assert_eq!(block1.offsets, block2.offsets);
+    }
+}
+
+#[test]
+fn test_mmap_iterator_survives_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_mmap = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for idx in 0..100 {
+            storage
+                .put(
+                    format!("key_{:05}", idx).as_bytes(),
+                    format!("value_{:05}@{}", idx, round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    // the SST files held by the iterator are removed from the disk here
+    storage.force_full_compaction().unwrap();
+    let mut cnt = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.key(), format!("key_{:05}", cnt).as_bytes());
+        assert_eq!(iter.value(), format!("value_{:05}@2", cnt).as_bytes());
+        iter.next().unwrap();
+        cnt += 1;
+    }
+    assert_eq!(cnt, 100);
+    drop(iter);
+
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(b"key_00010"), Bound::Included(b"key_00011"))
+            .unwrap(),
+        vec![
+            (Bytes::from("key_00010"), Bytes::from("value_00010@2")),
+            (Bytes::from("key_00011"), Bytes::from("value_00011@2")),
+        ],
+    );
+}
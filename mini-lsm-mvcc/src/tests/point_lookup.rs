@@ -0,0 +1,110 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction)
+}
+
+fn block_cache_accesses(storage: &MiniLsm) -> u64 {
+    let stats = storage.block_cache_stats();
+    stats.hits + stats.misses
+}
+
+#[test]
+fn test_point_lookup_across_sources() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage
+            .put(format!("key_{:03}", idx).as_bytes(), b"level")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    let level_snapshot = storage.new_txn().unwrap();
+
+    storage.put(b"key_001", b"l0").unwrap();
+    storage.delete(b"key_002").unwrap();
+    storage.force_flush().unwrap();
+    let l0_snapshot = storage.new_txn().unwrap();
+
+    storage.put(b"key_002", b"imm").unwrap();
+    storage.put(b"key_003", b"imm").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"key_003", b"memtable").unwrap();
+    storage.delete(b"key_004").unwrap();
+
+    let get = |key: &[u8]| storage.get(key).unwrap();
+    assert_eq!(get(b"key_000"), Some(Bytes::from_static(b"level")));
+    assert_eq!(get(b"key_001"), Some(Bytes::from_static(b"l0")));
+    assert_eq!(get(b"key_002"), Some(Bytes::from_static(b"imm")));
+    assert_eq!(get(b"key_003"), Some(Bytes::from_static(b"memtable")));
+    assert_eq!(get(b"key_004"), None);
+    assert_eq!(get(b"key_100"), None);
+    assert_eq!(get(b"key_0"), None);
+
+    assert_eq!(
+        l0_snapshot.get(b"key_001").unwrap(),
+        Some(Bytes::from_static(b"l0"))
+    );
+    assert_eq!(l0_snapshot.get(b"key_002").unwrap(), None);
+    assert_eq!(
+        l0_snapshot.get(b"key_003").unwrap(),
+        Some(Bytes::from_static(b"level"))
+    );
+    for key in [b"key_001", b"key_002", b"key_003", b"key_004"] {
+        assert_eq!(
+            level_snapshot.get(key).unwrap(),
+            Some(Bytes::from_static(b"level"))
+        );
+    }
+}
+
+#[test]
+fn test_point_lookup_short_circuit() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage
+            .put(format!("key_{:03}", idx).as_bytes(), b"level")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.put(b"key_001", b"l0").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"key_002", b"memtable").unwrap();
+
+    // found in the memtable, no SST is read
+    let accesses = block_cache_accesses(&storage);
+    assert_eq!(
+        storage.get(b"key_002").unwrap(),
+        Some(Bytes::from_static(b"memtable"))
+    );
+    assert_eq!(block_cache_accesses(&storage), accesses);
+
+    // found in L0, the older level is not read
+    let accesses = block_cache_accesses(&storage);
+    assert_eq!(
+        storage.get(b"key_001").unwrap(),
+        Some(Bytes::from_static(b"l0"))
+    );
+    assert_eq!(block_cache_accesses(&storage), accesses + 1);
+
+    // only the SST of the level covering the key is read
+    let accesses = block_cache_accesses(&storage);
+    assert_eq!(
+        storage.get(b"key_050").unwrap(),
+        Some(Bytes::from_static(b"level"))
+    );
+    assert_eq!(block_cache_accesses(&storage), accesses + 1);
+}
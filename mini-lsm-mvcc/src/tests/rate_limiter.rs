@@ -0,0 +1,100 @@
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    rate_limiter::{IoPriority, RateLimiter},
+};
+
+#[test]
+fn test_rate_limiter_throughput() {
+    let limiter = RateLimiter::new(1 << 20);
+    let start = Instant::now();
+    // the first 100KB are the initial burst, the remaining 300KB take 0.3s
+    limiter.request(400 << 10, IoPriority::Low);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    assert_eq!(limiter.stats().low_priority_bytes, 400 << 10);
+}
+
+#[test]
+fn test_rate_limiter_high_priority_first() {
+    let limiter = Arc::new(RateLimiter::new(10 << 10));
+    // drain the initial burst of 1KB
+    limiter.request(1 << 10, IoPriority::High);
+
+    let (tx, rx) = mpsc::channel();
+    let low = {
+        let limiter = limiter.clone();
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            limiter.request(1 << 10, IoPriority::Low);
+            tx.send(IoPriority::Low).unwrap();
+        })
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    limiter.request(1 << 10, IoPriority::High);
+    tx.send(IoPriority::High).unwrap();
+    low.join().unwrap();
+    assert_eq!(rx.recv().unwrap(), IoPriority::High);
+    assert_eq!(rx.recv().unwrap(), IoPriority::Low);
+}
+
+#[test]
+fn test_rate_limiter_auto_tune() {
+    let limiter = Arc::new(RateLimiter::new_auto_tuned(20 << 20, 1 << 30));
+    assert_eq!(limiter.bytes_per_sec(), 1 << 20);
+    let instance1 = limiter.register();
+    let instance2 = limiter.register();
+    instance1.update_pending_compaction_bytes(1 << 29);
+    assert_eq!(limiter.bytes_per_sec(), (1 << 20) + (19 << 20) / 2);
+    // the pending bytes of the instances add up
+    instance2.update_pending_compaction_bytes(1 << 28);
+    instance1.update_pending_compaction_bytes(1 << 28);
+    assert_eq!(limiter.bytes_per_sec(), (1 << 20) + (19 << 20) / 2);
+    drop(instance2);
+    assert_eq!(limiter.bytes_per_sec(), (1 << 20) + (19 << 20) / 4);
+    instance1.update_pending_compaction_bytes(4 << 30);
+    assert_eq!(limiter.bytes_per_sec(), 20 << 20);
+    // a fixed rate cannot be set on an auto-tuned limiter
+    limiter.set_bytes_per_sec(1 << 10);
+    assert_eq!(limiter.bytes_per_sec(), 20 << 20);
+}
+
+#[test]
+fn test_rate_limited_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let limiter = Arc::new(RateLimiter::new(1 << 30));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.rate_limiter = Some(limiter.clone());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        for idx in 0..1000 {
+            storage
+                .put(
+                    format!("key_{:05}", idx).as_bytes(),
+                    format!("value_{}_{:05}", round, idx).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let stats = limiter.stats();
+    assert!(stats.high_priority_bytes > 0);
+    assert_eq!(stats.low_priority_bytes, 0);
+
+    storage.force_full_compaction().unwrap();
+    let stats = limiter.stats();
+    // compaction reads both SSTs and writes the merged one
+    assert!(stats.low_priority_bytes > stats.high_priority_bytes);
+    for idx in 0..1000 {
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(format!("value_1_{:05}", idx).into())
+        );
+    }
+}
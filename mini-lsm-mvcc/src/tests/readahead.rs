@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::{tempdir, TempDir};
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{concat_iterator::SstConcatIterator, StorageIterator},
+    key::KeySlice,
+    lsm_storage::{BlockCache, LsmStorageOptions, MiniLsm},
+    prefetch::PrefetchPool,
+    table::{ReadOptions, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+fn build_sst(
+    dir: &TempDir,
+    id: usize,
+    range: std::ops::Range<usize>,
+    block_cache: Arc<BlockCache>,
+) -> SsTable {
+    let mut builder = SsTableBuilder::new(256);
+    for idx in range {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:05}", idx).as_bytes()),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    builder
+        .build(
+            id,
+            Some(block_cache),
+            dir.path().join(format!("{}.sst", id)),
+        )
+        .unwrap()
+}
+
+fn check_iter(mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>, num_keys: usize) {
+    for idx in 0..num_keys {
+        assert!(iter.is_valid());
+        assert_eq!(
+            iter.key().for_testing_key_ref(),
+            format!("key_{:05}", idx).as_bytes()
+        );
+        assert_eq!(iter.value(), format!("value_{:05}", idx).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_readahead() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let sst = Arc::new(build_sst(&dir, 1, 0..1000, block_cache.clone()));
+    let num_of_blocks = sst.num_of_blocks();
+    assert!(num_of_blocks > 16);
+    let read_options = ReadOptions {
+        readahead_blocks: 4,
+        ..Default::default()
+    };
+    let iter =
+        SsTableIterator::create_and_seek_to_first_with_options(sst.clone(), read_options.clone())
+            .unwrap();
+    check_iter(iter, 1000);
+    let stats = block_cache.stats();
+    // every readahead misses the cache once
+    assert!(stats.misses <= (num_of_blocks as u64 - 1) / 4 + 2);
+    assert_eq!(stats.inserts, num_of_blocks as u64);
+
+    // blocks are read from the cache afterwards
+    let iter = SsTableIterator::create_and_seek_to_first_with_options(sst, read_options).unwrap();
+    check_iter(iter, 1000);
+    assert_eq!(block_cache.stats().inserts, num_of_blocks as u64);
+}
+
+#[test]
+fn test_read_without_filling_cache() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let sst = Arc::new(build_sst(&dir, 1, 0..1000, block_cache.clone()));
+    for readahead_blocks in [1, 4] {
+        let read_options = ReadOptions {
+            fill_cache: false,
+            readahead_blocks,
+            ..Default::default()
+        };
+        let iter =
+            SsTableIterator::create_and_seek_to_first_with_options(sst.clone(), read_options)
+                .unwrap();
+        check_iter(iter, 1000);
+        let stats = block_cache.stats();
+        assert_eq!(stats.inserts, 0);
+        assert_eq!(stats.entries, 0);
+    }
+}
+
+#[test]
+fn test_concat_iterator_prefetch() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let ssts = (0..5)
+        .map(|id| {
+            Arc::new(build_sst(
+                &dir,
+                id,
+                id * 200..(id + 1) * 200,
+                block_cache.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    let read_options = ReadOptions {
+        readahead_blocks: 4,
+        prefetch_tables: 2,
+        prefetch_pool: Some(Arc::new(PrefetchPool::new(2))),
+        ..Default::default()
+    };
+    let iter = SstConcatIterator::create_and_seek_to_first_with_options(
+        ssts.clone(),
+        read_options.clone(),
+    )
+    .unwrap();
+    check_iter(iter, 1000);
+    let mut iter = SstConcatIterator::create_and_seek_to_key_with_options(
+        ssts,
+        KeySlice::for_testing_from_slice_no_ts(b"key_00500"),
+        read_options,
+    )
+    .unwrap();
+    for idx in 500..1000 {
+        assert_eq!(
+            iter.key().for_testing_key_ref(),
+            format!("key_{:05}", idx).as_bytes()
+        );
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_compaction_bypasses_block_cache() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.readahead_blocks = 4;
+    options.target_sst_size = 4096;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        for idx in 0..1000 {
+            storage
+                .put(
+                    format!("key_{:05}", idx).as_bytes(),
+                    format!("value_{:05}@{}", idx, round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let inserts = storage.block_cache_stats().inserts;
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.block_cache_stats().inserts, inserts);
+    assert!(storage.inner.state.read().levels[0].1.len() > 1);
+
+    // scan the compacted level with readahead and prefetching
+    let mut iter = storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    for idx in 0..1000 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), format!("key_{:05}", idx).as_bytes());
+        assert_eq!(iter.value(), format!("value_{:05}@1", idx).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(
+        storage.get(b"key_00042").unwrap(),
+        Some(Bytes::from("value_00042@1"))
+    );
+}
+
+#[test]
+fn test_prefetch_pool_started_by_readahead() {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.readahead_blocks = 1;
+    options.compaction_readahead_blocks = 1;
+    let open_with_data = |dir: &TempDir, options: LsmStorageOptions| {
+        let storage = MiniLsm::open(dir, options).unwrap();
+        for idx in 0..100 {
+            storage
+                .put(format!("key_{:05}", idx).as_bytes(), b"value")
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        storage
+    };
+
+    let dir = tempdir().unwrap();
+    let storage = open_with_data(&dir, options.clone());
+    storage.force_full_compaction().unwrap();
+    storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    // no thread is started without readahead
+    assert!(storage.inner.prefetch_pool.get().is_none());
+
+    options.readahead_blocks = 4;
+    let dir = tempdir().unwrap();
+    let storage = open_with_data(&dir, options);
+    assert!(storage.inner.prefetch_pool.get().is_none());
+    storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    assert!(storage.inner.prefetch_pool.get().is_some());
+}
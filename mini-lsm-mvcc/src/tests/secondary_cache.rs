@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    block_cache::{BlockCache, SecondaryCacheOptions},
+    iterators::StorageIterator,
+    key::KeySlice,
+    table::{ReadOptions, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+fn build_sst(block_cache: Arc<BlockCache>, dir: &tempfile::TempDir) -> SsTable {
+    let mut builder = SsTableBuilder::new(256);
+    for idx in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:05}", idx).as_bytes()),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    let sst = builder
+        .build(1, Some(block_cache), dir.path().join("1.sst"))
+        .unwrap();
+    assert!(sst.num_of_blocks() > 16);
+    sst
+}
+
+fn read_all_blocks(sst: &SsTable, block_cache: &BlockCache) {
+    for idx in 0..sst.num_of_blocks() {
+        let block = sst.read_block_cached(idx).unwrap();
+        assert_eq!(block.data, sst.read_block(idx).unwrap().data);
+        assert_eq!(block.offsets, sst.read_block(idx).unwrap().offsets);
+        // apply the pending evictions
+        block_cache.stats();
+    }
+}
+
+#[test]
+fn test_secondary_cache_arena() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(
+        BlockCache::new_with_secondary_cache(
+            4096,
+            &SecondaryCacheOptions {
+                arena_capacity: 1 << 20,
+                cache_file: None,
+                cache_file_capacity: 0,
+            },
+        )
+        .unwrap(),
+    );
+    let sst = build_sst(block_cache.clone(), &dir);
+    read_all_blocks(&sst, &block_cache);
+    let stats = block_cache.stats();
+    assert!(stats.evictions > 0);
+    let secondary = stats.secondary.unwrap();
+    assert_eq!(secondary.arena.inserts, stats.evictions);
+    assert_eq!(secondary.arena.hits, 0);
+    assert_eq!(secondary.file, None);
+    // blocks are compressed in the arena
+    assert!(secondary.arena.size < stats.evictions * 256);
+
+    read_all_blocks(&sst, &block_cache);
+    let stats = block_cache.stats();
+    let secondary = stats.secondary.unwrap();
+    assert!(secondary.arena.hits > 0);
+    assert_eq!(secondary.arena.hits + secondary.arena.misses, stats.misses);
+}
+
+#[test]
+fn test_secondary_cache_file() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(
+        BlockCache::new_with_secondary_cache(
+            4096,
+            &SecondaryCacheOptions {
+                arena_capacity: 1024,
+                cache_file: Some(dir.path().join("block_cache")),
+                cache_file_capacity: 1 << 20,
+            },
+        )
+        .unwrap(),
+    );
+    let sst = build_sst(block_cache.clone(), &dir);
+    read_all_blocks(&sst, &block_cache);
+    let secondary = block_cache.stats().secondary.unwrap();
+    assert!(secondary.arena.evictions > 0);
+    assert!(secondary.arena.size <= 1024);
+    let file = secondary.file.unwrap();
+    assert_eq!(file.inserts, secondary.arena.evictions);
+
+    read_all_blocks(&sst, &block_cache);
+    let secondary = block_cache.stats().secondary.unwrap();
+    assert!(secondary.file.unwrap().hits > 0);
+}
+
+#[test]
+fn test_secondary_cache_readahead() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(
+        BlockCache::new_with_secondary_cache(
+            4096,
+            &SecondaryCacheOptions {
+                arena_capacity: 1 << 20,
+                cache_file: None,
+                cache_file_capacity: 0,
+            },
+        )
+        .unwrap(),
+    );
+    let sst = Arc::new(build_sst(block_cache.clone(), &dir));
+    read_all_blocks(&sst, &block_cache);
+    let hits = block_cache.stats().secondary.unwrap().arena.hits;
+
+    // reads bypassing the cache still look into the secondary cache before the disk
+    let read_options = ReadOptions {
+        fill_cache: false,
+        readahead_blocks: 4,
+        ..Default::default()
+    };
+    let mut iter =
+        SsTableIterator::create_and_seek_to_first_with_options(sst, read_options).unwrap();
+    for idx in 0..1000 {
+        assert_eq!(
+            iter.key().for_testing_key_ref(),
+            format!("key_{:05}", idx).as_bytes()
+        );
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert!(block_cache.stats().secondary.unwrap().arena.hits > hits);
+}
@@ -0,0 +1,209 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionTriggerOptions, LeveledCompactionOptions,
+        SimpleLeveledCompactionOptions,
+    },
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MutableOptions},
+};
+
+fn simple_options(level0_file_num_compaction_trigger: usize) -> SimpleLeveledCompactionOptions {
+    SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger,
+        max_levels: 3,
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:01000}", idx).into_bytes()
+}
+
+fn put_range(storage: &Arc<LsmStorageInner>, begin: usize, end: usize) {
+    for i in begin..end {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+}
+
+fn flush_all(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    while !storage.state.read().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+#[test]
+fn test_set_target_sst_size() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(simple_options(100)));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    put_range(&storage, 0, 100);
+    assert!(storage.state.read().imm_memtables.is_empty());
+
+    // the memtable is frozen at the new size
+    storage
+        .set_options(MutableOptions {
+            target_sst_size: Some(16 << 10),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(storage.current_options().options.target_sst_size, 16 << 10);
+    put_range(&storage, 100, 200);
+    assert!(!storage.state.read().imm_memtables.is_empty());
+
+    // and compactions write SSTs of the new size
+    flush_all(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(3))
+        .unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.levels[2].1.len() > 1);
+    for i in 0..200 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(value_of(i)))
+        );
+    }
+}
+
+#[test]
+fn test_set_compaction_options() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(simple_options(4)));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for _ in 0..2 {
+        put_range(&storage, 0, 10);
+        flush_all(&storage);
+    }
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 2);
+
+    // the new controller compacts L0 at the lower trigger
+    storage
+        .set_options(MutableOptions {
+            compaction_options: Some(CompactionOptions::Simple(simple_options(2))),
+            ..Default::default()
+        })
+        .unwrap();
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert_eq!(state.levels[0].1.len(), 1);
+}
+
+#[test]
+fn test_set_invalid_options() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(simple_options(4)));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+
+    let invalid_options = [
+        MutableOptions {
+            target_sst_size: Some(0),
+            ..Default::default()
+        },
+        MutableOptions {
+            compaction_options: Some(CompactionOptions::Leveled(LeveledCompactionOptions {
+                level_size_multiplier: 10,
+                level0_file_num_compaction_trigger: 4,
+                max_levels: 3,
+                base_level_size_mb: 1,
+            })),
+            ..Default::default()
+        },
+        MutableOptions {
+            compaction_options: Some(CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                max_levels: 4,
+                ..simple_options(4)
+            })),
+            ..Default::default()
+        },
+        MutableOptions {
+            max_subcompactions: Some(0),
+            ..Default::default()
+        },
+        MutableOptions {
+            max_subcompactions: Some(usize::MAX),
+            ..Default::default()
+        },
+        // the valid changes are not applied either
+        MutableOptions {
+            num_memtable_limit: Some(10),
+            compaction_triggers: Some(CompactionTriggerOptions {
+                tombstone_ratio: Some(1.5),
+                max_sst_age: None,
+            }),
+            ..Default::default()
+        },
+    ];
+    for options in invalid_options {
+        assert!(storage.set_options(options).is_err());
+        let current = storage.current_options();
+        let options = &current.options;
+        assert_eq!(options.target_sst_size, 1 << 20);
+        assert_eq!(options.num_memtable_limit, 2);
+        assert!(matches!(
+            options.compaction_options,
+            CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 4,
+                max_levels: 3,
+                ..
+            })
+        ));
+        assert!(options.compaction_triggers.tombstone_ratio.is_none());
+        assert_eq!(options.max_subcompactions, 1);
+    }
+    assert!(!dir.path().join("OPTIONS").exists());
+}
+
+#[test]
+fn test_set_options_persisted() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(simple_options(4)));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options.clone()).unwrap());
+    storage
+        .set_options(MutableOptions {
+            target_sst_size: Some(4 << 20),
+            ..Default::default()
+        })
+        .unwrap();
+    storage
+        .set_options(MutableOptions {
+            compaction_options: Some(CompactionOptions::Simple(simple_options(2))),
+            max_subcompactions: Some(4),
+            ..Default::default()
+        })
+        .unwrap();
+    drop(storage);
+
+    // the options changed by both calls replace the ones given to `open`
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let current = storage.current_options();
+    let options = &current.options;
+    assert_eq!(options.target_sst_size, 4 << 20);
+    assert_eq!(options.max_subcompactions, 4);
+    assert_eq!(options.num_memtable_limit, 2);
+    assert!(matches!(
+        options.compaction_options,
+        CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            ..
+        })
+    ));
+    assert_eq!(storage.options.target_sst_size, 4 << 20);
+}
@@ -0,0 +1,139 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, FixedPrefixSstPartitioner, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MutableOptions},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn leveled_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 4 << 10;
+    options
+}
+
+fn flush_all(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    while !storage.state.read().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+#[test]
+fn test_cut_output_at_grandparent_overlap() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    options.max_grandparent_overlap_bytes = Some(8 << 10);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let value = vec![b'v'; 64];
+    for idx in 0..2000 {
+        storage.put(&key_of(idx), &value).unwrap();
+    }
+    flush_all(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(2))
+        .unwrap();
+    assert!(storage.state.read().levels[1].1.len() > 10);
+
+    // without the limit, the L1 output would fit in a single SST
+    storage
+        .set_options(MutableOptions {
+            target_sst_size: Some(1 << 30),
+            ..Default::default()
+        })
+        .unwrap();
+    for idx in (0..2000).step_by(10) {
+        storage.put(&key_of(idx), b"new").unwrap();
+    }
+    flush_all(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(1))
+        .unwrap();
+
+    let state = storage.state.read().clone();
+    let level1 = &state.levels[0].1;
+    assert!(level1.len() > 1);
+    let grandparents = state.levels[1]
+        .1
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .collect::<Vec<_>>();
+    let max_grandparent_size = grandparents.iter().map(|x| x.table_size()).max().unwrap();
+    for id in level1 {
+        let table = &state.sstables[id];
+        let overlapped_bytes = grandparents
+            .iter()
+            .filter(|x| {
+                x.first_key().key_ref() <= table.last_key().key_ref()
+                    && table.first_key().key_ref() <= x.last_key().key_ref()
+            })
+            .map(|x| x.table_size())
+            .sum::<u64>();
+        // the SST may also overlap the grandparent SSTs containing its first and last key
+        assert!(overlapped_bytes <= (8 << 10) + 2 * max_grandparent_size);
+    }
+    for idx in 0..2000 {
+        let expected = if idx % 10 == 0 { &b"new"[..] } else { &value };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::copy_from_slice(expected))
+        );
+    }
+}
+
+#[test]
+fn test_cut_output_at_partitions() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    options.target_sst_size = 1 << 30;
+    options.sst_partitioner = Some(Arc::new(FixedPrefixSstPartitioner::new(8)));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let key_of = |tenant: usize, idx: usize| format!("tenant{:02}_{:05}", tenant, idx).into_bytes();
+    for round in 0..3 {
+        for tenant in (round..10).step_by(2) {
+            for idx in 0..100 {
+                storage.put(&key_of(tenant, idx), b"value").unwrap();
+            }
+        }
+        flush_all(&storage);
+    }
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, None)
+        .unwrap();
+
+    // one SST per tenant on the bottom level
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    let bottom_level = &state.levels.last().unwrap().1;
+    assert_eq!(bottom_level.len(), 10);
+    for id in bottom_level {
+        let table = &state.sstables[id];
+        assert_eq!(
+            table.first_key().key_ref()[..8],
+            table.last_key().key_ref()[..8]
+        );
+    }
+    for tenant in 0..10 {
+        for idx in 0..100 {
+            assert_eq!(
+                storage.get(&key_of(tenant, idx)).unwrap(),
+                Some(Bytes::from_static(b"value"))
+            );
+        }
+    }
+}
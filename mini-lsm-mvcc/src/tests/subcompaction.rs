@@ -0,0 +1,126 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, TieredCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn value_of(round: usize, idx: usize) -> Vec<u8> {
+    format!("value_{:05}_{:03}", idx, round).into_bytes()
+}
+
+/// Flush all memtables, so that the flush thread does not add L0 SSTs after a compaction.
+fn flush_all(storage: &MiniLsm) {
+    storage.force_flush().unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+#[test]
+fn test_full_compaction_with_subcompactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 4096;
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for idx in (round..1000).step_by(round + 1) {
+            storage
+                .put(format!("key_{:05}", idx).as_bytes(), &value_of(round, idx))
+                .unwrap();
+        }
+        flush_all(&storage);
+    }
+    for idx in (0..1000).step_by(7) {
+        storage
+            .delete(format!("key_{:05}", idx).as_bytes())
+            .unwrap();
+    }
+    flush_all(&storage);
+    // keep an older version alive to compact it in the right subcompaction
+    let txn = storage.new_txn().unwrap();
+    for round in 3..5 {
+        for idx in (0..1000).step_by(3) {
+            storage
+                .put(format!("key_{:05}", idx).as_bytes(), &value_of(round, idx))
+                .unwrap();
+        }
+        flush_all(&storage);
+        storage.force_full_compaction().unwrap();
+    }
+
+    let state = storage.inner.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    let level = &state.levels[0].1;
+    assert!(level.len() > 4);
+    for pair in level.windows(2) {
+        assert!(
+            state.sstables[&pair[0]].last_key().key_ref()
+                < state.sstables[&pair[1]].first_key().key_ref()
+        );
+    }
+    drop(state);
+
+    let expected = |round_limit: usize, idx: usize| {
+        let mut value = None;
+        for round in 0..round_limit.min(3) {
+            if idx >= round && (idx - round).is_multiple_of(round + 1) {
+                value = Some(value_of(round, idx));
+            }
+        }
+        if idx.is_multiple_of(7) {
+            value = None;
+        }
+        if round_limit == 5 && idx.is_multiple_of(3) {
+            value = Some(value_of(4, idx));
+        }
+        value.map(Bytes::from)
+    };
+    for idx in 0..1000 {
+        let key = format!("key_{:05}", idx);
+        assert_eq!(storage.get(key.as_bytes()).unwrap(), expected(5, idx));
+        assert_eq!(txn.get(key.as_bytes()).unwrap(), expected(3, idx));
+    }
+}
+
+#[test]
+fn test_tiered_compaction_with_subcompactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        },
+    ));
+    options.target_sst_size = 4096;
+    options.max_subcompactions = 3;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..10 {
+        for idx in 0..500 {
+            storage
+                .put(format!("key_{:05}", idx).as_bytes(), &value_of(round, idx))
+                .unwrap();
+        }
+        flush_all(&storage);
+        storage.inner.trigger_compaction().unwrap();
+    }
+    for idx in 0..500 {
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from(value_of(9, idx)))
+        );
+    }
+    let state = storage.inner.state.read().clone();
+    for (_, tier) in &state.levels {
+        for pair in tier.windows(2) {
+            assert!(
+                state.sstables[&pair[0]].last_key().key_ref()
+                    < state.sstables[&pair[1]].first_key().key_ref()
+            );
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn table_cache_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.max_open_files = Some(2);
+    options
+}
+
+fn put_ssts(storage: &MiniLsm, num_ssts: usize) {
+    for sst in 0..num_ssts {
+        for idx in 0..100 {
+            storage
+                .put(
+                    format!("key_{:05}", idx * num_ssts + sst).as_bytes(),
+                    format!("value_{:05}", idx * num_ssts + sst).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+}
+
+fn check_ssts(storage: &MiniLsm, num_ssts: usize) {
+    for idx in 0..100 * num_ssts {
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value_{:05}", idx)))
+        );
+    }
+}
+
+#[test]
+fn test_table_cache_max_open_files() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, table_cache_options()).unwrap();
+    put_ssts(&storage, 5);
+    let stats = storage.table_cache_stats().unwrap();
+    assert_eq!(stats.open_files, 2);
+    assert_eq!(stats.evictions, 3);
+
+    check_ssts(&storage, 5);
+    let stats = storage.table_cache_stats().unwrap();
+    assert_eq!(stats.open_files, 2);
+    assert!(stats.misses > 0);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, table_cache_options()).unwrap();
+    assert_eq!(storage.table_cache_stats().unwrap().open_files, 2);
+    check_ssts(&storage, 5);
+}
+
+#[test]
+fn test_table_cache_disabled() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    put_ssts(&storage, 3);
+    assert_eq!(storage.table_cache_stats(), None);
+    check_ssts(&storage, 3);
+}
+
+#[test]
+fn test_table_cache_cached_and_pinned_index() {
+    for pin_l0 in [false, true] {
+        let dir = tempdir().unwrap();
+        let mut options = table_cache_options();
+        options.cache_index_and_filter_blocks = true;
+        options.pin_l0_index_and_filter_blocks = pin_l0;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        put_ssts(&storage, 5);
+        // each SST has one data block and one index in the block cache
+        assert_eq!(storage.block_cache_stats().entries, 5);
+        check_ssts(&storage, 5);
+        assert_eq!(storage.block_cache_stats().entries, 10);
+
+        storage.close().unwrap();
+        drop(storage);
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        check_ssts(&storage, 5);
+    }
+}
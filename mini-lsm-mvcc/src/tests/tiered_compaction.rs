@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, TieredCompactionController, TieredCompactionLimits,
+        TieredCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState},
+    table::SsTable,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn tiered_options(num_tiers: usize) -> TieredCompactionOptions {
+    TieredCompactionOptions {
+        num_tiers,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    }
+}
+
+fn create_state(options: &TieredCompactionOptions) -> LsmStorageState {
+    LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Tiered(options.clone()),
+    ))
+}
+
+/// Add a meta-only SST of `size` bytes covering `[begin, end]`.
+fn add_table(state: &mut LsmStorageState, id: usize, size: u64, begin: usize, end: usize) {
+    let first_key = KeyBytes::for_testing_from_bytes_no_ts(key_of(begin).into());
+    let last_key = KeyBytes::for_testing_from_bytes_no_ts(key_of(end).into());
+    state.sstables.insert(
+        id,
+        Arc::new(SsTable::create_meta_only(id, size, first_key, last_key)),
+    );
+}
+
+#[test]
+fn test_tiered_compaction_by_bytes() {
+    let options = tiered_options(3);
+    let controller = TieredCompactionController::new(options.clone());
+    let mut state = create_state(&options);
+    state.levels = vec![(4, vec![4]), (3, vec![3]), (1, vec![1, 2])];
+    add_table(&mut state, 1, 100, 0, 49);
+    add_table(&mut state, 2, 100, 50, 99);
+    add_table(&mut state, 3, 100, 0, 99);
+    add_table(&mut state, 4, 100, 0, 99);
+
+    // the newer tiers are within the space amplification and the size ratio
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(4, vec![4]), (3, vec![3])]);
+    assert!(!task.bottom_tier_included);
+
+    // the same number of SSTs with more than twice the bytes of the bottom tier
+    add_table(&mut state, 4, 1000, 0, 99);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, state.levels);
+    assert!(task.bottom_tier_included);
+}
+
+#[test]
+fn test_tiered_compaction_max_merge_width() {
+    let mut options = tiered_options(3);
+    options.max_size_amplification_percent = 1000;
+    options.size_ratio = 1000;
+    let limits = TieredCompactionLimits {
+        max_merge_width: Some(2),
+        max_compaction_bytes: None,
+    };
+    let controller = TieredCompactionController::new(options.clone()).with_limits(limits.clone());
+    let mut state = create_state(&options);
+    for id in 1..=5 {
+        state.levels.insert(0, (id, vec![id]));
+        add_table(&mut state, id, 100, 0, 99);
+    }
+
+    // reducing the sorted runs takes the newest tiers up to the width
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(5, vec![5]), (4, vec![4])]);
+    assert!(!task.bottom_tier_included);
+
+    // reducing the space amplification takes the oldest tiers up to the width
+    options.max_size_amplification_percent = 200;
+    let controller = TieredCompactionController::new(options).with_limits(limits);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(2, vec![2]), (1, vec![1])]);
+    assert!(task.bottom_tier_included);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[6]);
+    assert_eq!(removed, vec![2, 1]);
+    assert_eq!(
+        state.levels,
+        vec![(5, vec![5]), (4, vec![4]), (3, vec![3]), (6, vec![6])]
+    );
+}
+
+#[test]
+fn test_tiered_incremental_compaction() {
+    let mut options = tiered_options(2);
+    options.max_size_amplification_percent = 5;
+    let controller =
+        TieredCompactionController::new(options.clone()).with_limits(TieredCompactionLimits {
+            max_merge_width: None,
+            max_compaction_bytes: Some(500),
+        });
+    let mut state = create_state(&options);
+    state.levels = vec![(6, vec![6, 7]), (1, vec![1, 2, 3, 4])];
+    for id in 1..=4 {
+        add_table(&mut state, id, 100, id * 100, id * 100 + 99);
+    }
+    add_table(&mut state, 6, 50, 120, 150);
+    add_table(&mut state, 7, 300, 210, 250);
+
+    // the key range with the most bytes in the newer tiers per byte read is merged first
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.is_incremental);
+    assert!(task.bottom_tier_included);
+    assert_eq!(task.tiers, vec![(6, vec![7]), (1, vec![2])]);
+
+    // the output replaces the range in the bottom tier
+    add_table(&mut state, 8, 200, 200, 249);
+    add_table(&mut state, 9, 200, 250, 299);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[8, 9]);
+    assert_eq!(removed, vec![7, 2]);
+    assert_eq!(state.levels, vec![(6, vec![6]), (1, vec![1, 8, 9, 3, 4])]);
+
+    // the remaining SST of the newer tier is merged next
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.is_incremental);
+    assert_eq!(task.tiers, vec![(6, vec![6]), (1, vec![1])]);
+    let (state, _) = controller.apply_compaction_result(&state, &task, &[10]);
+    assert_eq!(state.levels, vec![(1, vec![10, 8, 9, 3, 4])]);
+}
+
+#[test]
+fn test_tiered_incremental_compaction_integration() {
+    let dir = tempdir().unwrap();
+    let mut compaction_options = tiered_options(3);
+    compaction_options.max_size_amplification_percent = 50;
+    let mut options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(compaction_options));
+    options.tiered_compaction_limits.max_compaction_bytes = Some(48 << 10);
+    options.target_sst_size = 8 << 10;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let value_of = |round: usize, idx: usize| format!("value_{}_{:0200}", round, idx);
+    for round in 0..4 {
+        for idx in (round * 100..1000).step_by(round + 1) {
+            storage
+                .put(&key_of(idx), value_of(round, idx).as_bytes())
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        while !storage.state.read().imm_memtables.is_empty() {
+            storage.force_flush_next_imm_memtable().unwrap();
+        }
+        for _ in 0..20 {
+            storage.trigger_compaction().unwrap();
+        }
+    }
+
+    let state = storage.state.read().clone();
+    for (_, ids) in &state.levels {
+        for pair in ids.windows(2) {
+            assert!(
+                state.sstables[&pair[0]].last_key().key_ref()
+                    < state.sstables[&pair[1]].first_key().key_ref()
+            );
+        }
+    }
+    for idx in 0..1000 {
+        let round = (0..4)
+            .rev()
+            .find(|round| idx >= round * 100 && (idx - round * 100) % (round + 1) == 0)
+            .unwrap();
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(round, idx)))
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bytes::Buf;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, TimeSource, TimeWindowCompactionController, TimeWindowCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm, MutableOptions},
+    table::SsTable,
+};
+
+fn time_key(time: u64) -> KeyBytes {
+    KeyBytes::for_testing_from_bytes_no_ts(time.to_be_bytes().to_vec().into())
+}
+
+fn options(max_windows: Option<usize>) -> TimeWindowCompactionOptions {
+    TimeWindowCompactionOptions {
+        window_size: 10,
+        time_source: TimeSource::Key(Arc::new(|mut key: &[u8]| key.get_u64())),
+        size_ratio: 50,
+        min_merge_width: 3,
+        max_windows,
+    }
+}
+
+fn storage_options(options: TimeWindowCompactionOptions) -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::TimeWindow(options))
+}
+
+/// Create a state with one tier of one meta-only SST per `(size, time)`, from the newest to the
+/// oldest tier.
+fn state_with_tiers(
+    options: &TimeWindowCompactionOptions,
+    tiers: &[(u64, u64)],
+) -> LsmStorageState {
+    let mut state = LsmStorageState::create(&storage_options(options.clone()));
+    for (idx, (size, time)) in tiers.iter().enumerate() {
+        let id = tiers.len() - idx;
+        let table = SsTable::create_meta_only(id, *size, time_key(*time), time_key(*time));
+        state.levels.push((id, vec![id]));
+        state.sstables.insert(id, Arc::new(table));
+    }
+    state
+}
+
+#[test]
+fn test_time_window_active_window() {
+    let options = options(None);
+    let controller = TimeWindowCompactionController::new(options.clone());
+    // tiers 1 and 2 are in a closed window, and the others are in the active window
+    let mut state = state_with_tiers(
+        &options,
+        &[(100, 25), (100, 24), (400, 21), (100, 15), (100, 12)],
+    );
+    assert_eq!(state.levels.len(), 5);
+
+    // the closed window is merged first as the active window has only two similar tiers
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.dropped_tiers.is_empty());
+    assert_eq!(task.tiers, vec![(2, vec![2]), (1, vec![1])]);
+    assert!(task.bottom_tier_included);
+    let (new_state, removed) = controller.apply_compaction_result(&state, &task, &[6]);
+    assert_eq!(removed, vec![2, 1]);
+    let tier_ids = new_state
+        .levels
+        .iter()
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    assert_eq!(tier_ids, vec![5, 4, 3, 6]);
+
+    // the tiers of the closed window are not picked while one of them is being compacted
+    assert!(controller
+        .generate_compaction_task_skipping(&state, &HashSet::from([1]))
+        .is_none());
+
+    // a third small tier in the active window triggers size-tiered merging of the small tiers
+    let id = 7;
+    state.levels.insert(0, (id, vec![id]));
+    state.sstables.insert(
+        id,
+        Arc::new(SsTable::create_meta_only(
+            id,
+            120,
+            time_key(27),
+            time_key(27),
+        )),
+    );
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(7, vec![7]), (5, vec![5]), (4, vec![4])]);
+    assert!(!task.bottom_tier_included);
+    let (state, _) = controller.apply_compaction_result(&state, &task, &[8]);
+    let tier_ids = state.levels.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(tier_ids, vec![8, 3, 2, 1]);
+}
+
+#[test]
+fn test_time_window_expire() {
+    let options = options(Some(2));
+    let controller = TimeWindowCompactionController::new(options.clone());
+    let state = state_with_tiers(
+        &options,
+        &[(100, 35), (100, 25), (100, 15), (100, 12), (100, 5)],
+    );
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.tiers.is_empty());
+    assert_eq!(
+        task.dropped_tiers,
+        vec![(3, vec![3]), (2, vec![2]), (1, vec![1])]
+    );
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[]);
+    assert_eq!(removed, vec![3, 2, 1]);
+    let tier_ids = state.levels.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(tier_ids, vec![5, 4]);
+    assert!(controller.generate_compaction_task(&state).is_none());
+}
+
+#[test]
+fn test_integration_time_window() {
+    let dir = tempdir().unwrap();
+    let compaction_options = TimeWindowCompactionOptions {
+        window_size: 500,
+        time_source: TimeSource::MaxTs,
+        size_ratio: 100,
+        min_merge_width: 2,
+        max_windows: None,
+    };
+    let mut options = storage_options(compaction_options.clone());
+    options.target_sst_size = 4 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let value = vec![b'v'; 64];
+    for idx in 0..3000 {
+        storage
+            .put(format!("key_{:05}", idx).as_bytes(), &value)
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+    // the background compaction thread may run some of the tasks, and the SSTs it is compacting
+    // are skipped by `trigger_compaction`
+    loop {
+        let levels = storage.inner.state.read().levels.clone();
+        storage.inner.trigger_compaction().unwrap();
+        if storage.inner.state.read().levels == levels
+            && storage.inner.compacting_sstables.lock().is_empty()
+        {
+            break;
+        }
+    }
+
+    // every closed window is merged into one tier
+    let state = storage.inner.state.read().clone();
+    let windows = state
+        .levels
+        .iter()
+        .map(|(_, tier)| {
+            tier.iter()
+                .map(|id| state.sstables[id].max_ts() / 500)
+                .max()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let active_window = *windows.iter().max().unwrap();
+    let closed_windows = windows
+        .iter()
+        .filter(|window| **window != active_window)
+        .collect::<Vec<_>>();
+    assert!(closed_windows.len() >= 2);
+    assert_eq!(
+        closed_windows.iter().collect::<HashSet<_>>().len(),
+        closed_windows.len()
+    );
+    for idx in 0..3000 {
+        assert_eq!(
+            storage
+                .get(format!("key_{:05}", idx).as_bytes())
+                .unwrap()
+                .unwrap(),
+            value
+        );
+    }
+    let levels = state.levels.clone();
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels, levels);
+}
+
+#[test]
+fn test_set_time_window_options() {
+    let dir = tempdir().unwrap();
+    let compaction_options = TimeWindowCompactionOptions {
+        window_size: 1 << 40,
+        time_source: TimeSource::MaxTs,
+        size_ratio: 100,
+        min_merge_width: 4,
+        max_windows: None,
+    };
+    let options = storage_options(compaction_options.clone());
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for idx in 0..3 {
+        storage
+            .put(format!("key_{:05}", idx).as_bytes(), b"value")
+            .unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels.len(), 3);
+
+    // the other strategies and an empty window are rejected
+    for compaction_options in [
+        CompactionOptions::NoCompaction,
+        CompactionOptions::TimeWindow(TimeWindowCompactionOptions {
+            window_size: 0,
+            ..compaction_options.clone()
+        }),
+    ] {
+        assert!(storage
+            .set_options(MutableOptions {
+                compaction_options: Some(compaction_options),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    // the new controller merges the tiers of the active window at the lower width
+    storage
+        .set_options(MutableOptions {
+            compaction_options: Some(CompactionOptions::TimeWindow(TimeWindowCompactionOptions {
+                min_merge_width: 3,
+                ..compaction_options
+            })),
+            ..Default::default()
+        })
+        .unwrap();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels.len(), 1);
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionController,
+        SimpleLeveledCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState},
+    table::SsTable,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+fn put_range(storage: &Arc<LsmStorageInner>, begin: usize, end: usize) {
+    for i in begin..end {
+        storage.put(&key_of(i), &key_of(i)).unwrap();
+    }
+    flush(storage);
+}
+
+#[test]
+fn test_leveled_trivial_move() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options.clone()).unwrap());
+    put_range(&storage, 0, 100);
+    put_range(&storage, 100, 200);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    assert_eq!(l0_sstables.len(), 2);
+
+    // the L0 SSTs overlap neither with each other nor with the base level
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert_eq!(
+        state.levels[2].1,
+        vec![l0_sstables[1], l0_sstables[0]],
+        "SSTs should be moved in key order"
+    );
+    for id in &l0_sstables {
+        assert!(storage.path_of_sst(*id).exists());
+    }
+
+    // an SST overlapping with the base level is rewritten, the others are left alone
+    put_range(&storage, 50, 60);
+    put_range(&storage, 60, 70);
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert!(!state.levels[2].1.contains(&l0_sstables[1]));
+    assert!(state.levels[2].1.contains(&l0_sstables[0]));
+    assert!(!storage.path_of_sst(l0_sstables[1]).exists());
+
+    // the moves are replayed from the manifest
+    drop(storage);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    assert_eq!(storage.state.read().levels, state.levels);
+    for i in 0..200 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(key_of(i)))
+        );
+    }
+}
+
+fn meta_only_table(id: usize, begin: usize, end: usize) -> Arc<SsTable> {
+    let first_key = KeyBytes::for_testing_from_bytes_no_ts(key_of(begin).into());
+    let last_key = KeyBytes::for_testing_from_bytes_no_ts(key_of(end).into());
+    Arc::new(SsTable::create_meta_only(id, 100, first_key, last_key))
+}
+
+#[test]
+fn test_simple_leveled_trivial_move() {
+    let options = SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 100,
+        max_levels: 2,
+    };
+    let controller = SimpleLeveledCompactionController::new(options.clone());
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Simple(options),
+    ));
+    state.levels[0].1 = vec![1];
+    state.levels[1].1 = vec![2];
+    state.sstables.insert(1, meta_only_table(1, 0, 10));
+    state.sstables.insert(2, meta_only_table(2, 20, 30));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.is_trivial_move);
+    let (new_state, removed) = controller.apply_compaction_result(&state, &task, &[1]);
+    assert!(removed.is_empty());
+    assert!(new_state.levels[0].1.is_empty());
+    assert_eq!(new_state.levels[1].1, vec![1, 2]);
+
+    // overlapping SSTs are rewritten
+    state.sstables.insert(2, meta_only_table(2, 5, 30));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(!task.is_trivial_move);
+}
+
+#[test]
+fn test_replay_simple_leveled_trivial_moves() {
+    let options = SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 100,
+        max_levels: 3,
+    };
+    let controller = SimpleLeveledCompactionController::new(options.clone());
+    let mut state = LsmStorageState::create(&LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Simple(options),
+    ));
+    state.levels[0].1 = vec![1];
+    state.levels[1].1 = vec![2];
+    state.sstables.insert(1, meta_only_table(1, 0, 10));
+    state.sstables.insert(2, meta_only_table(2, 20, 30));
+    // the SSTs are not opened yet when the manifest is replayed
+    let mut replayed_state = state.clone();
+    replayed_state.sstables.clear();
+    for _ in 0..2 {
+        let task = controller.generate_compaction_task(&state).unwrap();
+        assert!(task.is_trivial_move);
+        let output = task.upper_level_sst_ids.clone();
+        (state, _) = controller.apply_compaction_result(&state, &task, &output);
+        (replayed_state, _) = controller.apply_compaction_result(&replayed_state, &task, &output);
+    }
+    assert_eq!(state.levels[2].1, vec![1, 2]);
+    assert_eq!(replayed_state.levels, state.levels);
+}
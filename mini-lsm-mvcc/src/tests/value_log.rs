@@ -0,0 +1,193 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    value_log::ValueLogOptions,
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+fn value_log_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_log = Some(ValueLogOptions {
+        value_threshold: 64,
+        gc_discard_ratio: 0.5,
+    });
+    options
+}
+
+fn large_value(idx: usize, round: usize) -> Bytes {
+    Bytes::from(format!("value_{:05}@{}_{}", idx, round, "x".repeat(100)))
+}
+
+fn vlog_files(path: &Path) -> Vec<String> {
+    let mut files: Vec<_> = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".vlog"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_value_log_separation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    for idx in 0..100 {
+        let key = format!("key_{:05}", idx);
+        if idx % 2 == 0 {
+            storage.put(key.as_bytes(), &large_value(idx, 0)).unwrap();
+        } else {
+            storage.put(key.as_bytes(), b"small").unwrap();
+        }
+    }
+    storage.delete(b"key_00000").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(vlog_files(dir.path()).len(), 1);
+
+    assert_eq!(storage.get(b"key_00000").unwrap(), None);
+    assert_eq!(
+        storage.get(b"key_00001").unwrap(),
+        Some(Bytes::from("small"))
+    );
+    assert_eq!(storage.get(b"key_00002").unwrap(), Some(large_value(2, 0)));
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(b"key_00000"), Bound::Included(b"key_00003"))
+            .unwrap(),
+        vec![
+            (Bytes::from("key_00001"), Bytes::from("small")),
+            (Bytes::from("key_00002"), large_value(2, 0)),
+            (Bytes::from("key_00003"), Bytes::from("small")),
+        ],
+    );
+
+    storage.close().unwrap();
+    drop(storage);
+    // a value log file left by a flush interrupted before the manifest record is removed on open
+    std::fs::write(dir.path().join("99999.vlog"), b"orphan").unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    assert_eq!(vlog_files(dir.path()).len(), 1);
+    assert_eq!(storage.get(b"key_00098").unwrap(), Some(large_value(98, 0)));
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    let put_round = |round: usize, step: usize| {
+        for idx in (0..100).step_by(step) {
+            storage
+                .put(
+                    format!("key_{:05}", idx).as_bytes(),
+                    &large_value(idx, round),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    };
+    put_round(0, 1);
+    put_round(1, 2);
+    let txn = storage.new_txn().unwrap();
+    put_round(2, 2);
+    assert_eq!(vlog_files(dir.path()).len(), 3);
+
+    // the snapshot keeps the even keys of round 1 alive, so only round 0 has garbage, and the odd
+    // keys of round 0 are relocated
+    storage.force_full_compaction().unwrap();
+    storage.force_value_log_gc().unwrap();
+    assert_eq!(vlog_files(dir.path()).len(), 3);
+    for idx in 0..100 {
+        let key = format!("key_{:05}", idx);
+        let (snapshot_round, latest_round) = if idx % 2 == 0 { (1, 2) } else { (0, 0) };
+        assert_eq!(
+            txn.get(key.as_bytes()).unwrap(),
+            Some(large_value(idx, snapshot_round))
+        );
+        assert_eq!(
+            storage.get(key.as_bytes()).unwrap(),
+            Some(large_value(idx, latest_round))
+        );
+    }
+    drop(txn);
+
+    // round 1 becomes garbage once compacted again without the snapshot
+    storage.force_full_compaction().unwrap();
+    storage.force_value_log_gc().unwrap();
+    assert_eq!(vlog_files(dir.path()).len(), 2);
+    for idx in 0..100 {
+        let latest_round = if idx % 2 == 0 { 2 } else { 0 };
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(large_value(idx, latest_round))
+        );
+    }
+}
+
+/// Put a value of every key with a step of `step`, and flush. The second flush into L0 triggers
+/// a compaction of all SSTs into L1, which the function waits for.
+fn put_and_wait_for_compaction(storage: &MiniLsm, round: usize, step: usize) {
+    for idx in (0..100).step_by(step) {
+        storage
+            .put(
+                format!("key_{:05}", idx).as_bytes(),
+                &large_value(idx, round),
+            )
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    let start = Instant::now();
+    while storage.inner.state.read().l0_sstables.len() > 1 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_value_log_discard_persisted() {
+    let dir = tempdir().unwrap();
+    let mut options = value_log_options();
+    options.compaction_options = CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 1,
+    });
+    // only collect the garbage in `force_value_log_gc`
+    options.value_log.as_mut().unwrap().gc_discard_ratio = 2.0;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..2 {
+        put_and_wait_for_compaction(&storage, round, 1);
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // the values of round 0 discarded before the restart are still collected
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.force_value_log_gc().unwrap();
+    assert_eq!(vlog_files(dir.path()).len(), 1);
+    for idx in 0..100 {
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(large_value(idx, 1))
+        );
+    }
+
+    // a truncated file fails the GC instead of panicking
+    for round in 2..4 {
+        put_and_wait_for_compaction(&storage, round, 2);
+    }
+    storage.close().unwrap();
+    drop(storage);
+    let file = dir.path().join(&vlog_files(dir.path())[0]);
+    let data = std::fs::read(&file).unwrap();
+    std::fs::write(&file, &data[..data.len() - 10]).unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.force_value_log_gc().is_err());
+}
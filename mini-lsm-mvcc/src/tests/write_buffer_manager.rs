@@ -0,0 +1,91 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    block_cache::BlockCache,
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    write_buffer_manager::WriteBufferManager,
+};
+
+fn wait_until(mut f: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !f() {
+        assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_write_buffer_manager_flushes_largest_instance() {
+    let manager = Arc::new(WriteBufferManager::new(64 << 10));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.write_buffer_manager = Some(manager.clone());
+    let dir1 = tempdir().unwrap();
+    let dir2 = tempdir().unwrap();
+    let storage1 = MiniLsm::open(&dir1, options.clone()).unwrap();
+    let storage2 = MiniLsm::open(&dir2, options).unwrap();
+    assert_eq!(manager.stats().instances, 2);
+
+    let value = vec![b'v'; 100];
+    for idx in 0..10 {
+        storage2
+            .put(format!("key_{:05}", idx).as_bytes(), &value)
+            .unwrap();
+    }
+    // each instance is far below `target_sst_size`, but together they exceed the budget
+    for idx in 0..1000 {
+        storage1
+            .put(format!("key_{:05}", idx).as_bytes(), &value)
+            .unwrap();
+    }
+    assert!(manager.stats().flush_requests > 0);
+    wait_until(|| manager.memory_usage() <= 64 << 10);
+    assert!(!storage1.inner.state.read().l0_sstables.is_empty());
+    // the smaller instance keeps its memtable
+    assert!(storage2.inner.state.read().l0_sstables.is_empty());
+    assert!(!storage2.inner.state.read().memtable.is_empty());
+    for idx in 0..1000 {
+        assert_eq!(
+            storage1
+                .get(format!("key_{:05}", idx).as_bytes())
+                .unwrap()
+                .as_deref(),
+            Some(&value[..])
+        );
+    }
+
+    storage2.close().unwrap();
+    drop(storage2);
+    assert_eq!(manager.stats().instances, 1);
+}
+
+#[test]
+fn test_write_buffer_manager_charges_block_cache() {
+    let block_cache = BlockCache::new(4 << 20);
+    let manager = Arc::new(WriteBufferManager::new_with_block_cache(
+        1 << 20,
+        &block_cache,
+    ));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.write_buffer_manager = Some(manager.clone());
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let value = vec![b'v'; 1000];
+    for idx in 0..500 {
+        storage
+            .put(format!("key_{:05}", idx).as_bytes(), &value)
+            .unwrap();
+    }
+    let usage = manager.memory_usage() as u64;
+    assert!(usage >= 500 * 1000);
+    let stats = block_cache.stats();
+    assert!(stats.size >= usage);
+    assert!(stats.size < usage + (256 << 10));
+
+    storage.force_flush().unwrap();
+    assert_eq!(manager.memory_usage(), 0);
+    assert_eq!(block_cache.stats().size, 0);
+}
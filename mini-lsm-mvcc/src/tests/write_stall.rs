@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    write_stall::{WriteController, WriteStallCause, WriteStallCondition, WriteStallOptions},
+};
+
+fn write_stall_options() -> WriteStallOptions {
+    WriteStallOptions {
+        slowdown_imm_memtables: 2,
+        stop_imm_memtables: 3,
+        slowdown_l0_files: 4,
+        stop_l0_files: 6,
+        slowdown_pending_compaction_bytes: 1 << 20,
+        stop_pending_compaction_bytes: 2 << 20,
+        slowdown_delay: Duration::from_millis(1),
+    }
+}
+
+#[test]
+fn test_write_controller_conditions() {
+    let controller = WriteController::new(Some(write_stall_options()));
+    controller.update(1, 3, 0, true);
+    assert_eq!(controller.stats().condition, WriteStallCondition::Normal);
+    assert_eq!(controller.stats().cause, None);
+    controller.update(2, 0, 0, true);
+    assert_eq!(controller.stats().condition, WriteStallCondition::Delayed);
+    assert_eq!(
+        controller.stats().cause,
+        Some(WriteStallCause::ImmMemtables)
+    );
+    controller.update(0, 4, 0, true);
+    assert_eq!(controller.stats().cause, Some(WriteStallCause::L0Files));
+    // L0 files and pending compaction bytes are ignored without compaction
+    controller.update(0, 6, 2 << 20, false);
+    assert_eq!(controller.stats().condition, WriteStallCondition::Normal);
+    controller.update(0, 0, 2 << 20, true);
+    assert_eq!(controller.stats().condition, WriteStallCondition::Stopped);
+    assert_eq!(
+        controller.stats().cause,
+        Some(WriteStallCause::PendingCompactionBytes)
+    );
+
+    // a stopped write resumes once the condition recovers
+    let controller = Arc::new(controller);
+    let start = Instant::now();
+    let writer = {
+        let controller = controller.clone();
+        std::thread::spawn(move || controller.wait_for_write().unwrap())
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    controller.update(0, 0, 1 << 20, true);
+    writer.join().unwrap();
+    let stats = controller.stats();
+    assert_eq!(stats.stopped_writes, 1);
+    // the writer may start waiting later than the sleep starts
+    assert!(stats.stopped_duration > Duration::ZERO);
+    assert!(stats.stopped_duration <= start.elapsed());
+    assert_eq!(stats.delayed_writes, 1);
+    assert!(stats.delayed_duration >= Duration::from_millis(1));
+
+    // a stopped write fails once the storage is closed
+    controller.update(0, 0, 2 << 20, true);
+    let writer = {
+        let controller = controller.clone();
+        std::thread::spawn(move || controller.wait_for_write())
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    controller.close();
+    assert!(writer.join().unwrap().is_err());
+    assert!(controller.wait_for_write().is_err());
+}
+
+#[test]
+fn test_write_stall_on_imm_memtables() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 1024;
+    options.num_memtable_limit = 100;
+    options.write_stall = Some(write_stall_options());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let value = vec![b'v'; 100];
+    for idx in 0..300 {
+        storage
+            .put(format!("key_{:05}", idx).as_bytes(), &value)
+            .unwrap();
+        // a write batch may freeze at most one memtable after writes stop
+        assert!(storage.inner.state.read().imm_memtables.len() <= 4);
+    }
+    let stats = storage.write_stall_stats();
+    assert!(stats.stopped_writes > 0);
+    assert!(stats.stopped_duration > Duration::ZERO);
+    assert!(stats.delayed_writes > 0);
+    for idx in 0..300 {
+        assert_eq!(
+            storage.get(format!("key_{:05}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from(value.clone()))
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! Key-value separation. Values of at least `value_threshold` bytes are written to value log files
+//! when an SST is built, and the SST only stores a pointer to them. Compactions move the pointers
+//! around without rewriting the values, and the value log GC relocates the live values of files
+//! with a lot of garbage.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hasher;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::{MutexGuard, RwLock};
+
+use crate::iterators::StorageIterator;
+use crate::key::{KeyBytes, KeySlice};
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::ManifestRecord;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::table::FileObject;
+
+/// Tag of an SST value that is stored inline.
+pub const VALUE_TAG_INLINE: u8 = 0;
+/// Tag of an SST value that is a pointer into the value log.
+pub const VALUE_TAG_POINTER: u8 = 1;
+
+#[derive(Debug, Clone)]
+pub struct ValueLogOptions {
+    /// Values of at least this size are stored in the value log.
+    pub value_threshold: usize,
+    /// A value log file is garbage-collected once this ratio of its bytes is discarded.
+    pub gc_discard_ratio: f64,
+}
+
+/// A pointer to a record in the value log.
+///
+/// The pointer always refers to the file and the offset where the record was first written. When
+/// the GC relocates a record, the record keeps this identity, so that the SSTs do not need to be
+/// rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValuePointer {
+    pub file_id: usize,
+    pub offset: u64,
+    /// The length of the whole record.
+    pub len: u32,
+}
+
+impl ValuePointer {
+    pub const ENCODED_LEN: usize = 20;
+
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0; Self::ENCODED_LEN];
+        let mut slice = &mut buf[..];
+        slice.put_u64(self.file_id as u64);
+        slice.put_u64(self.offset);
+        slice.put_u32(self.len);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN {
+            bail!("invalid value pointer length: {}", buf.len());
+        }
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u32(),
+        })
+    }
+}
+
+/// A record in a value log file, decoded.
+pub(crate) struct ValueLogRecord {
+    /// The identity of the record.
+    pub(crate) pointer: ValuePointer,
+    pub(crate) key: KeyBytes,
+    pub(crate) value: Bytes,
+}
+
+impl ValueLogRecord {
+    /// Decode a record from the beginning of `buf`, returning the record and its length. Fails if
+    /// `buf` ends before the record does.
+    fn decode(buf: Bytes) -> Result<(Self, usize)> {
+        let mut rbuf = &buf[..];
+        let mut hasher = crc32fast::Hasher::new();
+        Self::check_remaining(rbuf, 8 + 8 + 2)?;
+        let file_id = rbuf.get_u64();
+        hasher.write_u64(file_id);
+        let offset = rbuf.get_u64();
+        hasher.write_u64(offset);
+        let key_len = rbuf.get_u16() as usize;
+        hasher.write_u16(key_len as u16);
+        let key_begin = buf.len() - rbuf.remaining();
+        Self::check_remaining(rbuf, key_len + 8 + 4)?;
+        hasher.write(&rbuf[..key_len]);
+        rbuf.advance(key_len);
+        let ts = rbuf.get_u64();
+        hasher.write_u64(ts);
+        let value_len = rbuf.get_u32() as usize;
+        hasher.write_u32(value_len as u32);
+        let value_begin = buf.len() - rbuf.remaining();
+        Self::check_remaining(rbuf, value_len + 4)?;
+        hasher.write(&rbuf[..value_len]);
+        rbuf.advance(value_len);
+        let checksum = rbuf.get_u32();
+        if hasher.finalize() != checksum {
+            bail!("value log checksum mismatch");
+        }
+        let len = buf.len() - rbuf.remaining();
+        let record = Self {
+            pointer: ValuePointer {
+                file_id: file_id as usize,
+                offset,
+                len: len as u32,
+            },
+            key: KeyBytes::from_bytes_with_ts(buf.slice(key_begin..key_begin + key_len), ts),
+            value: buf.slice(value_begin..value_begin + value_len),
+        };
+        Ok((record, len))
+    }
+
+    fn check_remaining(buf: &[u8], len: usize) -> Result<()> {
+        if buf.remaining() < len {
+            bail!("value log record truncated");
+        }
+        Ok(())
+    }
+}
+
+/// Builds a value log file. The file is written to the disk in `finish`.
+///
+/// ```text
+/// | orig file id (u64) | orig offset (u64) | key_len (u16) | key | ts (u64) | value_len (u32) | value | checksum (u32) |
+/// ```
+pub struct ValueLogBuilder {
+    id: usize,
+    path: PathBuf,
+    data: Vec<u8>,
+    /// Write the file at the pace of the rate limiter with the priority.
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
+}
+
+impl ValueLogBuilder {
+    pub fn new(id: usize, path: impl AsRef<Path>) -> Self {
+        Self {
+            id,
+            path: path.as_ref().to_path_buf(),
+            data: Vec::new(),
+            rate_limiter: None,
+        }
+    }
+
+    /// Write the value log file at the pace allowed by `rate_limiter`, at `priority`.
+    pub fn with_rate_limiter(
+        mut self,
+        rate_limiter: Arc<RateLimiter>,
+        priority: IoPriority,
+    ) -> Self {
+        self.rate_limiter = Some((rate_limiter, priority));
+        self
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Append a value and return the pointer to it.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> ValuePointer {
+        let offset = self.data.len() as u64;
+        self.put_record(self.id, offset, key, value)
+    }
+
+    /// Append a record relocated by the GC, which keeps the identity of the record.
+    pub(crate) fn add_relocated(&mut self, record: &ValueLogRecord) {
+        self.put_record(
+            record.pointer.file_id,
+            record.pointer.offset,
+            record.key.as_key_slice(),
+            &record.value,
+        );
+    }
+
+    fn put_record(
+        &mut self,
+        file_id: usize,
+        offset: u64,
+        key: KeySlice,
+        value: &[u8],
+    ) -> ValuePointer {
+        let begin = self.data.len();
+        let buf = &mut self.data;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.write_u64(file_id as u64);
+        buf.put_u64(file_id as u64);
+        hasher.write_u64(offset);
+        buf.put_u64(offset);
+        hasher.write_u16(key.key_len() as u16);
+        buf.put_u16(key.key_len() as u16);
+        hasher.write(key.key_ref());
+        buf.put_slice(key.key_ref());
+        hasher.write_u64(key.ts());
+        buf.put_u64(key.ts());
+        hasher.write_u32(value.len() as u32);
+        buf.put_u32(value.len() as u32);
+        hasher.write(value);
+        buf.put_slice(value);
+        buf.put_u32(hasher.finalize());
+        ValuePointer {
+            file_id,
+            offset,
+            len: (self.data.len() - begin) as u32,
+        }
+    }
+
+    /// Write the value log file to the disk. Nothing is written if no value is added.
+    pub fn finish(self) -> Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        FileObject::create(
+            &self.path,
+            self.data,
+            false,
+            self.rate_limiter
+                .as_ref()
+                .map(|(rate_limiter, priority)| (rate_limiter.as_ref(), *priority)),
+        )?;
+        Ok(())
+    }
+}
+
+/// An opened value log file.
+pub(crate) struct ValueLogFile {
+    id: usize,
+    file: FileObject,
+    /// Maps the identity of the records relocated into this file to their offsets, built on the
+    /// first read of a relocated record.
+    relocated: OnceLock<HashMap<(usize, u64), u64>>,
+}
+
+impl ValueLogFile {
+    fn open(id: usize, path: &Path, enable_mmap: bool) -> Result<Self> {
+        Ok(Self {
+            id,
+            file: FileObject::open_with_mode(path, enable_mmap)?,
+            relocated: OnceLock::new(),
+        })
+    }
+
+    fn read(&self, pointer: ValuePointer) -> Result<Bytes> {
+        let offset = if pointer.file_id == self.id {
+            pointer.offset
+        } else {
+            if self.relocated.get().is_none() {
+                let index = self
+                    .records()?
+                    .into_iter()
+                    .map(|(record, offset)| {
+                        ((record.pointer.file_id, record.pointer.offset), offset)
+                    })
+                    .collect();
+                let _ = self.relocated.set(index);
+            }
+            match self
+                .relocated
+                .get()
+                .unwrap()
+                .get(&(pointer.file_id, pointer.offset))
+            {
+                Some(offset) => *offset,
+                None => bail!("value {:?} not found in {}.vlog", pointer, self.id),
+            }
+        };
+        let (record, _) =
+            ValueLogRecord::decode(self.file.read_bytes(offset, pointer.len as u64)?)?;
+        if record.pointer.file_id != pointer.file_id || record.pointer.offset != pointer.offset {
+            bail!("value {:?} not found in {}.vlog", pointer, self.id);
+        }
+        Ok(record.value)
+    }
+
+    /// Read all records together with their offsets in this file.
+    fn records(&self) -> Result<Vec<(ValueLogRecord, u64)>> {
+        let buf = self.file.read_bytes(0, self.file.size())?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let (record, len) = ValueLogRecord::decode(buf.slice(offset..))?;
+            records.push((record, offset as u64));
+            offset += len;
+        }
+        Ok(records)
+    }
+}
+
+#[derive(Default)]
+struct ValueLogState {
+    files: HashMap<usize, Arc<ValueLogFile>>,
+    /// Maps a garbage-collected file to the file now holding its live records, or `None` if it had
+    /// no live records.
+    redirects: HashMap<usize, Option<usize>>,
+    /// The discarded bytes of each file, reported by compactions and replayed from the manifest on
+    /// open.
+    discarded: HashMap<usize, u64>,
+}
+
+/// The value log files of the storage engine.
+pub struct ValueLog {
+    path: PathBuf,
+    enable_mmap: bool,
+    state: RwLock<ValueLogState>,
+}
+
+impl ValueLog {
+    pub(crate) fn new(path: impl AsRef<Path>, enable_mmap: bool) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            enable_mmap,
+            state: RwLock::new(ValueLogState::default()),
+        }
+    }
+
+    fn holder(state: &ValueLogState, file_id: usize) -> Result<usize> {
+        match state.redirects.get(&file_id) {
+            Some(Some(holder)) => Ok(*holder),
+            Some(None) => bail!("{}.vlog is garbage-collected without live values", file_id),
+            None => Ok(file_id),
+        }
+    }
+
+    /// Get the file currently holding the records first written to `file_id`.
+    fn holder_file(&self, file_id: usize) -> Result<Arc<ValueLogFile>> {
+        {
+            let state = self.state.read();
+            if let Some(file) = state.files.get(&Self::holder(&state, file_id)?) {
+                return Ok(file.clone());
+            }
+        }
+        let mut state = self.state.write();
+        // the file might be garbage-collected before we take the write lock
+        let holder = Self::holder(&state, file_id)?;
+        if let Some(file) = state.files.get(&holder) {
+            return Ok(file.clone());
+        }
+        let file = Arc::new(ValueLogFile::open(
+            holder,
+            &LsmStorageInner::path_of_vlog_static(&self.path, holder),
+            self.enable_mmap,
+        )?);
+        state.files.insert(holder, file.clone());
+        Ok(file)
+    }
+
+    /// Read the value a pointer refers to.
+    pub fn read(&self, pointer: ValuePointer) -> Result<Bytes> {
+        self.holder_file(pointer.file_id)?.read(pointer)
+    }
+
+    /// Sum up the bytes of the values no longer referenced by any SST by the file they were first
+    /// written to.
+    pub(crate) fn discarded_bytes(pointers: Vec<ValuePointer>) -> Vec<(usize, u64)> {
+        let mut discarded = BTreeMap::<usize, u64>::new();
+        for pointer in pointers {
+            *discarded.entry(pointer.file_id).or_default() += pointer.len as u64;
+        }
+        discarded.into_iter().collect()
+    }
+
+    /// Record the bytes discarded from the files, as returned by `discarded_bytes`.
+    pub(crate) fn add_discarded(&self, discarded: &[(usize, u64)]) {
+        let mut state = self.state.write();
+        for (file_id, bytes) in discarded {
+            if let Ok(holder) = Self::holder(&state, *file_id) {
+                *state.discarded.entry(holder).or_default() += bytes;
+            }
+        }
+    }
+
+    /// Pick the file with the highest ratio of discarded bytes, if the ratio reaches `discard_ratio`.
+    pub(crate) fn pick_gc_candidate(&self, discard_ratio: f64) -> Result<Option<usize>> {
+        let discarded = self.state.read().discarded.clone();
+        let mut candidate = None;
+        let mut max_ratio = discard_ratio;
+        for (file_id, discarded_bytes) in discarded {
+            let size = self.holder_file(file_id)?.file.size();
+            let ratio = discarded_bytes as f64 / size as f64;
+            if ratio >= max_ratio {
+                max_ratio = ratio;
+                candidate = Some(file_id);
+            }
+        }
+        Ok(candidate)
+    }
+
+    /// Redirect the records of a garbage-collected file to the output file.
+    pub(crate) fn apply_gc(&self, input: usize, output: Option<usize>) {
+        let mut state = self.state.write();
+        for holder in state.redirects.values_mut() {
+            if *holder == Some(input) {
+                *holder = output;
+            }
+        }
+        state.redirects.insert(input, output);
+        state.files.remove(&input);
+        state.discarded.remove(&input);
+    }
+}
+
+impl LsmStorageInner {
+    /// Record the values no longer referenced by any SST in the manifest and the value log. Called
+    /// with the state lock held after the compaction dropping them is recorded, so that the
+    /// discarded bytes and the GCs are replayed in the same order on open.
+    pub(crate) fn record_discarded_values(
+        &self,
+        state_lock: &MutexGuard<()>,
+        pointers: Vec<ValuePointer>,
+    ) -> Result<()> {
+        let discarded = ValueLog::discarded_bytes(pointers);
+        if discarded.is_empty() {
+            return Ok(());
+        }
+        self.manifest().add_record(
+            state_lock,
+            ManifestRecord::ValueLogDiscard(discarded.clone()),
+        )?;
+        self.value_log.add_discarded(&discarded);
+        Ok(())
+    }
+
+    /// Garbage-collect a value log file. The records still referenced by a version visible to some
+    /// reader are relocated into a new file, and then the file is removed.
+    pub(crate) fn gc_value_log_file(&self, file_id: usize) -> Result<()> {
+        let records = self.value_log.holder_file(file_id)?.records()?;
+        let watermark = self.mvcc().watermark();
+        let output_id = self.next_sst_id();
+        let mut builder = ValueLogBuilder::new(output_id, self.path_of_vlog(output_id));
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            builder = builder.with_rate_limiter(rate_limiter.clone(), IoPriority::Low);
+        }
+
+        // the pointers referenced by the versions which must be kept, collected in one scan over
+        // the keys of the records
+        let keys = records
+            .iter()
+            .map(|(record, _)| record.key.key_ref())
+            .collect::<BTreeSet<_>>();
+        let mut live_pointers = HashSet::new();
+        if let (Some(first_key), Some(last_key)) = (keys.first(), keys.last()) {
+            let mut iter = self.scan_raw(Bound::Included(first_key), Bound::Included(last_key))?;
+            for key in &keys {
+                while iter.is_valid() && iter.key().key_ref() < *key {
+                    iter.next()?;
+                }
+                while iter.is_valid() && iter.key().key_ref() == *key {
+                    if iter.is_value_pointer() {
+                        live_pointers.insert(ValuePointer::decode(iter.value())?);
+                    }
+                    if iter.key().ts() <= watermark {
+                        // versions below the latest one under the watermark are invisible
+                        break;
+                    }
+                    iter.next()?;
+                }
+            }
+        }
+        let mut relocated = 0;
+        for (record, _) in &records {
+            if live_pointers.contains(&record.pointer) {
+                builder.add_relocated(record);
+                relocated += 1;
+            }
+        }
+
+        let output = if builder.is_empty() {
+            None
+        } else {
+            builder.finish()?;
+            self.sync_dir()?;
+            Some(output_id)
+        };
+        {
+            let state_lock = self.state_lock.lock();
+            self.manifest()
+                .add_record(&state_lock, ManifestRecord::ValueLogGc(file_id, output))?;
+            self.value_log.apply_gc(file_id, output);
+        }
+        std::fs::remove_file(self.path_of_vlog(file_id))?;
+        self.sync_dir()?;
+        println!(
+            "value log gc: {}.vlog -> {:?}, {}/{} records relocated",
+            file_id,
+            output,
+            relocated,
+            records.len()
+        );
+        Ok(())
+    }
+
+    /// Garbage-collect the value log file with the most garbage if it reaches the discard ratio.
+    fn trigger_value_log_gc(&self) -> Result<()> {
+        let Some(options) = self.options.value_log.as_ref() else {
+            return Ok(());
+        };
+        if let Some(file_id) = self.value_log.pick_gc_candidate(options.gc_discard_ratio)? {
+            self.gc_value_log_file(file_id)?;
+        }
+        Ok(())
+    }
+
+    /// Garbage-collect all value log files with discarded values.
+    pub fn force_value_log_gc(&self) -> Result<()> {
+        while let Some(file_id) = self.value_log.pick_gc_candidate(f64::MIN_POSITIVE)? {
+            self.gc_value_log_file(file_id)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spawn_value_log_gc_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+        if self.options.value_log.is_none() {
+            return Ok(None);
+        }
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(std::time::Duration::from_millis(50));
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if !this.background_work.is_paused() {
+                        if let Err(e) = this.trigger_value_log_gc() {
+                            eprintln!("value log gc failed: {}", e);
+                        }
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Ok(Some(handle))
+    }
+}
@@ -6,10 +6,9 @@ use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut, Bytes};
-use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
-use crate::key::{KeyBytes, KeySlice};
+use crate::key::KeySlice;
 
 pub struct Wal {
     file: Arc<Mutex<BufWriter<File>>>,
@@ -29,7 +28,11 @@ impl Wal {
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Open a WAL and replay the key-value pairs in it with `put`.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        mut put: impl FnMut(KeySlice, &[u8]) -> Result<()>,
+    ) -> Result<Self> {
         let path = path.as_ref();
         let mut file = OpenOptions::new()
             .read(true)
@@ -57,7 +60,7 @@ impl Wal {
             if hasher.finalize() != checksum {
                 bail!("checksum mismatch");
             }
-            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            put(KeySlice::from_slice(&key, ts), &value)?;
         }
         Ok(Self {
             file: Arc::new(Mutex::new(BufWriter::new(file))),
@@ -0,0 +1,168 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::block_cache::{BlockCache, RESERVATION_CHUNK_SIZE};
+
+/// The memtable memory of one storage instance.
+#[derive(Default)]
+struct WriteBufferSlot {
+    usage: AtomicUsize,
+    /// Set by the manager when the instance should flush its memtables.
+    flush_requested: AtomicBool,
+}
+
+/// A snapshot of the write buffer manager statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteBufferStats {
+    /// The memory used by the memtables of all instances in bytes.
+    pub memory_usage: usize,
+    pub buffer_size: usize,
+    /// The number of storage instances using the manager.
+    pub instances: usize,
+    /// The number of flushes requested because the budget is exceeded.
+    pub flush_requests: u64,
+}
+
+/// Enforces a memtable memory budget shared by storage instances. When the memtables of all
+/// instances take more than the budget, the instance using the most memory is asked to freeze and
+/// flush its memtables.
+///
+/// Pass the same manager in `LsmStorageOptions::write_buffer_manager` of every instance sharing
+/// the budget.
+pub struct WriteBufferManager {
+    buffer_size: usize,
+    usage: AtomicUsize,
+    slots: Mutex<Vec<Arc<WriteBufferSlot>>>,
+    flush_requests: AtomicU64,
+    /// Charges the memtable memory against the capacity of a block cache.
+    block_cache: Option<BlockCache>,
+}
+
+impl WriteBufferManager {
+    /// Create a manager limiting the memtables of all instances to `buffer_size` bytes.
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            usage: AtomicUsize::new(0),
+            slots: Mutex::new(Vec::new()),
+            flush_requests: AtomicU64::new(0),
+            block_cache: None,
+        }
+    }
+
+    /// Create a manager limiting the memtables of all instances to `buffer_size` bytes, which also
+    /// reserves the memory used by the memtables in `block_cache`, so that the memtables and the
+    /// cached blocks stay within the cache capacity together.
+    pub fn new_with_block_cache(buffer_size: usize, block_cache: &BlockCache) -> Self {
+        Self {
+            block_cache: Some(block_cache.share()),
+            ..Self::new(buffer_size)
+        }
+    }
+
+    pub(crate) fn register(self: &Arc<Self>) -> WriteBufferHandle {
+        let slot = Arc::new(WriteBufferSlot::default());
+        self.slots.lock().push(slot.clone());
+        WriteBufferHandle {
+            manager: self.clone(),
+            slot,
+        }
+    }
+
+    fn should_flush(&self) -> bool {
+        self.usage.load(Ordering::SeqCst) > self.buffer_size
+    }
+
+    /// Ask the instance using the most memory to flush, unless it has not finished the previous
+    /// flush yet.
+    fn request_flush(&self) {
+        let slots = self.slots.lock();
+        let Some(largest) = slots
+            .iter()
+            .max_by_key(|slot| slot.usage.load(Ordering::SeqCst))
+        else {
+            return;
+        };
+        if largest.usage.load(Ordering::SeqCst) > 0
+            && !largest.flush_requested.swap(true, Ordering::SeqCst)
+        {
+            self.flush_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn update_usage(&self, slot: &WriteBufferSlot, usage: usize) {
+        let old_usage = slot.usage.swap(usage, Ordering::SeqCst);
+        let (old_total, new_total) = if usage >= old_usage {
+            let old_total = self.usage.fetch_add(usage - old_usage, Ordering::SeqCst);
+            (old_total, old_total + (usage - old_usage))
+        } else {
+            let old_total = self.usage.fetch_sub(old_usage - usage, Ordering::SeqCst);
+            (old_total, old_total - (old_usage - usage))
+        };
+        if let Some(block_cache) = &self.block_cache {
+            // the reservation only changes when the usage moves to another chunk
+            let chunk = |usage: usize| (usage as u64).div_ceil(RESERVATION_CHUNK_SIZE);
+            if chunk(old_total) != chunk(new_total) {
+                block_cache.reserve(self.usage.load(Ordering::SeqCst) as u64);
+            }
+        }
+        if self.should_flush() {
+            self.request_flush();
+        }
+    }
+
+    /// The memory used by the memtables of all instances in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.usage.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> WriteBufferStats {
+        WriteBufferStats {
+            memory_usage: self.memory_usage(),
+            buffer_size: self.buffer_size,
+            instances: self.slots.lock().len(),
+            flush_requests: self.flush_requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Debug for WriteBufferManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriteBufferManager")
+            .field("buffer_size", &self.buffer_size)
+            .field("memory_usage", &self.memory_usage())
+            .finish()
+    }
+}
+
+/// The registration of a storage instance with a [`WriteBufferManager`], which is removed when
+/// dropped.
+pub(crate) struct WriteBufferHandle {
+    manager: Arc<WriteBufferManager>,
+    slot: Arc<WriteBufferSlot>,
+}
+
+impl WriteBufferHandle {
+    /// Report the memory used by the memtables of the instance.
+    pub(crate) fn update_usage(&self, usage: usize) {
+        self.manager.update_usage(&self.slot, usage);
+    }
+
+    /// Check if the manager asked the instance to flush, and clear the request.
+    pub(crate) fn take_flush_request(&self) -> bool {
+        self.slot.flush_requested.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for WriteBufferHandle {
+    fn drop(&mut self) {
+        self.update_usage(0);
+        self.manager
+            .slots
+            .lock()
+            .retain(|slot| !Arc::ptr_eq(slot, &self.slot));
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use parking_lot::{Condvar, Mutex};
+
+/// Thresholds of slowing down and stopping writes when flush or compaction falls behind.
+#[derive(Debug, Clone)]
+pub struct WriteStallOptions {
+    /// Slow down writes when there are this many immutable memtables.
+    pub slowdown_imm_memtables: usize,
+    /// Stop writes when there are this many immutable memtables.
+    pub stop_imm_memtables: usize,
+    /// Slow down writes when there are this many L0 SSTs.
+    pub slowdown_l0_files: usize,
+    /// Stop writes when there are this many L0 SSTs.
+    pub stop_l0_files: usize,
+    /// Slow down writes when compaction needs to rewrite this many bytes to catch up.
+    pub slowdown_pending_compaction_bytes: u64,
+    /// Stop writes when compaction needs to rewrite this many bytes to catch up.
+    pub stop_pending_compaction_bytes: u64,
+    /// The time each write batch is delayed by while writes are slowed down.
+    pub slowdown_delay: Duration,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> Self {
+        Self {
+            slowdown_imm_memtables: 8,
+            stop_imm_memtables: 16,
+            slowdown_l0_files: 20,
+            stop_l0_files: 36,
+            slowdown_pending_compaction_bytes: 16 << 30, // 16GB
+            stop_pending_compaction_bytes: 64 << 30,     // 64GB
+            slowdown_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    #[default]
+    Normal,
+    /// Writes are slowed down.
+    Delayed,
+    /// Writes wait until background work catches up.
+    Stopped,
+}
+
+/// The reason of a write stall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCause {
+    ImmMemtables,
+    L0Files,
+    PendingCompactionBytes,
+}
+
+/// A snapshot of the write stall state and statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    pub condition: WriteStallCondition,
+    /// The cause of the current stall, `None` if writes are not stalled.
+    pub cause: Option<WriteStallCause>,
+    /// The number of write batches delayed.
+    pub delayed_writes: u64,
+    /// The number of write batches stopped.
+    pub stopped_writes: u64,
+    /// The total time write batches were delayed.
+    pub delayed_duration: Duration,
+    /// The total time write batches were stopped.
+    pub stopped_duration: Duration,
+}
+
+/// Slows down and stops writes according to the state of the LSM tree, which is updated by
+/// [`WriteController::update`] whenever the state changes.
+pub(crate) struct WriteController {
+    options: Option<WriteStallOptions>,
+    stats: Mutex<WriteStallStats>,
+    /// Notified when writes are no longer stopped, or the storage is closed.
+    resumed: Condvar,
+    /// Set when the storage is closed, after which no flush or compaction ends a stop.
+    closed: AtomicBool,
+}
+
+impl WriteController {
+    pub(crate) fn new(options: Option<WriteStallOptions>) -> Self {
+        Self {
+            options,
+            stats: Mutex::new(WriteStallStats::default()),
+            resumed: Condvar::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Recompute the stall condition. The L0 and compaction thresholds only apply if `compaction`
+    /// is enabled, as nothing else brings them down.
+    pub(crate) fn update(
+        &self,
+        imm_memtables: usize,
+        l0_files: usize,
+        pending_compaction_bytes: u64,
+        compaction: bool,
+    ) {
+        let Some(options) = &self.options else {
+            return;
+        };
+        let compaction_stall = |l0_limit: usize, bytes_limit: u64| {
+            if !compaction {
+                None
+            } else if l0_files >= l0_limit {
+                Some(WriteStallCause::L0Files)
+            } else if pending_compaction_bytes >= bytes_limit {
+                Some(WriteStallCause::PendingCompactionBytes)
+            } else {
+                None
+            }
+        };
+        let stop = (imm_memtables >= options.stop_imm_memtables)
+            .then_some(WriteStallCause::ImmMemtables)
+            .or_else(|| {
+                compaction_stall(options.stop_l0_files, options.stop_pending_compaction_bytes)
+            });
+        let slowdown = (imm_memtables >= options.slowdown_imm_memtables)
+            .then_some(WriteStallCause::ImmMemtables)
+            .or_else(|| {
+                compaction_stall(
+                    options.slowdown_l0_files,
+                    options.slowdown_pending_compaction_bytes,
+                )
+            });
+        let (condition, cause) = match (stop, slowdown) {
+            (Some(cause), _) => (WriteStallCondition::Stopped, Some(cause)),
+            (None, Some(cause)) => (WriteStallCondition::Delayed, Some(cause)),
+            (None, None) => (WriteStallCondition::Normal, None),
+        };
+        let mut stats = self.stats.lock();
+        stats.condition = condition;
+        stats.cause = cause;
+        if condition != WriteStallCondition::Stopped {
+            self.resumed.notify_all();
+        }
+    }
+
+    /// Check if writes are stalled by `cause`.
+    pub(crate) fn stalled_by(&self, cause: WriteStallCause) -> bool {
+        self.stats.lock().cause == Some(cause)
+    }
+
+    /// Called before each write batch. Blocks while writes are stopped, and sleeps if writes are
+    /// slowed down. Returns an error if the storage is closed while writes are stopped.
+    pub(crate) fn wait_for_write(&self) -> Result<()> {
+        let Some(options) = &self.options else {
+            return Ok(());
+        };
+        let mut stats = self.stats.lock();
+        if stats.condition == WriteStallCondition::Stopped {
+            let start = Instant::now();
+            while stats.condition == WriteStallCondition::Stopped {
+                if self.closed.load(Ordering::Relaxed) {
+                    bail!("storage closed while writes are stopped");
+                }
+                self.resumed.wait(&mut stats);
+            }
+            stats.stopped_writes += 1;
+            stats.stopped_duration += start.elapsed();
+        }
+        if stats.condition == WriteStallCondition::Delayed {
+            drop(stats);
+            let start = Instant::now();
+            std::thread::sleep(options.slowdown_delay);
+            let mut stats = self.stats.lock();
+            stats.delayed_writes += 1;
+            stats.delayed_duration += start.elapsed();
+        }
+        Ok(())
+    }
+
+    /// Wake up the writes waiting for a stop to end, as the background work ending it is
+    /// shutting down.
+    pub(crate) fn close(&self) {
+        // taken so that a write about to wait sees the flag
+        let _stats = self.stats.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.resumed.notify_all();
+    }
+
+    pub(crate) fn stats(&self) -> WriteStallStats {
+        *self.stats.lock()
+    }
+}